@@ -0,0 +1,54 @@
+//! Writes the golden frames from `vectors/` as the seed corpora of the fuzz targets:
+//!
+//! <pre>cargo run --example seed_corpus</pre>
+//!
+//! `ProtoError` frames are also seeded into `global_rep_decode` wrapped into `GlobalRep::Error`.
+
+use std::{
+    fs,
+    path::Path,
+};
+
+use serde_json::Value as Json;
+
+const GLOBAL_REP_ERROR_TAG: u8 = 11;
+
+fn hex(s: &str) -> Vec<u8> {
+    (0 .. s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i .. i + 2], 16).unwrap()).collect()
+}
+
+fn seed(root: &Path, target: &str, name: &str, frame: &[u8]) {
+    let dir = root.join("corpus").join(target);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(name), frame).unwrap();
+}
+
+fn main() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut seeded = 0;
+    for entry in fs::read_dir(root.join("../vectors")).unwrap() {
+        let path = entry.unwrap().path();
+        let corpus: Json = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let version = corpus["proto_version"].as_u64().unwrap();
+        for vector in corpus["vectors"].as_array().unwrap() {
+            let name = format!("v{}-{}", version, vector["name"].as_str().unwrap());
+            let frame = hex(vector["frame"].as_str().unwrap());
+            match vector["message"].as_str().unwrap() {
+                "GlobalReq" =>
+                    seed(root, "global_req_decode", &name, &frame),
+                "GlobalRep" =>
+                    seed(root, "global_rep_decode", &name, &frame),
+                "ProtoError" => {
+                    seed(root, "proto_error_decode", &name, &frame);
+                    let mut wrapped = vec![GLOBAL_REP_ERROR_TAG];
+                    wrapped.extend_from_slice(&frame);
+                    seed(root, "global_rep_decode", &format!("{}-wrapped", name), &wrapped);
+                },
+                other =>
+                    panic!("{}: unknown message {:?}", name, other),
+            }
+            seeded += 1;
+        }
+    }
+    println!("seeded {} golden frames", seeded);
+}
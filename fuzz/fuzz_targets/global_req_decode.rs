@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use spiderq_proto::GlobalReq;
+use spiderq_proto_fuzz::check_decoder;
+
+fuzz_target!(|data: &[u8]| {
+    check_decoder!(GlobalReq, data);
+});
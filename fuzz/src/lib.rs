@@ -0,0 +1,14 @@
+/// Decodes `data` and, if it succeeds, checks that encoding the value gives
+/// back exactly the consumed prefix of `data`.
+#[macro_export]
+macro_rules! check_decoder {
+    ($ty:ty, $data:expr) => ({
+        let data: &[u8] = $data;
+        if let Ok((value, rest)) = <$ty>::decode(data) {
+            let consumed = &data[.. data.len() - rest.len()];
+            let mut area = vec![0; value.encode_len()];
+            assert!(value.encode(&mut area).is_empty(), "encode_len is larger than the bytes written for {:?}", value);
+            assert_eq!(&area[..], consumed, "{:?} is not encoded back to the decoded frame", value);
+        }
+    })
+}
@@ -0,0 +1,439 @@
+//! `#[derive(SpiderqCodec)]` for `spiderq_proto` wire types.
+//!
+//! For an enum with data carrying variants the derive generates inherent
+//! `decode`, `encode_len` and `encode` methods. Every variant is written as a
+//! `u8` tag given with `#[tag(n)]` followed by its fields in declaration order.
+//! Decoding errors are reported against `MessageKind::<Enum>` for the tag and
+//! `MessageKind::<Enum><Variant>` for variant fields (override both with
+//! `#[message(Kind)]`).
+//!
+//! Fields are encoded with `spiderq_proto::codec::Wire` and are described by
+//! `#[field(Kind, len = LenKind, count = CountKind, wire = Type)]`, where
+//! every part is optional for named fields: the default kind is the field name
+//! in camel case, `len` names the length prefix of byte strings, `count` the
+//! count prefix of sequences and `wire` the integer type used on the wire for
+//! `usize` fields.
+//!
+//! For an enum with unit variants only the derive implements `Wire` itself, so
+//! it may be used as a field: a single `u8` tag given with `#[tag(n)]`.
+//!
+//! Both kinds also describe themselves for `spiderq_proto::schema`: message
+//! enums get a `SCHEMA` const and tag enums a `Wire::WIRE_TYPE`.
+
+#![allow(clippy::redundant_field_names)]
+
+extern crate proc_macro;
+
+use std::collections::HashMap;
+
+use proc_macro2::{
+    Literal,
+    Span,
+    TokenStream,
+};
+use quote::{
+    format_ident,
+    quote,
+};
+use syn::{
+    parse_macro_input,
+    Attribute,
+    Data,
+    DeriveInput,
+    Error,
+    Fields,
+    Ident,
+    LitInt,
+    Type,
+};
+
+#[proc_macro_derive(SpiderqCodec, attributes(tag, message, field))]
+pub fn derive_spiderq_codec(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct FieldDef {
+    ty: Type,
+    name: Option<Ident>,
+    kind: Ident,
+    len: Option<Ident>,
+    count: Option<Ident>,
+    wire: Option<Type>,
+}
+
+struct VariantDef {
+    ident: Ident,
+    tag: u8,
+    message: Ident,
+    named: bool,
+    fields: Vec<FieldDef>,
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream, Error> {
+    let data = match input.data {
+        Data::Enum(ref data) => data,
+        _ => return Err(Error::new_spanned(&input.ident, "SpiderqCodec can only be derived for enums")),
+    };
+    let enum_message = message_attr(&input.attrs)?.unwrap_or_else(|| input.ident.clone());
+
+    let mut tags = HashMap::new();
+    let mut variants = Vec::new();
+    for variant in data.variants.iter() {
+        let tag = tag_attr(&variant.attrs)?
+            .ok_or_else(|| Error::new_spanned(&variant.ident, "missing #[tag(n)] attribute"))?;
+        if let Some(other) = tags.insert(tag, variant.ident.clone()) {
+            return Err(Error::new_spanned(&variant.ident, format!("tag {} is already used for `{}`", tag, other)));
+        }
+        let message = message_attr(&variant.attrs)?
+            .unwrap_or_else(|| format_ident!("{}{}", input.ident, variant.ident));
+        let mut fields = Vec::new();
+        for field in variant.fields.iter() {
+            fields.push(field_def(field)?);
+        }
+        variants.push(VariantDef {
+            ident: variant.ident.clone(),
+            tag: tag,
+            message: message,
+            named: matches!(variant.fields, Fields::Named(..)),
+            fields: fields,
+        });
+    }
+
+    if variants.iter().all(|variant| variant.fields.is_empty()) {
+        Ok(expand_tag_enum(&input.ident, &variants))
+    } else {
+        Ok(expand_message_enum(&input.ident, &enum_message, &variants))
+    }
+}
+
+fn tag_attr(attrs: &[Attribute]) -> Result<Option<u8>, Error> {
+    match attrs.iter().find(|attr| attr.path().is_ident("tag")) {
+        Some(attr) => Ok(Some(attr.parse_args::<LitInt>()?.base10_parse()?)),
+        None => Ok(None),
+    }
+}
+
+fn message_attr(attrs: &[Attribute]) -> Result<Option<Ident>, Error> {
+    match attrs.iter().find(|attr| attr.path().is_ident("message")) {
+        Some(attr) => Ok(Some(attr.parse_args()?)),
+        None => Ok(None),
+    }
+}
+
+fn field_def(field: &syn::Field) -> Result<FieldDef, Error> {
+    let mut kind = field.ident.as_ref().map(|ident| Ident::new(&camel_case(&ident.to_string()), ident.span()));
+    let (mut len, mut count, mut wire) = (None, None, None);
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("field")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("len") {
+                len = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("count") {
+                count = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("wire") {
+                wire = Some(meta.value()?.parse()?);
+            } else if meta.input.peek(syn::Token![=]) || meta.input.peek(syn::token::Paren) {
+                return Err(meta.error("unknown #[field] key, expected `len`, `count` or `wire`"));
+            } else if let Some(ident) = meta.path.get_ident() {
+                kind = Some(ident.clone());
+            } else {
+                return Err(meta.error("expected field kind, `len`, `count` or `wire`"));
+            }
+            Ok(())
+        })?;
+    }
+    let kind = kind.ok_or_else(|| Error::new_spanned(&field.ty, "unnamed fields require #[field(Kind)] attribute"))?;
+    Ok(FieldDef {
+        ty: field.ty.clone(),
+        name: field.ident.clone(),
+        kind: kind,
+        len: len,
+        count: count,
+        wire: wire,
+    })
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn tag_literal(tag: u8) -> Literal {
+    Literal::u8_suffixed(tag)
+}
+
+fn field_spec(message: &Ident, field: &FieldDef) -> TokenStream {
+    let kind = &field.kind;
+    let len = field.len.as_ref().unwrap_or(kind);
+    let count = field.count.as_ref().unwrap_or(kind);
+    quote! {
+        ::spiderq_proto::codec::FieldSpec {
+            message: ::spiderq_proto::MessageKind::#message,
+            field: ::spiderq_proto::FieldKind::#kind,
+            len: ::spiderq_proto::FieldKind::#len,
+            count: ::spiderq_proto::FieldKind::#count,
+        }
+    }
+}
+
+fn binding(index: usize) -> Ident {
+    Ident::new(&format!("field_{}", index), Span::call_site())
+}
+
+fn pattern(enum_ident: &Ident, variant: &VariantDef) -> TokenStream {
+    let ident = &variant.ident;
+    let bindings = (0 .. variant.fields.len()).map(binding);
+    if variant.fields.is_empty() {
+        quote!(#enum_ident::#ident)
+    } else if variant.named {
+        let names = variant.fields.iter().map(|field| &field.name);
+        quote!(#enum_ident::#ident { #(#names: #bindings,)* })
+    } else {
+        quote!(#enum_ident::#ident(#(#bindings,)*))
+    }
+}
+
+fn expand_message_enum(enum_ident: &Ident, enum_message: &Ident, variants: &[VariantDef]) -> TokenStream {
+    let decode_arms = variants.iter().map(|variant| {
+        let tag = tag_literal(variant.tag);
+        let reads = variant.fields.iter().enumerate().map(|(index, field)| {
+            let var = binding(index);
+            let spec = field_spec(&variant.message, field);
+            let ty = &field.ty;
+            match field.wire {
+                Some(ref wire) => quote! {
+                    let (#var, buf) = <#wire as ::spiderq_proto::codec::Wire>::decode(buf, #spec)?;
+                    let #var = #var as #ty;
+                },
+                None => quote! {
+                    let (#var, buf) = <#ty as ::spiderq_proto::codec::Wire>::decode(buf, #spec)?;
+                },
+            }
+        });
+        let value = pattern(enum_ident, variant);
+        quote! {
+            #tag => {
+                #(#reads)*
+                Ok((#value, buf))
+            },
+        }
+    });
+
+    let len_arms = variants.iter().map(|variant| {
+        let tag = tag_literal(variant.tag);
+        let lens = variant.fields.iter().enumerate().map(|(index, field)| {
+            let var = binding(index);
+            match field.wire {
+                Some(ref wire) => quote!(<#wire as ::spiderq_proto::codec::Wire>::encode_len(&(*#var as #wire))),
+                None => quote!(::spiderq_proto::codec::Wire::encode_len(#var)),
+            }
+        });
+        let pat = pattern(enum_ident, variant);
+        quote! {
+            #pat => <u8 as ::spiderq_proto::codec::Wire>::encode_len(&#tag) #(+ #lens)*,
+        }
+    });
+
+    let encode_arms = variants.iter().map(|variant| {
+        let tag = tag_literal(variant.tag);
+        let writes = variant.fields.iter().enumerate().map(|(index, field)| {
+            let var = binding(index);
+            match field.wire {
+                Some(ref wire) => quote! {
+                    let area = <#wire as ::spiderq_proto::codec::Wire>::encode(&(*#var as #wire), area);
+                },
+                None => quote! {
+                    let area = ::spiderq_proto::codec::Wire::encode(#var, area);
+                },
+            }
+        });
+        let pat = pattern(enum_ident, variant);
+        quote! {
+            #pat => {
+                let area = <u8 as ::spiderq_proto::codec::Wire>::encode(&#tag, area);
+                #(#writes)*
+                area
+            },
+        }
+    });
+
+    let mut sorted: Vec<_> = variants.iter().collect();
+    sorted.sort_by_key(|variant| variant.tag);
+    let variant_schemas = sorted.iter().map(|variant| {
+        let name = variant.ident.to_string();
+        let tag = tag_literal(variant.tag);
+        let message = if variant.fields.is_empty() {
+            quote!(None)
+        } else {
+            let message = &variant.message;
+            quote!(Some(::spiderq_proto::MessageKind::#message))
+        };
+        let fields = variant.fields.iter().map(field_schema);
+        quote! {
+            ::spiderq_proto::schema::VariantSchema {
+                name: #name,
+                tag: #tag,
+                message: #message,
+                fields: &[#(#fields,)*],
+            },
+        }
+    });
+    let enum_name = enum_ident.to_string();
+
+    quote! {
+        impl #enum_ident {
+            pub const SCHEMA: ::spiderq_proto::schema::MessageSchema = ::spiderq_proto::schema::MessageSchema {
+                name: #enum_name,
+                message: ::spiderq_proto::MessageKind::#enum_message,
+                variants: &[#(#variant_schemas)*],
+            };
+
+            pub fn decode<'a>(data: &'a [u8]) -> ::std::result::Result<(#enum_ident, &'a [u8]), ::spiderq_proto::ProtoError> {
+                let spec = ::spiderq_proto::codec::FieldSpec::new(
+                    ::spiderq_proto::MessageKind::#enum_message,
+                    ::spiderq_proto::FieldKind::Tag,
+                );
+                let (tag, buf) = <u8 as ::spiderq_proto::codec::Wire>::decode(data, spec)?;
+                match tag {
+                    #(#decode_arms)*
+                    tag => Err(::spiderq_proto::ProtoError::InvalidTag { message: spec.message, field: spec.field, tag: tag, }),
+                }
+            }
+
+            pub fn encode_len(&self) -> usize {
+                match self {
+                    #(#len_arms)*
+                }
+            }
+
+            pub fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+                match self {
+                    #(#encode_arms)*
+                }
+            }
+        }
+    }
+}
+
+fn field_schema(field: &FieldDef) -> TokenStream {
+    let name = match field.name {
+        Some(ref name) => {
+            let name = name.to_string();
+            quote!(Some(#name))
+        },
+        None => quote!(None),
+    };
+    let ty = &field.ty;
+    let rust_type: String = quote!(#ty).to_string().chars().filter(|c| !c.is_whitespace()).collect();
+    let wire_ty = field.wire.as_ref().unwrap_or(ty);
+    let kind = &field.kind;
+    let len = field.len.as_ref().unwrap_or(kind);
+    let count = field.count.as_ref().unwrap_or(kind);
+    quote! {
+        ::spiderq_proto::schema::FieldSchema {
+            name: #name,
+            rust_type: #rust_type,
+            wire: <#wire_ty as ::spiderq_proto::codec::Wire>::WIRE_TYPE,
+            kind: ::spiderq_proto::FieldKind::#kind,
+            len: ::spiderq_proto::FieldKind::#len,
+            count: ::spiderq_proto::FieldKind::#count,
+        }
+    }
+}
+
+fn expand_tag_enum(enum_ident: &Ident, variants: &[VariantDef]) -> TokenStream {
+    let idents: Vec<_> = variants.iter().map(|variant| &variant.ident).collect();
+    let tags: Vec<_> = variants.iter().map(|variant| tag_literal(variant.tag)).collect();
+    let enum_name = enum_ident.to_string();
+    let names = idents.iter().map(|ident| ident.to_string());
+    quote! {
+        impl ::spiderq_proto::codec::Wire for #enum_ident {
+            const WIRE_TYPE: ::spiderq_proto::schema::WireType =
+                ::spiderq_proto::schema::WireType::Enum(&::spiderq_proto::schema::EnumSchema {
+                    name: #enum_name,
+                    variants: &[#(::spiderq_proto::schema::EnumVariantSchema { name: #names, tag: #tags, },)*],
+                });
+
+            fn encode_len(&self) -> usize {
+                ::std::mem::size_of::<u8>()
+            }
+
+            fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+                let tag = match self {
+                    #(#enum_ident::#idents => #tags,)*
+                };
+                <u8 as ::spiderq_proto::codec::Wire>::encode(&tag, area)
+            }
+
+            fn decode<'a>(data: &'a [u8], spec: ::spiderq_proto::codec::FieldSpec) ->
+                ::std::result::Result<(#enum_ident, &'a [u8]), ::spiderq_proto::ProtoError>
+            {
+                match <u8 as ::spiderq_proto::codec::Wire>::decode(data, spec)? {
+                    #((#tags, buf) => Ok((#enum_ident::#idents, buf)),)*
+                    (tag, _) => Err(::spiderq_proto::ProtoError::InvalidTag { message: spec.message, field: spec.field, tag: tag, }),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use quote::quote;
+    use super::expand;
+
+    #[test]
+    fn tag_enum_expansion() {
+        let input = syn::parse_quote! {
+            enum Mode {
+                #[tag(1)] Poll,
+                #[tag(7)] Block,
+            }
+        };
+        let expected = quote! {
+            impl ::spiderq_proto::codec::Wire for Mode {
+                const WIRE_TYPE: ::spiderq_proto::schema::WireType =
+                    ::spiderq_proto::schema::WireType::Enum(&::spiderq_proto::schema::EnumSchema {
+                        name: "Mode",
+                        variants: &[
+                            ::spiderq_proto::schema::EnumVariantSchema { name: "Poll", tag: 1u8, },
+                            ::spiderq_proto::schema::EnumVariantSchema { name: "Block", tag: 7u8, },
+                        ],
+                    });
+
+                fn encode_len(&self) -> usize {
+                    ::std::mem::size_of::<u8>()
+                }
+
+                fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+                    let tag = match self {
+                        Mode::Poll => 1u8,
+                        Mode::Block => 7u8,
+                    };
+                    <u8 as ::spiderq_proto::codec::Wire>::encode(&tag, area)
+                }
+
+                fn decode<'a>(data: &'a [u8], spec: ::spiderq_proto::codec::FieldSpec) ->
+                    ::std::result::Result<(Mode, &'a [u8]), ::spiderq_proto::ProtoError>
+                {
+                    match <u8 as ::spiderq_proto::codec::Wire>::decode(data, spec)? {
+                        (1u8, buf) => Ok((Mode::Poll, buf)),
+                        (7u8, buf) => Ok((Mode::Block, buf)),
+                        (tag, _) => Err(::spiderq_proto::ProtoError::InvalidTag { message: spec.message, field: spec.field, tag: tag, }),
+                    }
+                }
+            }
+        };
+        assert_eq!(expand(&input).unwrap().to_string(), expected.to_string());
+    }
+}
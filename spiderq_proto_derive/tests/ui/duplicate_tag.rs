@@ -0,0 +1,10 @@
+use spiderq_proto_derive::SpiderqCodec;
+
+#[derive(SpiderqCodec)]
+enum Mode {
+    #[tag(1)] Poll,
+    #[tag(2)] Block,
+    #[tag(1)] Wait,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use spiderq_proto_derive::SpiderqCodec;
+
+#[derive(SpiderqCodec)]
+enum Req {
+    #[tag(1)] Ping,
+    #[tag(2)] Lookup(#[field(len = KeyLen)] Vec<u8>),
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use spiderq_proto_derive::SpiderqCodec;
+
+#[derive(SpiderqCodec)]
+enum Req {
+    #[tag(1)] Ping,
+    #[tag(2)] Lookup {
+        #[field(size = KeyLen)] key: Vec<u8>,
+    },
+}
+
+fn main() {}
@@ -0,0 +1,338 @@
+use std::sync::Arc;
+
+use proptest::{
+    prelude::*,
+    collection::vec,
+    sample::select,
+    strategy::{BoxedStrategy, LazyJust},
+};
+
+use super::{
+    Key,
+    ServerErrorCode,
+    RepayStatus,
+    LendMode,
+    AddMode,
+    AuthMechanism,
+    GlobalReq,
+    GlobalRep,
+    ProtoError,
+    MessageKind,
+    FieldKind,
+};
+
+/// Upper bound for generated key and value lengths.
+pub const MAX_BYTES_LEN: usize = 64;
+
+/// Byte string of up to `MAX_BYTES_LEN` bytes.
+pub fn bytes() -> BoxedStrategy<Key> {
+    vec(any::<u8>(), 0 ..= MAX_BYTES_LEN).prop_map(Arc::from).boxed()
+}
+
+/// `usize` which fits a `uint32_t` wire field.
+pub fn wire_u32() -> BoxedStrategy<usize> {
+    any::<u32>().prop_map(|value| value as usize).boxed()
+}
+
+/// `usize` which fits a `uint64_t` wire field.
+pub fn wire_u64() -> BoxedStrategy<usize> {
+    any::<u64>().prop_map(|value| value as usize).boxed()
+}
+
+macro_rules! arbitrary_unit_enum {
+    ($ty:ident { $($variant:ident),* $(,)* }) => {
+        impl Arbitrary for $ty {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<$ty>;
+
+            fn arbitrary_with(_args: ()) -> BoxedStrategy<$ty> {
+                prop_oneof![$(LazyJust::new(|| $ty::$variant),)*].boxed()
+            }
+        }
+    }
+}
+
+arbitrary_unit_enum!(RepayStatus { Penalty, Reward, Front, Drop, });
+arbitrary_unit_enum!(LendMode { Block, Poll, });
+arbitrary_unit_enum!(AddMode { Head, Tail, });
+arbitrary_unit_enum!(AuthMechanism { Token, HmacSha256, });
+
+impl Arbitrary for MessageKind {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<MessageKind>;
+
+    fn arbitrary_with(_args: ()) -> BoxedStrategy<MessageKind> {
+        select(MessageKind::ALL).boxed()
+    }
+}
+
+impl Arbitrary for FieldKind {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<FieldKind>;
+
+    fn arbitrary_with(_args: ()) -> BoxedStrategy<FieldKind> {
+        select(FieldKind::ALL).boxed()
+    }
+}
+
+impl Arbitrary for ServerErrorCode {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<ServerErrorCode>;
+
+    fn arbitrary_with(_args: ()) -> BoxedStrategy<ServerErrorCode> {
+        prop_oneof![
+            (1 ..= 7u16).prop_map(ServerErrorCode::from_code),
+            any::<u16>().prop_map(ServerErrorCode::from_code),
+        ].boxed()
+    }
+}
+
+impl Arbitrary for ProtoError {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<ProtoError>;
+
+    fn arbitrary_with(_args: ()) -> BoxedStrategy<ProtoError> {
+        prop_oneof![
+            (any::<MessageKind>(), any::<FieldKind>(), wire_u32(), wire_u32())
+                .prop_map(|(message, field, required, given)| ProtoError::NotEnoughData {
+                    message: message,
+                    field: field,
+                    required: required,
+                    given: given,
+                }),
+            (any::<MessageKind>(), any::<FieldKind>(), any::<u8>())
+                .prop_map(|(message, field, tag)| ProtoError::InvalidTag { message: message, field: field, tag: tag, }),
+            (any::<MessageKind>(), any::<FieldKind>())
+                .prop_map(|(message, field)| ProtoError::InvalidUtf8 { message: message, field: field, }),
+            bytes().prop_map(ProtoError::DbQueueOutOfSync),
+        ].boxed()
+    }
+}
+
+impl Arbitrary for GlobalReq {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<GlobalReq>;
+
+    fn arbitrary_with(_args: ()) -> BoxedStrategy<GlobalReq> {
+        prop_oneof![
+            LazyJust::new(|| GlobalReq::Ping),
+            LazyJust::new(|| GlobalReq::Count),
+            (bytes(), bytes(), any::<AddMode>())
+                .prop_map(|(key, value, mode)| GlobalReq::Add { key: key, value: value, mode: mode, }),
+            (bytes(), bytes()).prop_map(|(key, value)| GlobalReq::Update(key, value)),
+            bytes().prop_map(GlobalReq::Lookup),
+            bytes().prop_map(GlobalReq::Remove),
+            (any::<u64>(), any::<LendMode>())
+                .prop_map(|(timeout, mode)| GlobalReq::Lend { timeout: timeout, mode: mode, }),
+            (any::<u64>(), bytes(), bytes(), any::<RepayStatus>())
+                .prop_map(|(lend_key, key, value, status)| GlobalReq::Repay {
+                    lend_key: lend_key,
+                    key: key,
+                    value: value,
+                    status: status,
+                }),
+            (any::<u64>(), bytes(), any::<u64>())
+                .prop_map(|(lend_key, key, timeout)| GlobalReq::Heartbeat { lend_key: lend_key, key: key, timeout: timeout, }),
+            LazyJust::new(|| GlobalReq::Stats),
+            LazyJust::new(|| GlobalReq::Flush),
+            LazyJust::new(|| GlobalReq::Terminate),
+            LazyJust::new(|| GlobalReq::Info),
+            (any::<AuthMechanism>(), bytes())
+                .prop_map(|(mechanism, payload)| GlobalReq::Auth { mechanism: mechanism, payload: payload, }),
+        ].boxed()
+    }
+}
+
+fn stats_got() -> BoxedStrategy<GlobalRep> {
+    vec(wire_u64(), 10)
+        .prop_map(|counters| GlobalRep::StatsGot {
+            ping: counters[0],
+            count: counters[1],
+            add: counters[2],
+            update: counters[3],
+            lookup: counters[4],
+            remove: counters[5],
+            lend: counters[6],
+            repay: counters[7],
+            heartbeat: counters[8],
+            stats: counters[9],
+        })
+        .boxed()
+}
+
+fn info_got() -> BoxedStrategy<GlobalRep> {
+    (any::<String>(), any::<u32>(), any::<u64>(), any::<String>(), wire_u32(), wire_u32(), vec(any::<String>(), 0 .. 8))
+        .prop_map(|(server_version, proto_version, uptime_ms, db_path, max_key_len, max_value_len, features)| GlobalRep::InfoGot {
+            server_version: server_version,
+            proto_version: proto_version,
+            uptime_ms: uptime_ms,
+            db_path: db_path,
+            max_key_len: max_key_len,
+            max_value_len: max_value_len,
+            features: features,
+        })
+        .boxed()
+}
+
+impl Arbitrary for GlobalRep {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<GlobalRep>;
+
+    fn arbitrary_with(_args: ()) -> BoxedStrategy<GlobalRep> {
+        prop_oneof![
+            LazyJust::new(|| GlobalRep::Pong),
+            wire_u32().prop_map(GlobalRep::Counted),
+            LazyJust::new(|| GlobalRep::Added),
+            LazyJust::new(|| GlobalRep::Kept),
+            LazyJust::new(|| GlobalRep::Updated),
+            LazyJust::new(|| GlobalRep::NotFound),
+            bytes().prop_map(GlobalRep::ValueFound),
+            LazyJust::new(|| GlobalRep::ValueNotFound),
+            LazyJust::new(|| GlobalRep::Removed),
+            LazyJust::new(|| GlobalRep::NotRemoved),
+            (any::<u64>(), bytes(), bytes())
+                .prop_map(|(lend_key, key, value)| GlobalRep::Lent { lend_key: lend_key, key: key, value: value, }),
+            LazyJust::new(|| GlobalRep::QueueEmpty),
+            LazyJust::new(|| GlobalRep::Repaid),
+            LazyJust::new(|| GlobalRep::Heartbeaten),
+            LazyJust::new(|| GlobalRep::Skipped),
+            stats_got(),
+            LazyJust::new(|| GlobalRep::Flushed),
+            LazyJust::new(|| GlobalRep::Terminated),
+            any::<ProtoError>().prop_map(GlobalRep::Error),
+            info_got(),
+            LazyJust::new(|| GlobalRep::AuthOk),
+            LazyJust::new(|| GlobalRep::AuthFailed),
+            bytes().prop_map(GlobalRep::AuthChallenge),
+            LazyJust::new(|| GlobalRep::Unauthenticated),
+            LazyJust::new(|| GlobalRep::Forbidden),
+            (any::<ServerErrorCode>(), any::<String>())
+                .prop_map(|(code, message)| GlobalRep::ServerError { code: code, message: message, }),
+        ].boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+    use byteorder::{ByteOrder, BigEndian};
+    use super::super::{
+        schema::{
+            WireType,
+            MessageSchema,
+            ProtoErrorEncoding,
+            proto_error_tags,
+        },
+        GlobalReq,
+        GlobalRep,
+        ProtoError,
+        MessageKind,
+        FieldKind,
+    };
+
+    /// Walks a complete frame following the schema and reports the error
+    /// decoding is expected to produce when the frame is cut at `end`.
+    struct Cut<'a> {
+        frame: &'a [u8],
+        pos: usize,
+        end: usize,
+    }
+
+    impl<'a> Cut<'a> {
+        fn take(&mut self, len: usize, message: MessageKind, field: FieldKind) -> Result<&'a [u8], ProtoError> {
+            if self.end - self.pos < len {
+                return Err(ProtoError::NotEnoughData { message: message, field: field, required: len, given: self.end - self.pos, });
+            }
+            let bytes = &self.frame[self.pos .. self.pos + len];
+            self.pos += len;
+            Ok(bytes)
+        }
+
+        fn wire(&mut self, wire: &WireType, message: MessageKind, field: FieldKind, len: FieldKind, count: FieldKind) -> Result<(), ProtoError> {
+            match wire {
+                &WireType::U8 | &WireType::Enum(..) => self.take(1, message, field).map(|_| ()),
+                &WireType::U16 => self.take(2, message, field).map(|_| ()),
+                &WireType::U32 => self.take(4, message, field).map(|_| ()),
+                &WireType::U64 => self.take(8, message, field).map(|_| ()),
+                &WireType::Bytes | &WireType::Utf8 => {
+                    let size = BigEndian::read_u32(self.take(4, message, len)?) as usize;
+                    self.take(size, message, field).map(|_| ())
+                },
+                &WireType::List(item) => {
+                    let items = BigEndian::read_u32(self.take(4, message, count)?);
+                    for _ in 0 .. items {
+                        self.wire(item, message, field, len, count)?;
+                    }
+                    Ok(())
+                },
+                &WireType::ProtoError => self.proto_error(),
+            }
+        }
+
+        fn proto_error(&mut self) -> Result<(), ProtoError> {
+            let tag = self.take(1, MessageKind::ProtoError, FieldKind::Tag)?[0];
+            let error_tag = proto_error_tags().into_iter().find(|error_tag| error_tag.tag == tag).unwrap();
+            if let ProtoErrorEncoding::Generic = error_tag.encoding {
+                self.take(1, MessageKind::ProtoError, FieldKind::Message)?;
+                self.take(1, MessageKind::ProtoError, FieldKind::Field)?;
+            }
+            for &(name, ref wire) in error_tag.payload() {
+                match name {
+                    "required" => self.wire(wire, MessageKind::ProtoError, FieldKind::Required, FieldKind::Required, FieldKind::Required)?,
+                    "given" => self.wire(wire, MessageKind::ProtoError, FieldKind::Given, FieldKind::Given, FieldKind::Given)?,
+                    "invalid_tag" => self.wire(wire, MessageKind::ProtoError, FieldKind::InvalidTag, FieldKind::InvalidTag, FieldKind::InvalidTag)?,
+                    "key" => self.wire(wire, MessageKind::ProtoErrorDbQueueOutOfSync, FieldKind::Key, FieldKind::KeyLen, FieldKind::Key)?,
+                    other => panic!("unexpected ProtoError payload field {:?}", other),
+                }
+            }
+            Ok(())
+        }
+
+        fn message(&mut self, schema: &MessageSchema) -> Result<(), ProtoError> {
+            let tag = self.take(1, schema.message, FieldKind::Tag)?[0];
+            let variant = schema.variants.iter().find(|variant| variant.tag == tag).unwrap();
+            for field in variant.fields {
+                self.wire(&field.wire, variant.message.unwrap(), field.kind, field.len, field.count)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn cut(frame: &[u8], end: usize) -> Cut<'_> {
+        Cut { frame: frame, pos: 0, end: end, }
+    }
+
+    macro_rules! check_message {
+        ($ty:ident, $value:expr, $walk:expr) => ({
+            let value = $value;
+            let mut area = vec![0; value.encode_len()];
+            prop_assert!(value.encode(&mut area).is_empty(), "encode_len is larger than the bytes written");
+            prop_assert_eq!($ty::decode(&area), Ok((value, &[][..])));
+            let mut whole = cut(&area, area.len());
+            prop_assert_eq!($walk(&mut whole), Ok(()));
+            prop_assert_eq!(whole.pos, area.len());
+            for end in 0 .. area.len() {
+                let expected = $walk(&mut cut(&area, end)).unwrap_err();
+                prop_assert!(matches!(expected, ProtoError::NotEnoughData { .. }), "expected error is {:?}", expected);
+                prop_assert_eq!($ty::decode(&area[.. end]).map(|_| ()), Err(expected), "frame cut at {}", end);
+            }
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn global_req_properties(req in any::<GlobalReq>()) {
+            check_message!(GlobalReq, req, |c: &mut Cut| c.message(&GlobalReq::SCHEMA));
+        }
+
+        #[test]
+        fn global_rep_properties(rep in any::<GlobalRep>()) {
+            check_message!(GlobalRep, rep, |c: &mut Cut| c.message(&GlobalRep::SCHEMA));
+        }
+
+        #[test]
+        fn proto_error_properties(error in any::<ProtoError>()) {
+            check_message!(ProtoError, error, |c: &mut Cut| c.proto_error());
+        }
+    }
+}
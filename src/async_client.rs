@@ -0,0 +1,443 @@
+//! Pipelining tokio client.
+//!
+//! Requests are written by a background task and replies are matched to them
+//! in order by another one, so many requests may be in flight over a single
+//! connection and dropping a request future never leaves a half written
+//! request or an unread reply in the stream: the reply is read and discarded.
+//!
+//! The server answers requests of a connection in order, so a parked
+//! `Lend { mode: Block }` would delay every reply behind it. When the client
+//! knows how to connect (`Client::connect` or `Client::connect_unix`) blocking
+//! lends use dedicated connections instead.
+
+use std::{
+    io,
+    mem,
+    collections::VecDeque,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    net::SocketAddr,
+};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use tokio::{
+    io::{
+        AsyncRead,
+        AsyncReadExt,
+        AsyncWrite,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpStream,
+        ToSocketAddrs,
+    },
+    sync::{
+        mpsc,
+        oneshot,
+    },
+    task::JoinHandle,
+};
+
+use super::{
+    Key,
+    Value,
+    LendMode,
+    AddMode,
+    RepayStatus,
+    GlobalReq,
+    GlobalRep,
+    codec,
+    client::{
+        self,
+        ClientError,
+        LentTask,
+        Stats,
+        DEFAULT_MAX_FRAME_LEN,
+    },
+};
+
+pub async fn write_frame<W>(stream: &mut W, frame: &[u8]) -> io::Result<()> where W: AsyncWrite + Unpin {
+    stream.write_all(&client::length_prefixed(frame)).await?;
+    stream.flush().await
+}
+
+pub async fn read_frame<R>(stream: &mut R, max_frame_len: usize) -> io::Result<Vec<u8>> where R: AsyncRead + Unpin {
+    let mut prefix = [0; 4];
+    stream.read_exact(&mut prefix).await?;
+    let mut frame = vec![0; client::frame_len(prefix, max_frame_len)?];
+    stream.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+type Reply = oneshot::Sender<Result<GlobalRep, ClientError>>;
+
+enum Pending {
+    Open(VecDeque<Reply>),
+    /// Connection failed with the error kind and message.
+    Failed(io::ErrorKind, String),
+}
+
+fn fail(pending: &Mutex<Pending>, err: &io::Error) {
+    let state = mem::replace(&mut *pending.lock().unwrap(), Pending::Failed(err.kind(), err.to_string()));
+    if let Pending::Open(replies) = state {
+        for reply in replies {
+            let _ = reply.send(Err(ClientError::Io(io::Error::new(err.kind(), err.to_string()))));
+        }
+    }
+}
+
+/// Single multiplexed connection driven by a writer and a reader task.
+struct Connection {
+    requests: mpsc::UnboundedSender<(Vec<u8>, Reply)>,
+    pending: Arc<Mutex<Pending>>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl Connection {
+    fn new<S>(stream: S, max_frame_len: usize) -> Connection where S: AsyncRead + AsyncWrite + Send + 'static {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (requests_tx, mut requests_rx) = mpsc::unbounded_channel::<(Vec<u8>, Reply)>();
+        let pending = Arc::new(Mutex::new(Pending::Open(VecDeque::new())));
+
+        let writer_pending = pending.clone();
+        let writer_task = tokio::spawn(async move {
+            while let Some((frame, reply)) = requests_rx.recv().await {
+                match &mut *writer_pending.lock().unwrap() {
+                    &mut Pending::Open(ref mut replies) =>
+                        replies.push_back(reply),
+                    &mut Pending::Failed(kind, ref message) => {
+                        let _ = reply.send(Err(ClientError::Io(io::Error::new(kind, message.clone()))));
+                        continue;
+                    },
+                }
+                if let Err(err) = write_frame(&mut writer, &frame).await {
+                    fail(&writer_pending, &err);
+                    return;
+                }
+            }
+        });
+
+        let reader_pending = pending.clone();
+        let reader_task = tokio::spawn(async move {
+            loop {
+                let frame = match read_frame(&mut reader, max_frame_len).await {
+                    Ok(frame) => frame,
+                    Err(err) => return fail(&reader_pending, &err),
+                };
+                let reply = match &mut *reader_pending.lock().unwrap() {
+                    &mut Pending::Open(ref mut replies) => replies.pop_front(),
+                    &mut Pending::Failed(..) => return,
+                };
+                let reply = match reply {
+                    Some(reply) => reply,
+                    None => return fail(&reader_pending, &io::Error::new(io::ErrorKind::InvalidData, "reply without a request")),
+                };
+                let result = match codec::decode_rep(&frame) {
+                    Ok(rep) => Ok(rep),
+                    Err(err) => {
+                        // framing is intact, so only this request fails
+                        Err(ClientError::Decode(err))
+                    },
+                };
+                // the receiver is gone if the request future was dropped
+                let _ = reply.send(result);
+            }
+        });
+
+        Connection {
+            requests: requests_tx,
+            pending: pending,
+            tasks: [writer_task, reader_task],
+        }
+    }
+
+    fn is_broken(&self) -> bool {
+        matches!(*self.pending.lock().unwrap(), Pending::Failed(..))
+    }
+
+    async fn request(&self, req: &GlobalReq) -> Result<GlobalRep, ClientError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if self.requests.send((client::encode_req(req), reply_tx)).is_err() {
+            return Err(ClientError::Broken);
+        }
+        match reply_rx.await {
+            Ok(result) => result,
+            Err(..) => Err(ClientError::Broken),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Connector {
+    Tcp(Vec<SocketAddr>),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Connector {
+    async fn connect(&self, max_frame_len: usize) -> io::Result<Connection> {
+        match self {
+            &Connector::Tcp(ref addrs) => {
+                let stream = TcpStream::connect(&addrs[..]).await?;
+                stream.set_nodelay(true)?;
+                Ok(Connection::new(stream, max_frame_len))
+            },
+            #[cfg(unix)]
+            &Connector::Unix(ref path) =>
+                Ok(Connection::new(tokio::net::UnixStream::connect(path).await?, max_frame_len)),
+        }
+    }
+}
+
+struct Inner {
+    main: Connection,
+    connector: Option<Connector>,
+    max_frame_len: usize,
+    /// Idle connections for blocking lends.
+    lend_connections: Mutex<Vec<Connection>>,
+}
+
+/// Cheaply clonable handle; all clones share the connection.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+impl Client {
+    pub async fn connect<A>(addr: A) -> io::Result<Client> where A: ToSocketAddrs {
+        let addrs: Vec<_> = tokio::net::lookup_host(addr).await?.collect();
+        let connector = Connector::Tcp(addrs);
+        let main = connector.connect(DEFAULT_MAX_FRAME_LEN).await?;
+        Ok(Client::with_connection(main, Some(connector)))
+    }
+
+    #[cfg(unix)]
+    pub async fn connect_unix<P>(path: P) -> io::Result<Client> where P: Into<PathBuf> {
+        let connector = Connector::Unix(path.into());
+        let main = connector.connect(DEFAULT_MAX_FRAME_LEN).await?;
+        Ok(Client::with_connection(main, Some(connector)))
+    }
+
+    /// Client over an established stream. It can not open more connections,
+    /// so blocking lends are sent over the same stream.
+    pub fn new<S>(stream: S) -> Client where S: AsyncRead + AsyncWrite + Send + 'static {
+        Client::with_connection(Connection::new(stream, DEFAULT_MAX_FRAME_LEN), None)
+    }
+
+    fn with_connection(main: Connection, connector: Option<Connector>) -> Client {
+        Client {
+            inner: Arc::new(Inner {
+                main: main,
+                connector: connector,
+                max_frame_len: DEFAULT_MAX_FRAME_LEN,
+                lend_connections: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Whether the main connection has failed: every further request fails.
+    pub fn is_broken(&self) -> bool {
+        self.inner.main.is_broken()
+    }
+
+    /// Sends a request and waits for its reply without interpreting it.
+    pub async fn request(&self, req: &GlobalReq) -> Result<GlobalRep, ClientError> {
+        match (req, &self.inner.connector) {
+            (&GlobalReq::Lend { mode: LendMode::Block, .. }, &Some(ref connector)) =>
+                self.blocking_lend(req, connector).await,
+            _ =>
+                self.inner.main.request(req).await,
+        }
+    }
+
+    async fn blocking_lend(&self, req: &GlobalReq, connector: &Connector) -> Result<GlobalRep, ClientError> {
+        let idle = self.inner.lend_connections.lock().unwrap().pop();
+        let connection = match idle {
+            Some(connection) if !connection.is_broken() => connection,
+            _ => connector.connect(self.inner.max_frame_len).await?,
+        };
+        // if this future is dropped while waiting, the connection is dropped as
+        // well: the server may still lend a task to it, which then returns to
+        // the queue after its timeout
+        let result = connection.request(req).await;
+        if result.is_ok() {
+            self.inner.lend_connections.lock().unwrap().push(connection);
+        }
+        result
+    }
+
+    pub async fn ping(&self) -> Result<(), ClientError> {
+        client::pong(self.request(&GlobalReq::Ping).await?)
+    }
+
+    pub async fn count(&self) -> Result<usize, ClientError> {
+        client::counted(self.request(&GlobalReq::Count).await?)
+    }
+
+    /// Returns `false` if the key already exists (the entry is kept).
+    pub async fn add(&self, key: Key, value: Value, mode: AddMode) -> Result<bool, ClientError> {
+        client::added(self.request(&GlobalReq::Add { key: key, value: value, mode: mode, }).await?)
+    }
+
+    /// Returns `false` if there is no entry with the key.
+    pub async fn update(&self, key: Key, value: Value) -> Result<bool, ClientError> {
+        client::updated(self.request(&GlobalReq::Update(key, value)).await?)
+    }
+
+    pub async fn lookup(&self, key: Key) -> Result<Option<Value>, ClientError> {
+        client::value_found(self.request(&GlobalReq::Lookup(key)).await?)
+    }
+
+    /// Returns `false` if there is no entry with the key.
+    pub async fn remove(&self, key: Key) -> Result<bool, ClientError> {
+        client::removed(self.request(&GlobalReq::Remove(key)).await?)
+    }
+
+    /// Returns `None` if the queue is empty (only in `LendMode::Poll`).
+    pub async fn lend(&self, timeout_ms: u64, mode: LendMode) -> Result<Option<LentTask>, ClientError> {
+        client::lent(self.request(&GlobalReq::Lend { timeout: timeout_ms, mode: mode, }).await?)
+    }
+
+    /// Returns `false` if the task is not lent anymore (e.g. its timeout has expired).
+    pub async fn repay(&self, lend_key: u64, key: Key, value: Value, status: RepayStatus) -> Result<bool, ClientError> {
+        client::repaid(self.request(&GlobalReq::Repay { lend_key: lend_key, key: key, value: value, status: status, }).await?)
+    }
+
+    /// Returns `false` if the task is not lent anymore.
+    pub async fn heartbeat(&self, lend_key: u64, key: Key, timeout_ms: u64) -> Result<bool, ClientError> {
+        client::heartbeaten(self.request(&GlobalReq::Heartbeat { lend_key: lend_key, key: key, timeout: timeout_ms, }).await?)
+    }
+
+    pub async fn stats(&self) -> Result<Stats, ClientError> {
+        client::stats_got(self.request(&GlobalReq::Stats).await?)
+    }
+
+    pub async fn flush(&self) -> Result<(), ClientError> {
+        client::flushed(self.request(&GlobalReq::Flush).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+    use super::super::{LendMode, AddMode, GlobalReq, GlobalRep};
+    use super::super::client::ClientError;
+    use super::super::codec::DecodeError;
+    use super::super::test_support::{bytes, encode_rep};
+    use super::{Client, read_frame, write_frame};
+
+    /// Echoes lookup keys as values (after a delay for `slow`), parks blocking lends forever.
+    async fn serve_connection(mut stream: TcpStream) {
+        while let Ok(frame) = read_frame(&mut stream, 1024).await {
+            let rep = match GlobalReq::decode(&frame).unwrap().0 {
+                GlobalReq::Ping => GlobalRep::Pong,
+                GlobalReq::Count => {
+                    write_frame(&mut stream, &[1, 0, 0, 0, 7, 0]).await.unwrap();
+                    continue;
+                },
+                GlobalReq::Add { .. } => GlobalRep::Added,
+                GlobalReq::Lookup(key) => {
+                    if &*key == b"slow" {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    GlobalRep::ValueFound(key)
+                },
+                GlobalReq::Lend { mode: LendMode::Block, .. } => park().await,
+                GlobalReq::Lend { mode: LendMode::Poll, .. } => GlobalRep::QueueEmpty,
+                req => panic!("unexpected request {:?}", req),
+            };
+            write_frame(&mut stream, &encode_rep(&rep)).await.unwrap();
+        }
+    }
+
+    async fn park() -> GlobalRep {
+        std::future::pending().await
+    }
+
+    async fn server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream));
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn pipelined_replies_in_order() {
+        let client = Client::connect(server().await).await.unwrap();
+        let handles: Vec<_> = (0 .. 100u32)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move { (i, client.lookup(bytes(&i.to_be_bytes())).await.unwrap()) })
+            })
+            .collect();
+        for handle in handles {
+            let (i, value) = handle.await.unwrap();
+            assert_eq!(value, Some(bytes(&i.to_be_bytes())));
+        }
+    }
+
+    #[tokio::test]
+    async fn blocking_lend_does_not_block_add() {
+        let client = Client::connect(server().await).await.unwrap();
+        let lend = tokio::spawn({
+            let client = client.clone();
+            async move { client.lend(1000, LendMode::Block).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let added = tokio::time::timeout(Duration::from_secs(5), client.add(bytes(b"cat"), bytes(b"small"), AddMode::Tail)).await;
+        assert!(added.unwrap().unwrap());
+        assert!(!lend.is_finished());
+        lend.abort();
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_keeps_stream_in_sync() {
+        let client = Client::connect(server().await).await.unwrap();
+        let slow = tokio::time::timeout(Duration::from_millis(10), client.lookup(bytes(b"slow"))).await;
+        assert!(slow.is_err());
+        assert_eq!(client.lookup(bytes(b"fast")).await.unwrap(), Some(bytes(b"fast")));
+        client.ping().await.unwrap();
+        assert!(!client.is_broken());
+    }
+
+    #[tokio::test]
+    async fn trailing_bytes() {
+        let client = Client::connect(server().await).await.unwrap();
+        match client.count().await {
+            Err(ClientError::Decode(DecodeError::TrailingBytes(1))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        client.ping().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn broken_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            drop(stream);
+        });
+        let client = Client::new(TcpStream::connect(addr).await.unwrap());
+        assert!(client.ping().await.is_err());
+        assert!(client.is_broken());
+        assert!(client.lend(10, LendMode::Poll).await.is_err());
+    }
+}
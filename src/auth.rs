@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use hmac::{
+    Hmac,
+    Mac,
+};
+use sha2::Sha256;
+
+use super::{
+    Value,
+    AuthMechanism,
+    Permissions,
+    GlobalReq,
+    GlobalRep,
+};
+
+pub const NONCE_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn hmac_response(secret: &[u8], nonce: &[u8]) -> Value {
+    let mut mac = HmacSha256::new_from_slice(secret)
+        .expect("HMAC accepts keys of any length");
+    mac.update(nonce);
+    Arc::from(&mac.finalize().into_bytes()[..])
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Set of mechanisms a key may be used with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mechanisms(u8);
+
+impl Mechanisms {
+    pub const NONE: Mechanisms = Mechanisms(0);
+    pub const ALL: Mechanisms = Mechanisms(0x03);
+
+    fn bit(mechanism: AuthMechanism) -> u8 {
+        match mechanism {
+            AuthMechanism::Token => 0x01,
+            AuthMechanism::HmacSha256 => 0x02,
+        }
+    }
+
+    pub fn with(self, mechanism: AuthMechanism) -> Mechanisms {
+        Mechanisms(self.0 | Mechanisms::bit(mechanism))
+    }
+
+    pub fn contains(&self, mechanism: AuthMechanism) -> bool {
+        self.0 & Mechanisms::bit(mechanism) != 0
+    }
+}
+
+impl From<AuthMechanism> for Mechanisms {
+    fn from(mechanism: AuthMechanism) -> Mechanisms {
+        Mechanisms::NONE.with(mechanism)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuthKey {
+    pub secret: Arc<[u8]>,
+    pub permissions: Permissions,
+    /// `Auth` with any other mechanism never matches this key.
+    pub mechanisms: Mechanisms,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SessionState {
+    Anonymous,
+    Challenged(Value),
+    Authenticated(Permissions),
+}
+
+pub struct AuthSession {
+    keys: Arc<[AuthKey]>,
+    state: SessionState,
+}
+
+impl AuthSession {
+    pub fn new(secret: Arc<[u8]>) -> AuthSession {
+        AuthSession::with_keys(Arc::from(vec![AuthKey { secret: secret, permissions: Permissions::ALL, mechanisms: Mechanisms::ALL, }]))
+    }
+
+    pub fn with_keys(keys: Arc<[AuthKey]>) -> AuthSession {
+        AuthSession {
+            keys: keys,
+            state: SessionState::Anonymous,
+        }
+    }
+
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        matches!(self.state, SessionState::Authenticated(..))
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        match self.state {
+            SessionState::Authenticated(permissions) => permissions,
+            _ => Permissions::NONE,
+        }
+    }
+
+    /// Returns a reply if the request is consumed by authentication: either it
+    /// is an `Auth` step or it is rejected with `Unauthenticated` or `Forbidden`.
+    /// `None` means that the request should be processed as usual.
+    pub fn handle(&mut self, req: &GlobalReq) -> Option<GlobalRep> {
+        match req {
+            &GlobalReq::Auth { mechanism, .. } if !self.keys.iter().any(|key| key.mechanisms.contains(mechanism)) =>
+                Some(self.finish(None)),
+            &GlobalReq::Auth { mechanism: AuthMechanism::Token, ref payload, } => {
+                let found = self.keys.iter()
+                    .filter(|key| key.mechanisms.contains(AuthMechanism::Token))
+                    .find(|key| constant_time_eq(payload, &key.secret))
+                    .map(|key| key.permissions);
+                Some(self.finish(found))
+            },
+            &GlobalReq::Auth { mechanism: AuthMechanism::HmacSha256, ref payload, } if payload.is_empty() => {
+                let mut nonce = [0; NONCE_LEN];
+                if getrandom::getrandom(&mut nonce).is_err() {
+                    return Some(self.finish(None));
+                }
+                let nonce: Value = Arc::from(&nonce[..]);
+                self.state = SessionState::Challenged(nonce.clone());
+                Some(GlobalRep::AuthChallenge(nonce))
+            },
+            &GlobalReq::Auth { mechanism: AuthMechanism::HmacSha256, ref payload, } => {
+                let found = match self.state {
+                    SessionState::Challenged(ref nonce) =>
+                        self.keys.iter()
+                        .filter(|key| key.mechanisms.contains(AuthMechanism::HmacSha256))
+                        .find(|key| {
+                            let mut mac = HmacSha256::new_from_slice(&key.secret)
+                                .expect("HMAC accepts keys of any length");
+                            mac.update(nonce);
+                            mac.verify_slice(payload).is_ok()
+                        })
+                        .map(|key| key.permissions),
+                    _ =>
+                        None,
+                };
+                Some(self.finish(found))
+            },
+            req if req.requires_auth() && !self.is_authenticated() =>
+                Some(GlobalRep::Unauthenticated),
+            req if !self.permissions().allows(req) =>
+                Some(GlobalRep::Forbidden),
+            _ =>
+                None,
+        }
+    }
+
+    fn finish(&mut self, found: Option<Permissions>) -> GlobalRep {
+        if let Some(permissions) = found {
+            self.state = SessionState::Authenticated(permissions);
+            GlobalRep::AuthOk
+        } else {
+            self.state = SessionState::Anonymous;
+            GlobalRep::AuthFailed
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Credentials {
+    Token(Arc<[u8]>),
+    HmacSha256(Arc<[u8]>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthStep {
+    Send(GlobalReq),
+    Done,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    Failed,
+    UnexpectedReply(GlobalRep),
+}
+
+impl Credentials {
+    pub fn start(&self) -> GlobalReq {
+        match self {
+            &Credentials::Token(ref token) =>
+                GlobalReq::Auth { mechanism: AuthMechanism::Token, payload: token.clone(), },
+            &Credentials::HmacSha256(..) =>
+                GlobalReq::Auth { mechanism: AuthMechanism::HmacSha256, payload: Arc::from(&[][..]), },
+        }
+    }
+
+    pub fn step(&self, rep: GlobalRep) -> Result<AuthStep, AuthError> {
+        match (self, rep) {
+            (_, GlobalRep::AuthOk) =>
+                Ok(AuthStep::Done),
+            (_, GlobalRep::AuthFailed) =>
+                Err(AuthError::Failed),
+            (&Credentials::HmacSha256(ref secret), GlobalRep::AuthChallenge(nonce)) =>
+                Ok(AuthStep::Send(GlobalReq::Auth {
+                    mechanism: AuthMechanism::HmacSha256,
+                    payload: hmac_response(secret, &nonce),
+                })),
+            (_, rep) =>
+                Err(AuthError::UnexpectedReply(rep)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use super::super::{Permission, Permissions, AuthMechanism, LendMode, GlobalReq, GlobalRep};
+    use super::{Mechanisms, AuthKey, AuthSession, SessionState, Credentials, AuthStep, AuthError};
+
+    fn run(credentials: Credentials, session: &mut AuthSession) -> Result<(), AuthError> {
+        let mut req = credentials.start();
+        loop {
+            let rep = session.handle(&req).unwrap();
+            match credentials.step(rep)? {
+                AuthStep::Send(next_req) => req = next_req,
+                AuthStep::Done => return Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn unauthenticated() {
+        let mut session = AuthSession::new(Arc::from(&b"secret"[..]));
+        assert_eq!(session.handle(&GlobalReq::Ping), None);
+        assert_eq!(session.handle(&GlobalReq::Info), None);
+        assert_eq!(session.handle(&GlobalReq::Terminate), Some(GlobalRep::Unauthenticated));
+    }
+
+    #[test]
+    fn token_ok() {
+        let mut session = AuthSession::new(Arc::from(&b"secret"[..]));
+        assert_eq!(run(Credentials::Token(Arc::from(&b"secret"[..])), &mut session), Ok(()));
+        assert!(session.is_authenticated());
+        assert_eq!(session.handle(&GlobalReq::Terminate), None);
+    }
+
+    #[test]
+    fn token_failed() {
+        let mut session = AuthSession::new(Arc::from(&b"secret"[..]));
+        assert_eq!(run(Credentials::Token(Arc::from(&b"guess"[..])), &mut session), Err(AuthError::Failed));
+        assert_eq!(session.state(), &SessionState::Anonymous);
+    }
+
+    #[test]
+    fn hmac_ok() {
+        let mut session = AuthSession::new(Arc::from(&b"secret"[..]));
+        assert_eq!(run(Credentials::HmacSha256(Arc::from(&b"secret"[..])), &mut session), Ok(()));
+        assert!(session.is_authenticated());
+    }
+
+    #[test]
+    fn hmac_failed() {
+        let mut session = AuthSession::new(Arc::from(&b"secret"[..]));
+        assert_eq!(run(Credentials::HmacSha256(Arc::from(&b"guess"[..])), &mut session), Err(AuthError::Failed));
+        assert!(!session.is_authenticated());
+    }
+
+    #[test]
+    fn failed_auth_drops_previous_session() {
+        let mut session = AuthSession::new(Arc::from(&b"secret"[..]));
+        run(Credentials::Token(Arc::from(&b"secret"[..])), &mut session).unwrap();
+        assert!(run(Credentials::Token(Arc::from(&b"guess"[..])), &mut session).is_err());
+        assert_eq!(session.handle(&GlobalReq::Count), Some(GlobalRep::Unauthenticated));
+    }
+
+    #[test]
+    fn role_keys() {
+        let keys = vec![
+            AuthKey { secret: Arc::from(&b"admin"[..]), permissions: Permissions::ALL, mechanisms: Mechanisms::ALL, },
+            AuthKey { secret: Arc::from(&b"worker"[..]), permissions: Permissions::from(Permission::Worker), mechanisms: Mechanisms::ALL, },
+        ];
+        let mut session = AuthSession::with_keys(Arc::from(keys));
+        assert_eq!(run(Credentials::HmacSha256(Arc::from(&b"worker"[..])), &mut session), Ok(()));
+        assert_eq!(session.state(), &SessionState::Authenticated(Permissions::from(Permission::Worker)));
+        assert_eq!(session.handle(&GlobalReq::Lend { timeout: 177, mode: LendMode::Poll, }), None);
+        assert_eq!(session.handle(&GlobalReq::Ping), None);
+        assert_eq!(session.handle(&GlobalReq::Terminate), Some(GlobalRep::Forbidden));
+    }
+
+    #[test]
+    fn disallowed_mechanism() {
+        let keys = vec![
+            AuthKey { secret: Arc::from(&b"admin"[..]), permissions: Permissions::ALL, mechanisms: Mechanisms::from(AuthMechanism::HmacSha256), },
+            AuthKey { secret: Arc::from(&b"worker"[..]), permissions: Permissions::from(Permission::Worker), mechanisms: Mechanisms::from(AuthMechanism::Token), },
+        ];
+        let mut session = AuthSession::with_keys(Arc::from(keys));
+        assert_eq!(run(Credentials::Token(Arc::from(&b"admin"[..])), &mut session), Err(AuthError::Failed));
+        assert_eq!(run(Credentials::HmacSha256(Arc::from(&b"worker"[..])), &mut session), Err(AuthError::Failed));
+        assert_eq!(run(Credentials::HmacSha256(Arc::from(&b"admin"[..])), &mut session), Ok(()));
+        assert_eq!(run(Credentials::Token(Arc::from(&b"worker"[..])), &mut session), Ok(()));
+        assert_eq!(session.permissions(), Permissions::from(Permission::Worker));
+
+        let keys = vec![AuthKey { secret: Arc::from(&b"admin"[..]), permissions: Permissions::ALL, mechanisms: Mechanisms::from(AuthMechanism::HmacSha256), }];
+        let mut session = AuthSession::with_keys(Arc::from(keys));
+        assert_eq!(session.handle(&Credentials::Token(Arc::from(&b"admin"[..])).start()), Some(GlobalRep::AuthFailed));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    env,
+    io,
+    process,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{
+        AsyncReadExt,
+        AsyncWriteExt,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    time::timeout,
+};
+
+use zeromq::{
+    Socket,
+    SocketRecv,
+    SocketSend,
+    ReqSocket,
+    ZmqMessage,
+    ZmqError,
+};
+
+use spiderq_proto::{
+    prometheus,
+    GlobalReq,
+    GlobalRep,
+    ProtoError,
+};
+
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[allow(dead_code)]
+#[derive(Debug)]
+enum Error {
+    Zmq(ZmqError),
+    Timeout,
+    EmptyReply,
+    Decode(ProtoError),
+    UnexpectedReply(GlobalRep),
+}
+
+async fn request(socket: &mut ReqSocket, req: GlobalReq) -> Result<GlobalRep, Error> {
+    let mut frame = vec![0; req.encode_len()];
+    req.encode(&mut frame);
+    socket.send(ZmqMessage::from(frame)).await.map_err(Error::Zmq)?;
+    let reply = socket.recv().await.map_err(Error::Zmq)?;
+    let frame = reply.get(0).ok_or(Error::EmptyReply)?;
+    let (rep, _) = GlobalRep::decode(frame).map_err(Error::Decode)?;
+    Ok(rep)
+}
+
+async fn poll(endpoint: &str) -> Result<Vec<GlobalRep>, Error> {
+    let mut socket = ReqSocket::new();
+    socket.connect(endpoint).await.map_err(Error::Zmq)?;
+    let mut reps = Vec::new();
+    for req in [GlobalReq::Stats, GlobalReq::Count] {
+        match request(&mut socket, req).await? {
+            rep @ GlobalRep::StatsGot { .. } | rep @ GlobalRep::Counted(..) =>
+                reps.push(rep),
+            other =>
+                return Err(Error::UnexpectedReply(other)),
+        }
+    }
+    socket.close().await;
+    Ok(reps)
+}
+
+async fn scrape(endpoint: &str) -> String {
+    let mut body = String::new();
+    match timeout(POLL_TIMEOUT, poll(endpoint)).await.unwrap_or(Err(Error::Timeout)) {
+        Ok(reps) => {
+            prometheus::write_up(&mut body, true).unwrap();
+            body.push_str(&prometheus::render(&reps));
+        },
+        Err(e) => {
+            eprintln!("spiderq poll of {} failed: {:?}", endpoint, e);
+            prometheus::write_up(&mut body, false).unwrap();
+        },
+    }
+    body
+}
+
+async fn serve(mut stream: TcpStream, endpoint: &str) -> io::Result<()> {
+    let mut buf = [0; 1024];
+    let n = timeout(READ_TIMEOUT, stream.read(&mut buf)).await
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "http request read timed out")))?;
+    let request_line = buf[.. n].split(|&b| b == b'\n').next().unwrap_or(&[]);
+    let (status, content_type, body) = if request_line.starts_with(b"GET /metrics ") {
+        ("200 OK", prometheus::CONTENT_TYPE, scrape(endpoint).await)
+    } else {
+        ("404 Not Found", "text/plain", "not found, try /metrics\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body,
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<_> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <spiderq-zmq-endpoint> <listen-addr>", args[0]);
+        eprintln!("   eg: {} tcp://127.0.0.1:37731 0.0.0.0:9737", args[0]);
+        process::exit(1);
+    }
+    let (endpoint, listen_addr): (Arc<str>, _) = (Arc::from(&args[1][..]), &args[2]);
+
+    let listener = match TcpListener::bind(listen_addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("failed to bind {}: {}", listen_addr, e);
+            process::exit(1);
+        },
+    };
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let endpoint = endpoint.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, &endpoint).await {
+                        eprintln!("http connection error: {}", e);
+                    }
+                });
+            },
+            Err(e) =>
+                eprintln!("accept error: {}", e),
+        }
+    }
+}
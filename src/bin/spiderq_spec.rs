@@ -0,0 +1,21 @@
+use std::{
+    env,
+    process,
+};
+
+use spiderq_proto::schema;
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    match args.get(1).map(|arg| &arg[..]) {
+        Some("markdown") if args.len() == 2 =>
+            print!("{}", schema::markdown()),
+        Some("json") if args.len() == 2 =>
+            print!("{}", schema::json()),
+        _ => {
+            eprintln!("Usage: {} <markdown|json>", args[0]);
+            eprintln!("   eg: {} markdown > PROTOCOL.md", args[0]);
+            process::exit(1);
+        },
+    }
+}
@@ -0,0 +1,236 @@
+//! Serde representation of `Key` and `Value` byte strings.
+//!
+//! Protocol types serialize keys and values with `spiderq_proto::bytes_repr`:
+//! standard base64 strings in human readable formats with the `serde-base64`
+//! feature, a sequence of numbers without it, and a length prefixed string of
+//! bytes in binary formats either way. Own `Arc<[u8]>` fields pick the same
+//! representation with `#[serde(with = "spiderq_proto::bytes_repr")]` or a
+//! fixed one:
+//!
+//! * `spiderq_proto::bytes_repr::array` for byte arrays;
+//! * `spiderq_proto::bytes_repr::base64` (with the `serde-base64` feature) for
+//!   base64 strings.
+//!
+//! In human readable formats all of them accept byte arrays and, with the
+//! `serde-base64` feature, base64 strings.
+
+use std::{
+    fmt,
+    sync::Arc,
+};
+
+use serde::{
+    de::{
+        self,
+        Visitor,
+        SeqAccess,
+    },
+    Deserializer,
+};
+
+#[cfg(not(feature = "serde-base64"))]
+pub use self::array::{
+    serialize,
+    deserialize,
+};
+#[cfg(feature = "serde-base64")]
+pub use self::base64::{
+    serialize,
+    deserialize,
+};
+
+pub mod array {
+    use std::sync::Arc;
+
+    use serde::{
+        Serializer,
+        Deserializer,
+    };
+
+    pub fn serialize<S>(bytes: &Arc<[u8]>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_bytes(bytes)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Arc<[u8]>, D::Error> where D: Deserializer<'de> {
+        super::deserialize_bytes(deserializer)
+    }
+}
+
+#[cfg(feature = "serde-base64")]
+pub mod base64 {
+    use std::sync::Arc;
+
+    use ::base64::Engine;
+    use serde::{
+        Serializer,
+        Deserializer,
+    };
+
+    pub fn serialize<S>(bytes: &Arc<[u8]>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&::base64::engine::general_purpose::STANDARD.encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Arc<[u8]>, D::Error> where D: Deserializer<'de> {
+        super::deserialize_bytes(deserializer)
+    }
+}
+
+/// Binary formats (bincode, postcard) are not self describing, so only human
+/// readable ones are asked to guess the representation.
+fn deserialize_bytes<'de, D>(deserializer: D) -> Result<Arc<[u8]>, D::Error> where D: Deserializer<'de> {
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(BytesVisitor)
+    } else {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Arc<[u8]>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if cfg!(feature = "serde-base64") {
+            write!(f, "a byte array or a base64 string")
+        } else {
+            write!(f, "a byte array")
+        }
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Arc<[u8]>, E> where E: de::Error {
+        Ok(Arc::from(bytes))
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Arc<[u8]>, E> where E: de::Error {
+        Ok(Arc::from(bytes))
+    }
+
+    #[cfg(feature = "serde-base64")]
+    fn visit_str<E>(self, string: &str) -> Result<Arc<[u8]>, E> where E: de::Error {
+        use ::base64::Engine;
+        match ::base64::engine::general_purpose::STANDARD.decode(string) {
+            Ok(bytes) => Ok(Arc::from(bytes)),
+            Err(err) => Err(E::custom(format_args!("invalid base64 string: {}", err))),
+        }
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Arc<[u8]>, A::Error> where A: SeqAccess<'de> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Arc::from(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use super::super::{AddMode, ServerErrorCode, GlobalReq, GlobalRep, ProtoError, MessageKind, FieldKind};
+
+    fn round_trip_rep(rep: GlobalRep) -> String {
+        let json = serde_json::to_string(&rep).unwrap();
+        assert_eq!(serde_json::from_str::<GlobalRep>(&json).unwrap(), rep);
+        json
+    }
+
+    #[cfg(not(feature = "serde-base64"))]
+    #[test]
+    fn req_json() {
+        let req = GlobalReq::Add { key: Arc::from(&b"cat"[..]), value: Arc::from(&b""[..]), mode: AddMode::Tail, };
+        let json = serde_json::to_string(&req).unwrap();
+        assert_eq!(json, r#"{"Add":{"key":[99,97,116],"value":[],"mode":"Tail"}}"#);
+        assert_eq!(serde_json::from_str::<GlobalReq>(&json).unwrap(), req);
+        assert_eq!(serde_json::to_string(&GlobalReq::Ping).unwrap(), r#""Ping""#);
+    }
+
+    #[cfg(feature = "serde-base64")]
+    #[test]
+    fn req_json_base64() {
+        let req = GlobalReq::Add { key: Arc::from(&b"cat"[..]), value: Arc::from(&[0, 255][..]), mode: AddMode::Tail, };
+        let json = serde_json::to_string(&req).unwrap();
+        assert_eq!(json, r#"{"Add":{"key":"Y2F0","value":"AP8=","mode":"Tail"}}"#);
+        assert_eq!(serde_json::from_str::<GlobalReq>(&json).unwrap(), req);
+        let rep = GlobalRep::Error(ProtoError::DbQueueOutOfSync(Arc::from(&b"key"[..])));
+        assert_eq!(serde_json::to_string(&rep).unwrap(), r#"{"Error":{"DbQueueOutOfSync":"a2V5"}}"#);
+    }
+
+    #[test]
+    fn byte_arrays_always_accepted() {
+        let req: GlobalReq = serde_json::from_str(r#"{"Update":[[1,2],[3]]}"#).unwrap();
+        assert_eq!(req, GlobalReq::Update(Arc::from(&[1, 2][..]), Arc::from(&[3][..])));
+    }
+
+    #[test]
+    fn rep_json() {
+        round_trip_rep(GlobalRep::Counted(177));
+        round_trip_rep(GlobalRep::Lent { lend_key: 1, key: Arc::from(&b"k"[..]), value: Arc::from(&b"v"[..]), });
+        round_trip_rep(GlobalRep::ServerError { code: ServerErrorCode::from_code(100), message: "oops".to_string(), });
+        round_trip_rep(GlobalRep::Error(ProtoError::DbQueueOutOfSync(Arc::from(&b"key"[..]))));
+        let json = round_trip_rep(GlobalRep::Error(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalReqAdd,
+            field: FieldKind::KeyLen,
+            required: 4,
+            given: 1,
+        }));
+        assert_eq!(json, r#"{"Error":{"NotEnoughData":{"message":"GlobalReqAdd","field":"KeyLen","required":4,"given":1}}}"#);
+    }
+
+    #[test]
+    fn unknown_server_error_code() {
+        let json = round_trip_rep(GlobalRep::ServerError { code: ServerErrorCode::from_code(100), message: String::new(), });
+        assert_eq!(json, r#"{"ServerError":{"code":{"Unknown":100},"message":""}}"#);
+        assert!(serde_json::from_str::<GlobalRep>(r#"{"ServerError":{"code":{"Unknown":3},"message":""}}"#).is_err());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let reqs = vec![
+            GlobalReq::Add { key: Arc::from(&b"cat"[..]), value: Arc::from(&b""[..]), mode: AddMode::Tail, },
+            GlobalReq::Update(Arc::from(&[0, 255][..]), Arc::from(&b"value"[..])),
+            GlobalReq::Ping,
+        ];
+        for req in reqs {
+            let bytes = bincode::serialize(&req).unwrap();
+            assert_eq!(bincode::deserialize::<GlobalReq>(&bytes).unwrap(), req);
+            let bytes = postcard::to_allocvec(&req).unwrap();
+            assert_eq!(postcard::from_bytes::<GlobalReq>(&bytes).unwrap(), req);
+        }
+        let reps = vec![
+            GlobalRep::Lent { lend_key: 1, key: Arc::from(&b"k"[..]), value: Arc::from(&b"v"[..]), },
+            GlobalRep::ServerError { code: ServerErrorCode::from_code(100), message: "oops".to_string(), },
+            GlobalRep::Error(ProtoError::DbQueueOutOfSync(Arc::from(&b"key"[..]))),
+        ];
+        for rep in reps {
+            let bytes = bincode::serialize(&rep).unwrap();
+            assert_eq!(bincode::deserialize::<GlobalRep>(&bytes).unwrap(), rep);
+            let bytes = postcard::to_allocvec(&rep).unwrap();
+            assert_eq!(postcard::from_bytes::<GlobalRep>(&bytes).unwrap(), rep);
+        }
+    }
+
+    #[cfg(feature = "serde-base64")]
+    #[test]
+    fn base64_module() {
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Blob {
+            #[serde(with = "super::base64")]
+            data: Arc<[u8]>,
+        }
+
+        let blob = Blob { data: Arc::from(&b"cat"[..]), };
+        let json = serde_json::to_string(&blob).unwrap();
+        assert_eq!(json, r#"{"data":"Y2F0"}"#);
+        assert_eq!(serde_json::from_str::<Blob>(&json).unwrap(), blob);
+        assert_eq!(serde_json::from_str::<Blob>(r#"{"data":[99,97,116]}"#).unwrap(), blob);
+        assert_eq!(bincode::deserialize::<Blob>(&bincode::serialize(&blob).unwrap()).unwrap(), blob);
+        assert_eq!(postcard::from_bytes::<Blob>(&postcard::to_allocvec(&blob).unwrap()).unwrap(), blob);
+        assert!(serde_json::from_str::<Blob>(r#"{"data":"!!"}"#).is_err());
+        assert_eq!(serde_json::from_str::<GlobalReq>(r#"{"Lookup":"Y2F0"}"#).unwrap(), GlobalReq::Lookup(Arc::from(&b"cat"[..])));
+    }
+}
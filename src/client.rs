@@ -0,0 +1,555 @@
+//! Blocking client over a byte stream.
+//!
+//! Stream transports have no message boundaries of their own, so every frame
+//! is prefixed with its length as `uint32_t` (big endian).
+
+use std::{
+    io::{
+        self,
+        Read,
+        Write,
+    },
+    fmt,
+    net::{
+        TcpStream,
+        ToSocketAddrs,
+    },
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::UnixStream,
+    path::Path,
+};
+
+use super::{
+    Key,
+    Value,
+    ServerErrorCode,
+    LendMode,
+    AddMode,
+    RepayStatus,
+    GlobalReq,
+    GlobalRep,
+    ProtoError,
+    codec::{
+        self,
+        DecodeError,
+    },
+};
+
+/// Frames larger than this are rejected as a sign of a desynchronized stream.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// `frame` prefixed with its length, ready to be written in one go.
+pub fn length_prefixed(frame: &[u8]) -> Vec<u8> {
+    let mut area = Vec::with_capacity(4 + frame.len());
+    area.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+    area.extend_from_slice(frame);
+    area
+}
+
+/// Frame length from its prefix, checked against `max_frame_len`.
+pub fn frame_len(prefix: [u8; 4], max_frame_len: usize) -> io::Result<usize> {
+    let len = u32::from_be_bytes(prefix) as usize;
+    if len > max_frame_len {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes exceeds the limit of {} bytes", len, max_frame_len)));
+    }
+    Ok(len)
+}
+
+pub fn write_frame<W>(stream: &mut W, frame: &[u8]) -> io::Result<()> where W: Write {
+    stream.write_all(&length_prefixed(frame))?;
+    stream.flush()
+}
+
+pub fn read_frame<R>(stream: &mut R, max_frame_len: usize) -> io::Result<Vec<u8>> where R: Read {
+    let mut prefix = [0; 4];
+    stream.read_exact(&mut prefix)?;
+    let mut frame = vec![0; frame_len(prefix, max_frame_len)?];
+    stream.read_exact(&mut frame)?;
+    Ok(frame)
+}
+
+pub fn encode_req(req: &GlobalReq) -> Vec<u8> {
+    let mut frame = vec![0; req.encode_len()];
+    req.encode(&mut frame);
+    frame
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// Reply frame could not be decoded.
+    Decode(DecodeError),
+    /// Server could not decode the request.
+    Rejected(ProtoError),
+    Server { code: ServerErrorCode, message: String, },
+    Unauthenticated,
+    Forbidden,
+    UnexpectedReply(GlobalRep),
+    /// A previous failure left the connection in an unknown state.
+    Broken,
+}
+
+impl ClientError {
+    pub fn from_reply(rep: GlobalRep) -> ClientError {
+        match rep {
+            GlobalRep::Error(err) => ClientError::Rejected(err),
+            GlobalRep::ServerError { code, message, } => ClientError::Server { code: code, message: message, },
+            GlobalRep::Unauthenticated => ClientError::Unauthenticated,
+            GlobalRep::Forbidden => ClientError::Forbidden,
+            rep => ClientError::UnexpectedReply(rep),
+        }
+    }
+
+    /// Whether the connection can not be used for further requests.
+    pub fn is_desync(&self) -> bool {
+        match self {
+            &ClientError::Io(..) | &ClientError::Decode(..) | &ClientError::UnexpectedReply(..) | &ClientError::Broken =>
+                true,
+            &ClientError::Rejected(..) |
+            &ClientError::Server { .. } |
+            &ClientError::Unauthenticated |
+            &ClientError::Forbidden =>
+                false,
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ClientError::Io(ref err) => write!(f, "i/o error: {}", err),
+            &ClientError::Decode(ref err) => write!(f, "invalid reply: {}", err),
+            &ClientError::Rejected(ref err) => write!(f, "request rejected by server: {}", err),
+            &ClientError::Server { ref code, ref message, } => write!(f, "server error {:?}: {}", code, message),
+            &ClientError::Unauthenticated => write!(f, "connection is not authenticated"),
+            &ClientError::Forbidden => write!(f, "request is not permitted"),
+            &ClientError::UnexpectedReply(ref rep) => write!(f, "unexpected reply {}", rep),
+            &ClientError::Broken => write!(f, "connection is broken by a previous error"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            &ClientError::Io(ref err) => Some(err),
+            &ClientError::Decode(ref err) => Some(err),
+            &ClientError::Rejected(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> ClientError {
+        ClientError::Io(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LentTask {
+    pub lend_key: u64,
+    pub key: Key,
+    pub value: Value,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub ping: usize,
+    pub count: usize,
+    pub add: usize,
+    pub update: usize,
+    pub lookup: usize,
+    pub remove: usize,
+    pub lend: usize,
+    pub repay: usize,
+    pub heartbeat: usize,
+    pub stats: usize,
+}
+
+// Typed interpretation of replies, shared by the clients.
+
+pub(crate) fn pong(rep: GlobalRep) -> Result<(), ClientError> {
+    match rep { GlobalRep::Pong => Ok(()), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn counted(rep: GlobalRep) -> Result<usize, ClientError> {
+    match rep { GlobalRep::Counted(count) => Ok(count), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn added(rep: GlobalRep) -> Result<bool, ClientError> {
+    match rep { GlobalRep::Added => Ok(true), GlobalRep::Kept => Ok(false), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn updated(rep: GlobalRep) -> Result<bool, ClientError> {
+    match rep { GlobalRep::Updated => Ok(true), GlobalRep::NotFound => Ok(false), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn value_found(rep: GlobalRep) -> Result<Option<Value>, ClientError> {
+    match rep {
+        GlobalRep::ValueFound(value) => Ok(Some(value)),
+        GlobalRep::ValueNotFound => Ok(None),
+        rep => Err(ClientError::from_reply(rep)),
+    }
+}
+
+pub(crate) fn removed(rep: GlobalRep) -> Result<bool, ClientError> {
+    match rep { GlobalRep::Removed => Ok(true), GlobalRep::NotRemoved => Ok(false), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn lent(rep: GlobalRep) -> Result<Option<LentTask>, ClientError> {
+    match rep {
+        GlobalRep::Lent { lend_key, key, value, } => Ok(Some(LentTask { lend_key: lend_key, key: key, value: value, })),
+        GlobalRep::QueueEmpty => Ok(None),
+        rep => Err(ClientError::from_reply(rep)),
+    }
+}
+
+pub(crate) fn repaid(rep: GlobalRep) -> Result<bool, ClientError> {
+    match rep { GlobalRep::Repaid => Ok(true), GlobalRep::NotFound => Ok(false), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn heartbeaten(rep: GlobalRep) -> Result<bool, ClientError> {
+    match rep { GlobalRep::Heartbeaten => Ok(true), GlobalRep::Skipped => Ok(false), rep => Err(ClientError::from_reply(rep)), }
+}
+
+pub(crate) fn stats_got(rep: GlobalRep) -> Result<Stats, ClientError> {
+    match rep {
+        GlobalRep::StatsGot { ping, count, add, update, lookup, remove, lend, repay, heartbeat, stats, } => Ok(Stats {
+            ping: ping,
+            count: count,
+            add: add,
+            update: update,
+            lookup: lookup,
+            remove: remove,
+            lend: lend,
+            repay: repay,
+            heartbeat: heartbeat,
+            stats: stats,
+        }),
+        rep => Err(ClientError::from_reply(rep)),
+    }
+}
+
+pub(crate) fn flushed(rep: GlobalRep) -> Result<(), ClientError> {
+    match rep { GlobalRep::Flushed => Ok(()), rep => Err(ClientError::from_reply(rep)), }
+}
+
+/// Stream which supports i/o timeouts.
+pub trait Transport: Read + Write {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(read)?;
+        self.set_write_timeout(write)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(read)?;
+        self.set_write_timeout(write)
+    }
+}
+
+pub struct SyncClient<S> {
+    stream: S,
+    max_frame_len: usize,
+    broken: bool,
+}
+
+impl SyncClient<TcpStream> {
+    /// Connects with `timeout` used both for connecting and for every read and write.
+    pub fn connect<A>(addr: A, timeout: Option<Duration>) -> io::Result<SyncClient<TcpStream>> where A: ToSocketAddrs {
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to");
+        for addr in addr.to_socket_addrs()? {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    return SyncClient::with_timeouts(stream, timeout, timeout);
+                },
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+#[cfg(unix)]
+impl SyncClient<UnixStream> {
+    pub fn connect_unix<P>(path: P, timeout: Option<Duration>) -> io::Result<SyncClient<UnixStream>> where P: AsRef<Path> {
+        SyncClient::with_timeouts(UnixStream::connect(path)?, timeout, timeout)
+    }
+}
+
+impl<S> SyncClient<S> where S: Transport {
+    pub fn new(stream: S) -> SyncClient<S> {
+        SyncClient {
+            stream: stream,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            broken: false,
+        }
+    }
+
+    pub fn with_timeouts(stream: S, read: Option<Duration>, write: Option<Duration>) -> io::Result<SyncClient<S>> {
+        stream.set_timeouts(read, write)?;
+        Ok(SyncClient::new(stream))
+    }
+
+    pub fn set_timeouts(&self, read: Option<Duration>, write: Option<Duration>) -> io::Result<()> {
+        self.stream.set_timeouts(read, write)
+    }
+
+    pub fn set_max_frame_len(&mut self, max_frame_len: usize) {
+        self.max_frame_len = max_frame_len;
+    }
+
+    /// Whether an i/o or decoding failure (a timeout included) left the
+    /// stream in an unknown state: every further request fails with `ClientError::Broken`.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Writing to or reading from the stream directly desynchronizes the client.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Sends a request and waits for its reply without interpreting it.
+    pub fn request(&mut self, req: &GlobalReq) -> Result<GlobalRep, ClientError> {
+        if self.broken {
+            return Err(ClientError::Broken);
+        }
+        let result = self.exchange(req);
+        if result.is_err() {
+            self.broken = true;
+        }
+        result
+    }
+
+    fn exchange(&mut self, req: &GlobalReq) -> Result<GlobalRep, ClientError> {
+        write_frame(&mut self.stream, &encode_req(req))?;
+        let frame = read_frame(&mut self.stream, self.max_frame_len)?;
+        codec::decode_rep(&frame).map_err(ClientError::Decode)
+    }
+
+    fn call<T, F>(&mut self, req: GlobalReq, interpret: F) -> Result<T, ClientError> where F: FnOnce(GlobalRep) -> Result<T, ClientError> {
+        let rep = self.request(&req)?;
+        let result = interpret(rep);
+        if let Err(ref err) = result {
+            if err.is_desync() {
+                self.broken = true;
+            }
+        }
+        result
+    }
+
+    pub fn ping(&mut self) -> Result<(), ClientError> {
+        self.call(GlobalReq::Ping, pong)
+    }
+
+    pub fn count(&mut self) -> Result<usize, ClientError> {
+        self.call(GlobalReq::Count, counted)
+    }
+
+    /// Returns `false` if the key already exists (the entry is kept).
+    pub fn add(&mut self, key: Key, value: Value, mode: AddMode) -> Result<bool, ClientError> {
+        self.call(GlobalReq::Add { key: key, value: value, mode: mode, }, added)
+    }
+
+    /// Returns `false` if there is no entry with the key.
+    pub fn update(&mut self, key: Key, value: Value) -> Result<bool, ClientError> {
+        self.call(GlobalReq::Update(key, value), updated)
+    }
+
+    pub fn lookup(&mut self, key: Key) -> Result<Option<Value>, ClientError> {
+        self.call(GlobalReq::Lookup(key), value_found)
+    }
+
+    /// Returns `false` if there is no entry with the key.
+    pub fn remove(&mut self, key: Key) -> Result<bool, ClientError> {
+        self.call(GlobalReq::Remove(key), removed)
+    }
+
+    /// Returns `None` if the queue is empty (only in `LendMode::Poll`).
+    pub fn lend(&mut self, timeout_ms: u64, mode: LendMode) -> Result<Option<LentTask>, ClientError> {
+        self.call(GlobalReq::Lend { timeout: timeout_ms, mode: mode, }, lent)
+    }
+
+    /// Returns `false` if the task is not lent anymore (e.g. its timeout has expired).
+    pub fn repay(&mut self, lend_key: u64, key: Key, value: Value, status: RepayStatus) -> Result<bool, ClientError> {
+        self.call(GlobalReq::Repay { lend_key: lend_key, key: key, value: value, status: status, }, repaid)
+    }
+
+    /// Returns `false` if the task is not lent anymore.
+    pub fn heartbeat(&mut self, lend_key: u64, key: Key, timeout_ms: u64) -> Result<bool, ClientError> {
+        self.call(GlobalReq::Heartbeat { lend_key: lend_key, key: key, timeout: timeout_ms, }, heartbeaten)
+    }
+
+    pub fn stats(&mut self) -> Result<Stats, ClientError> {
+        self.call(GlobalReq::Stats, stats_got)
+    }
+
+    pub fn flush(&mut self) -> Result<(), ClientError> {
+        self.call(GlobalReq::Flush, flushed)
+    }
+
+    #[cfg(feature = "auth")]
+    pub fn authenticate(&mut self, credentials: &super::auth::Credentials) -> Result<(), ClientError> {
+        use super::auth::{AuthStep, AuthError};
+        let mut req = credentials.start();
+        loop {
+            let rep = self.request(&req)?;
+            match credentials.step(rep) {
+                Ok(AuthStep::Send(next_req)) => req = next_req,
+                Ok(AuthStep::Done) => return Ok(()),
+                Err(AuthError::Failed) => return Err(ClientError::Unauthenticated),
+                Err(AuthError::UnexpectedReply(rep)) => return Err(ClientError::from_reply(rep)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        thread,
+        time::Duration,
+        net::TcpListener,
+    };
+    use super::super::{ServerErrorCode, LendMode, AddMode, RepayStatus, GlobalReq, GlobalRep, ProtoError, MessageKind, FieldKind};
+    use super::super::codec::DecodeError;
+    use super::super::test_support::{bytes, serve};
+    use super::{SyncClient, ClientError, LentTask, read_frame, write_frame, DEFAULT_MAX_FRAME_LEN};
+
+    #[test]
+    fn typed_methods() {
+        let (addr, server) = serve(vec![
+            GlobalReq::Add { key: bytes(b"cat"), value: bytes(b"small"), mode: AddMode::Tail, },
+            GlobalReq::Add { key: bytes(b"cat"), value: bytes(b"big"), mode: AddMode::Tail, },
+            GlobalReq::Lookup(bytes(b"cat")),
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Repay { lend_key: 1, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Reward, },
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Count,
+        ], vec![
+            GlobalRep::Added,
+            GlobalRep::Kept,
+            GlobalRep::ValueFound(bytes(b"small")),
+            GlobalRep::Lent { lend_key: 1, key: bytes(b"cat"), value: bytes(b"small"), },
+            GlobalRep::NotFound,
+            GlobalRep::QueueEmpty,
+            GlobalRep::Counted(1),
+        ]);
+        let mut client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        assert!(client.add(bytes(b"cat"), bytes(b"small"), AddMode::Tail).unwrap());
+        assert!(!client.add(bytes(b"cat"), bytes(b"big"), AddMode::Tail).unwrap());
+        assert_eq!(client.lookup(bytes(b"cat")).unwrap(), Some(bytes(b"small")));
+        assert_eq!(client.lend(1000, LendMode::Poll).unwrap(),
+                   Some(LentTask { lend_key: 1, key: bytes(b"cat"), value: bytes(b"small"), }));
+        assert!(!client.repay(1, bytes(b"cat"), bytes(b"big"), RepayStatus::Reward).unwrap());
+        assert_eq!(client.lend(1000, LendMode::Poll).unwrap(), None);
+        assert_eq!(client.count().unwrap(), 1);
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn error_replies() {
+        let error = ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::KeyLen, required: 4, given: 0, };
+        let (addr, server) = serve(vec![GlobalReq::Ping, GlobalReq::Flush, GlobalReq::Stats], vec![
+            GlobalRep::Error(error),
+            GlobalRep::ServerError { code: ServerErrorCode::ShuttingDown, message: "bye".to_string(), },
+            GlobalRep::Pong,
+        ]);
+        let mut client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        match client.ping() {
+            Err(ClientError::Rejected(ProtoError::NotEnoughData { field: FieldKind::KeyLen, .. })) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match client.flush() {
+            Err(ClientError::Server { code: ServerErrorCode::ShuttingDown, ref message, }) if message == "bye" => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(!client.is_broken());
+        match client.stats() {
+            Err(ClientError::UnexpectedReply(GlobalRep::Pong)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(client.is_broken());
+        match client.ping() {
+            Err(ClientError::Broken) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = SyncClient::connect(listener.local_addr().unwrap(), Some(Duration::from_millis(50))).unwrap();
+        match client.ping() {
+            Err(ClientError::Io(ref err)) if err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(client.is_broken());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_stream() {
+        let (client_stream, mut server_stream) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let frame = read_frame(&mut server_stream, DEFAULT_MAX_FRAME_LEN).unwrap();
+            assert_eq!(frame, [11]);
+            write_frame(&mut server_stream, &[17]).unwrap();
+        });
+        let mut client = SyncClient::with_timeouts(client_stream, Some(Duration::from_secs(5)), None).unwrap();
+        client.ping().unwrap();
+        server.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn trailing_bytes() {
+        let (client_stream, mut server_stream) = std::os::unix::net::UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            read_frame(&mut server_stream, DEFAULT_MAX_FRAME_LEN).unwrap();
+            write_frame(&mut server_stream, &[17, 0]).unwrap();
+        });
+        let mut client = SyncClient::with_timeouts(client_stream, Some(Duration::from_secs(5)), None).unwrap();
+        match client.ping() {
+            Err(ClientError::Decode(DecodeError::TrailingBytes(1))) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(client.is_broken());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn frame_limit() {
+        let mut stream = io::Cursor::new(vec![0, 0, 1, 0]);
+        assert_eq!(read_frame(&mut stream, 255).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
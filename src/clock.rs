@@ -0,0 +1,126 @@
+//! Time source for lease deadlines, replaceable in tests.
+//!
+//! Code waiting for a deadline in real time registers a wake up with
+//! `Clock::on_advance`, so it notices when a `ManualClock` jumps forward.
+
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+/// Called after the clock was advanced, returns `false` once it is not needed anymore.
+pub type Wake = Box<dyn Fn() -> bool + Send + Sync>;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Registers `wake` to be called whenever time moves faster than in
+    /// real time. Clocks following real time never call it.
+    fn on_advance(&self, _wake: Wake) {
+    }
+}
+
+/// Real monotonic time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Time which only moves with `advance`. Clones share the same time, so a
+/// test keeps one and hands another to the code under test.
+#[derive(Clone)]
+pub struct ManualClock {
+    base: Instant,
+    elapsed: Arc<Mutex<Duration>>,
+    wakes: Arc<Mutex<Vec<Wake>>>,
+}
+
+impl fmt::Debug for ManualClock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ManualClock").field("elapsed", &self.elapsed()).finish()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            base: Instant::now(),
+            elapsed: Arc::new(Mutex::new(Duration::from_secs(0))),
+            wakes: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Moves time forward and wakes up everything registered with `on_advance`.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+        self.wakes.lock().unwrap().retain(|wake| wake());
+    }
+
+    /// Time advanced since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.base + self.elapsed()
+    }
+
+    fn on_advance(&self, wake: Wake) {
+        self.wakes.lock().unwrap().push(wake);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+    use super::{Clock, ManualClock};
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new();
+        let shared = clock.clone();
+        let start = clock.now();
+        assert_eq!(shared.now(), start);
+        shared.advance(Duration::from_millis(1500));
+        assert_eq!(clock.now() - start, Duration::from_millis(1500));
+        assert_eq!(clock.elapsed(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn wakes() {
+        let clock = ManualClock::new();
+        let woken = Arc::new(AtomicUsize::new(0));
+        let counter = woken.clone();
+        clock.on_advance(Box::new(move || counter.fetch_add(1, Ordering::SeqCst) == 0));
+        clock.advance(Duration::from_millis(1));
+        clock.advance(Duration::from_millis(1));
+        clock.advance(Duration::from_millis(1));
+        // unregistered after returning `false` on the second call
+        assert_eq!(woken.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    fmt,
+    sync::Arc,
+    mem::size_of,
+};
+
+use byteorder::{
+    ByteOrder,
+    BigEndian,
+};
+
+use super::{
+    schema::WireType,
+    U8Support,
+    ServerErrorCode,
+    MessageKind,
+    FieldKind,
+    ProtoError,
+    GlobalReq,
+    GlobalRep,
+};
+
+/// Where a field lives in a message: used to report decoding errors.
+/// `len` is the kind of a byte string length prefix and `count` is the kind
+/// of a sequence count prefix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldSpec {
+    pub message: MessageKind,
+    pub field: FieldKind,
+    pub len: FieldKind,
+    pub count: FieldKind,
+}
+
+impl FieldSpec {
+    pub fn new(message: MessageKind, field: FieldKind) -> FieldSpec {
+        FieldSpec {
+            message: message,
+            field: field,
+            len: field,
+            count: field,
+        }
+    }
+}
+
+pub trait Wire: Sized {
+    const WIRE_TYPE: WireType;
+
+    fn encode_len(&self) -> usize;
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8];
+    fn decode<'a>(data: &'a [u8], spec: FieldSpec) -> Result<(Self, &'a [u8]), ProtoError>;
+}
+
+fn take<'a>(data: &'a [u8], len: usize, message: MessageKind, field: FieldKind) -> Result<(&'a [u8], &'a [u8]), ProtoError> {
+    if data.len() < len {
+        Err(ProtoError::NotEnoughData { message: message, field: field, required: len, given: data.len(), })
+    } else {
+        Ok((&data[.. len], &data[len ..]))
+    }
+}
+
+macro_rules! impl_wire_int {
+    ($ty:ty, $wire_type:ident, $reader:ident, $writer:ident) => {
+        impl Wire for $ty {
+            const WIRE_TYPE: WireType = WireType::$wire_type;
+
+            fn encode_len(&self) -> usize {
+                size_of::<$ty>()
+            }
+
+            fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+                BigEndian::$writer(area, *self);
+                &mut area[size_of::<$ty>() ..]
+            }
+
+            fn decode<'a>(data: &'a [u8], spec: FieldSpec) -> Result<($ty, &'a [u8]), ProtoError> {
+                let (bytes, rest) = take(data, size_of::<$ty>(), spec.message, spec.field)?;
+                Ok((BigEndian::$reader(bytes), rest))
+            }
+        }
+    }
+}
+
+impl_wire_int!(u8, U8, read_u8, write_u8);
+impl_wire_int!(u16, U16, read_u16, write_u16);
+impl_wire_int!(u32, U32, read_u32, write_u32);
+impl_wire_int!(u64, U64, read_u64, write_u64);
+
+fn encode_bytes<'b>(src: &[u8], area: &'b mut [u8]) -> &'b mut [u8] {
+    let area = (src.len() as u32).encode(area);
+    area[.. src.len()].copy_from_slice(src);
+    &mut area[src.len() ..]
+}
+
+fn decode_bytes<'a>(data: &'a [u8], spec: FieldSpec) -> Result<(&'a [u8], &'a [u8]), ProtoError> {
+    let (len, rest) = u32::decode(data, FieldSpec::new(spec.message, spec.len))?;
+    take(rest, len as usize, spec.message, spec.field)
+}
+
+impl Wire for Arc<[u8]> {
+    const WIRE_TYPE: WireType = WireType::Bytes;
+
+    fn encode_len(&self) -> usize {
+        size_of::<u32>() + self.len()
+    }
+
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+        encode_bytes(self, area)
+    }
+
+    fn decode<'a>(data: &'a [u8], spec: FieldSpec) -> Result<(Arc<[u8]>, &'a [u8]), ProtoError> {
+        let (bytes, rest) = decode_bytes(data, spec)?;
+        Ok((Arc::from(bytes), rest))
+    }
+}
+
+impl Wire for String {
+    const WIRE_TYPE: WireType = WireType::Utf8;
+
+    fn encode_len(&self) -> usize {
+        size_of::<u32>() + self.len()
+    }
+
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+        encode_bytes(self.as_bytes(), area)
+    }
+
+    fn decode<'a>(data: &'a [u8], spec: FieldSpec) -> Result<(String, &'a [u8]), ProtoError> {
+        let (bytes, rest) = decode_bytes(data, spec)?;
+        match std::str::from_utf8(bytes) {
+            Ok(string) => Ok((string.to_owned(), rest)),
+            Err(..) => Err(ProtoError::InvalidUtf8 { message: spec.message, field: spec.field, }),
+        }
+    }
+}
+
+impl<T> Wire for Vec<T> where T: Wire {
+    const WIRE_TYPE: WireType = WireType::List(&T::WIRE_TYPE);
+
+    fn encode_len(&self) -> usize {
+        size_of::<u32>() + self.iter().map(Wire::encode_len).sum::<usize>()
+    }
+
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+        let mut area = (self.len() as u32).encode(area);
+        for item in self {
+            area = item.encode(area);
+        }
+        area
+    }
+
+    fn decode<'a>(data: &'a [u8], spec: FieldSpec) -> Result<(Vec<T>, &'a [u8]), ProtoError> {
+        let (count, mut buf) = u32::decode(data, FieldSpec::new(spec.message, spec.count))?;
+        let mut items = Vec::new();
+        for _ in 0 .. count {
+            let (item, rest) = T::decode(buf, spec)?;
+            items.push(item);
+            buf = rest;
+        }
+        Ok((items, buf))
+    }
+}
+
+impl Wire for ServerErrorCode {
+    const WIRE_TYPE: WireType = WireType::U16;
+
+    fn encode_len(&self) -> usize {
+        size_of::<u16>()
+    }
+
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+        self.code().encode(area)
+    }
+
+    fn decode<'a>(data: &'a [u8], spec: FieldSpec) -> Result<(ServerErrorCode, &'a [u8]), ProtoError> {
+        let (code, rest) = u16::decode(data, spec)?;
+        Ok((ServerErrorCode::from_code(code), rest))
+    }
+}
+
+/// Nested `ProtoError` is a self describing message, so `spec` is not used.
+impl Wire for ProtoError {
+    const WIRE_TYPE: WireType = WireType::ProtoError;
+
+    fn encode_len(&self) -> usize {
+        ProtoError::encode_len(self)
+    }
+
+    fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+        ProtoError::encode(self, area)
+    }
+
+    fn decode<'a>(data: &'a [u8], _spec: FieldSpec) -> Result<(ProtoError, &'a [u8]), ProtoError> {
+        ProtoError::decode(data)
+    }
+}
+
+/// A frame which is not exactly one message.
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Proto(ProtoError),
+    /// Count of bytes left after a decoded message.
+    TrailingBytes(usize),
+}
+
+impl DecodeError {
+    /// Answer to an undecodable request. There is no `ProtoError` for
+    /// trailing bytes, so those are reported as an `Internal` server error.
+    pub fn into_rep(self) -> GlobalRep {
+        match self {
+            DecodeError::Proto(err) =>
+                GlobalRep::Error(err),
+            err @ DecodeError::TrailingBytes(..) =>
+                GlobalRep::ServerError { code: ServerErrorCode::Internal, message: format!("invalid request: {}", err), },
+        }
+    }
+}
+
+impl From<ProtoError> for DecodeError {
+    fn from(err: ProtoError) -> DecodeError {
+        DecodeError::Proto(err)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &DecodeError::Proto(ref err) => err.fmt(f),
+            &DecodeError::TrailingBytes(len) => write!(f, "{} trailing bytes after the message", len),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            &DecodeError::Proto(ref err) => Some(err),
+            &DecodeError::TrailingBytes(..) => None,
+        }
+    }
+}
+
+fn whole<T>(decoded: (T, &[u8])) -> Result<T, DecodeError> {
+    match decoded {
+        (message, []) => Ok(message),
+        (_, rest) => Err(DecodeError::TrailingBytes(rest.len())),
+    }
+}
+
+/// Decodes a frame holding a single request.
+pub fn decode_req(frame: &[u8]) -> Result<GlobalReq, DecodeError> {
+    whole(GlobalReq::decode(frame)?)
+}
+
+/// Decodes a frame holding a single reply.
+pub fn decode_rep(frame: &[u8]) -> Result<GlobalRep, DecodeError> {
+    whole(GlobalRep::decode(frame)?)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use super::super::{AddMode, MessageKind, FieldKind, ProtoError, ServerErrorCode, GlobalReq, GlobalRep};
+    use super::{FieldSpec, Wire, DecodeError, decode_req, decode_rep};
+
+    fn spec() -> FieldSpec {
+        FieldSpec {
+            message: MessageKind::GlobalRepInfoGot,
+            field: FieldKind::Feature,
+            len: FieldKind::FeatureLen,
+            count: FieldKind::FeaturesCount,
+        }
+    }
+
+    fn encode<T>(value: &T) -> Vec<u8> where T: Wire {
+        let mut area = vec![0; value.encode_len()];
+        assert!(value.encode(&mut area).is_empty());
+        area
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let value = vec!["a".to_string(), "bcd".to_string()];
+        let area = encode(&value);
+        assert_eq!(area, [0, 0, 0, 2, 0, 0, 0, 1, b'a', 0, 0, 0, 3, b'b', b'c', b'd']);
+        assert_eq!(Vec::<String>::decode(&area, spec()), Ok((value, &[][..])));
+    }
+
+    #[test]
+    fn strings_not_enough_data() {
+        let area = encode(&vec!["abc".to_string()]);
+        assert_eq!(Vec::<String>::decode(&area[.. 2], spec()), Err(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalRepInfoGot, field: FieldKind::FeaturesCount, required: 4, given: 2,
+        }));
+        assert_eq!(Vec::<String>::decode(&area[.. 6], spec()), Err(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalRepInfoGot, field: FieldKind::FeatureLen, required: 4, given: 2,
+        }));
+        assert_eq!(Vec::<String>::decode(&area[.. 9], spec()), Err(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalRepInfoGot, field: FieldKind::Feature, required: 3, given: 1,
+        }));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let value: Arc<[u8]> = Arc::from(&b"key"[..]);
+        let area = encode(&value);
+        assert_eq!(area, [0, 0, 0, 3, b'k', b'e', b'y']);
+        assert_eq!(Arc::<[u8]>::decode(&area, spec()), Ok((value, &[][..])));
+    }
+
+    #[test]
+    fn derived_tag_enum() {
+        let spec = FieldSpec::new(MessageKind::GlobalReqAdd, FieldKind::Mode);
+        assert_eq!(encode(&AddMode::Tail), [2]);
+        assert_eq!(AddMode::decode(&[1, 7], spec), Ok((AddMode::Head, &[7][..])));
+        assert_eq!(AddMode::decode(&[3], spec), Err(ProtoError::InvalidTag {
+            message: MessageKind::GlobalReqAdd, field: FieldKind::Mode, tag: 3,
+        }));
+        assert_eq!(AddMode::decode(&[], spec), Err(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalReqAdd, field: FieldKind::Mode, required: 1, given: 0,
+        }));
+    }
+
+    #[test]
+    fn whole_frames() {
+        assert_eq!(decode_req(&[11]), Ok(GlobalReq::Ping));
+        assert_eq!(decode_req(&[11, 0, 0]), Err(DecodeError::TrailingBytes(2)));
+        assert_eq!(decode_rep(&[1, 0, 0, 0, 7]), Ok(GlobalRep::Counted(7)));
+        assert_eq!(decode_rep(&[1, 0, 0, 0, 7, 1]), Err(DecodeError::TrailingBytes(1)));
+        assert!(matches!(DecodeError::TrailingBytes(1).into_rep(), GlobalRep::ServerError { code: ServerErrorCode::Internal, .. }));
+        assert_eq!(decode_rep(&[1, 0]), Err(DecodeError::Proto(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalRepCounted, field: FieldKind::Count, required: 4, given: 1,
+        })));
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt;
+
+use super::{
+    GlobalReq,
+    GlobalRep,
+};
+
+/// How byte strings are shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BytesStyle {
+    /// Quoted UTF-8 if the bytes are valid printable UTF-8, hex otherwise.
+    Auto,
+    Hex,
+}
+
+/// Configurable pretty printer for protocol messages which keeps long keys
+/// and values from flooding logs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MessageFormatter {
+    max_len: Option<usize>,
+    style: BytesStyle,
+    redact_values: bool,
+}
+
+impl Default for MessageFormatter {
+    fn default() -> MessageFormatter {
+        MessageFormatter::new()
+    }
+}
+
+impl MessageFormatter {
+    pub const DEFAULT_MAX_LEN: usize = 32;
+
+    pub fn new() -> MessageFormatter {
+        MessageFormatter {
+            max_len: Some(MessageFormatter::DEFAULT_MAX_LEN),
+            style: BytesStyle::Auto,
+            redact_values: false,
+        }
+    }
+
+    /// Byte strings longer than `max_len` bytes are truncated, `None` shows them whole.
+    pub fn with_max_len(mut self, max_len: Option<usize>) -> MessageFormatter {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn with_style(mut self, style: BytesStyle) -> MessageFormatter {
+        self.style = style;
+        self
+    }
+
+    /// Hides values and nonces, showing only their length. Keys are kept.
+    /// `Auth` payloads carry credentials and are always hidden.
+    pub fn with_redact_values(mut self, redact_values: bool) -> MessageFormatter {
+        self.redact_values = redact_values;
+        self
+    }
+
+    pub fn req<'a>(&'a self, req: &'a GlobalReq) -> FormattedReq<'a> {
+        FormattedReq { formatter: self, req: req, }
+    }
+
+    pub fn rep<'a>(&'a self, rep: &'a GlobalRep) -> FormattedRep<'a> {
+        FormattedRep { formatter: self, rep: rep, }
+    }
+
+    fn key<'a>(&'a self, bytes: &'a [u8]) -> Preview<'a> {
+        Preview { formatter: self, bytes: bytes, redact: false, }
+    }
+
+    fn value<'a>(&'a self, bytes: &'a [u8]) -> Preview<'a> {
+        Preview { formatter: self, bytes: bytes, redact: self.redact_values, }
+    }
+
+    fn secret<'a>(&'a self, bytes: &'a [u8]) -> Preview<'a> {
+        Preview { formatter: self, bytes: bytes, redact: true, }
+    }
+}
+
+struct Preview<'a> {
+    formatter: &'a MessageFormatter,
+    bytes: &'a [u8],
+    redact: bool,
+}
+
+impl<'a> fmt::Debug for Preview<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.redact {
+            return write!(f, "<redacted {} bytes>", self.bytes.len());
+        }
+        let shown = match self.formatter.max_len {
+            Some(max_len) if max_len < self.bytes.len() => max_len,
+            _ => self.bytes.len(),
+        };
+        let utf8 = match self.formatter.style {
+            BytesStyle::Auto => printable_utf8(&self.bytes[.. shown], shown < self.bytes.len()),
+            BytesStyle::Hex => None,
+        };
+        match utf8 {
+            Some(string) => write!(f, "\"{}\"", string.escape_debug())?,
+            None => {
+                write!(f, "0x")?;
+                for byte in &self.bytes[.. shown] {
+                    write!(f, "{:02x}", byte)?;
+                }
+            },
+        }
+        if shown < self.bytes.len() {
+            write!(f, "... ({} bytes)", self.bytes.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Valid UTF-8 without control characters. If `truncated`, a multibyte
+/// character cut at the end is dropped.
+fn printable_utf8(bytes: &[u8], truncated: bool) -> Option<&str> {
+    let string = match std::str::from_utf8(bytes) {
+        Ok(string) => string,
+        Err(err) if truncated && err.error_len().is_none() => std::str::from_utf8(&bytes[.. err.valid_up_to()]).unwrap(),
+        Err(..) => return None,
+    };
+    if string.chars().any(char::is_control) {
+        None
+    } else {
+        Some(string)
+    }
+}
+
+struct AsDisplay<T>(T);
+
+impl<T> fmt::Debug for AsDisplay<T> where T: fmt::Display {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+pub struct FormattedReq<'a> {
+    formatter: &'a MessageFormatter,
+    req: &'a GlobalReq,
+}
+
+impl<'a> fmt::Display for FormattedReq<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fmt = self.formatter;
+        match self.req {
+            &GlobalReq::Ping => write!(f, "Ping"),
+            &GlobalReq::Count => write!(f, "Count"),
+            &GlobalReq::Add { ref key, ref value, ref mode, } => f.debug_struct("Add")
+                .field("key", &fmt.key(key))
+                .field("value", &fmt.value(value))
+                .field("mode", mode)
+                .finish(),
+            &GlobalReq::Update(ref key, ref value) => f.debug_tuple("Update")
+                .field(&fmt.key(key))
+                .field(&fmt.value(value))
+                .finish(),
+            &GlobalReq::Lookup(ref key) => f.debug_tuple("Lookup").field(&fmt.key(key)).finish(),
+            &GlobalReq::Remove(ref key) => f.debug_tuple("Remove").field(&fmt.key(key)).finish(),
+            &GlobalReq::Lend { timeout, ref mode, } => f.debug_struct("Lend")
+                .field("timeout", &timeout)
+                .field("mode", mode)
+                .finish(),
+            &GlobalReq::Repay { lend_key, ref key, ref value, ref status, } => f.debug_struct("Repay")
+                .field("lend_key", &lend_key)
+                .field("key", &fmt.key(key))
+                .field("value", &fmt.value(value))
+                .field("status", status)
+                .finish(),
+            &GlobalReq::Heartbeat { lend_key, ref key, timeout, } => f.debug_struct("Heartbeat")
+                .field("lend_key", &lend_key)
+                .field("key", &fmt.key(key))
+                .field("timeout", &timeout)
+                .finish(),
+            &GlobalReq::Stats => write!(f, "Stats"),
+            &GlobalReq::Flush => write!(f, "Flush"),
+            &GlobalReq::Terminate => write!(f, "Terminate"),
+            &GlobalReq::Info => write!(f, "Info"),
+            &GlobalReq::Auth { ref mechanism, ref payload, } => f.debug_struct("Auth")
+                .field("mechanism", mechanism)
+                .field("payload", &fmt.secret(payload))
+                .finish(),
+        }
+    }
+}
+
+pub struct FormattedRep<'a> {
+    formatter: &'a MessageFormatter,
+    rep: &'a GlobalRep,
+}
+
+impl<'a> fmt::Display for FormattedRep<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fmt = self.formatter;
+        match self.rep {
+            &GlobalRep::Pong => write!(f, "Pong"),
+            &GlobalRep::Counted(count) => f.debug_tuple("Counted").field(&count).finish(),
+            &GlobalRep::Added => write!(f, "Added"),
+            &GlobalRep::Kept => write!(f, "Kept"),
+            &GlobalRep::Updated => write!(f, "Updated"),
+            &GlobalRep::NotFound => write!(f, "NotFound"),
+            &GlobalRep::ValueFound(ref value) => f.debug_tuple("ValueFound").field(&fmt.value(value)).finish(),
+            &GlobalRep::ValueNotFound => write!(f, "ValueNotFound"),
+            &GlobalRep::Removed => write!(f, "Removed"),
+            &GlobalRep::NotRemoved => write!(f, "NotRemoved"),
+            &GlobalRep::Lent { lend_key, ref key, ref value, } => f.debug_struct("Lent")
+                .field("lend_key", &lend_key)
+                .field("key", &fmt.key(key))
+                .field("value", &fmt.value(value))
+                .finish(),
+            &GlobalRep::QueueEmpty => write!(f, "QueueEmpty"),
+            &GlobalRep::Repaid => write!(f, "Repaid"),
+            &GlobalRep::Heartbeaten => write!(f, "Heartbeaten"),
+            &GlobalRep::Skipped => write!(f, "Skipped"),
+            &GlobalRep::StatsGot { .. } => write!(f, "{:?}", self.rep),
+            &GlobalRep::Flushed => write!(f, "Flushed"),
+            &GlobalRep::Terminated => write!(f, "Terminated"),
+            &GlobalRep::Error(ref error) => f.debug_tuple("Error").field(&AsDisplay(error)).finish(),
+            &GlobalRep::InfoGot { .. } => write!(f, "{:?}", self.rep),
+            &GlobalRep::AuthOk => write!(f, "AuthOk"),
+            &GlobalRep::AuthFailed => write!(f, "AuthFailed"),
+            &GlobalRep::AuthChallenge(ref nonce) => f.debug_tuple("AuthChallenge").field(&fmt.value(nonce)).finish(),
+            &GlobalRep::Unauthenticated => write!(f, "Unauthenticated"),
+            &GlobalRep::Forbidden => write!(f, "Forbidden"),
+            &GlobalRep::ServerError { ref code, ref message, } => f.debug_struct("ServerError")
+                .field("code", code)
+                .field("message", message)
+                .finish(),
+        }
+    }
+}
+
+impl fmt::Display for GlobalReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        MessageFormatter::new().req(self).fmt(f)
+    }
+}
+
+impl fmt::Display for GlobalRep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        MessageFormatter::new().rep(self).fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{AddMode, AuthMechanism, GlobalReq, GlobalRep, ProtoError};
+    use super::super::test_support::bytes;
+    use super::{BytesStyle, MessageFormatter};
+
+    #[test]
+    fn display() {
+        let req = GlobalReq::Add { key: bytes(b"cat"), value: bytes(&[0, 1, 0xfe]), mode: AddMode::Tail, };
+        assert_eq!(req.to_string(), r#"Add { key: "cat", value: 0x0001fe, mode: Tail }"#);
+        assert_eq!(GlobalRep::Pong.to_string(), "Pong");
+        assert_eq!(GlobalRep::Error(ProtoError::DbQueueOutOfSync(bytes(b"k"))).to_string(),
+                   r#"Error(kv database and queue are out of sync for key "k")"#);
+    }
+
+    #[test]
+    fn truncation() {
+        let rep = GlobalRep::Lent { lend_key: 3, key: bytes(b"cat"), value: bytes("котик".as_bytes()), };
+        let formatter = MessageFormatter::new().with_max_len(Some(3));
+        assert_eq!(formatter.rep(&rep).to_string(), r#"Lent { lend_key: 3, key: "cat", value: "к"... (10 bytes) }"#);
+        let formatter = formatter.with_style(BytesStyle::Hex);
+        assert_eq!(formatter.rep(&rep).to_string(), "Lent { lend_key: 3, key: 0x636174, value: 0xd0bad0... (10 bytes) }");
+        let long = GlobalRep::ValueFound(bytes(&[b'a'; 100]));
+        assert_eq!(long.to_string(), format!("ValueFound(\"{}\"... (100 bytes))", "a".repeat(32)));
+        let formatter = MessageFormatter::new().with_max_len(None);
+        assert_eq!(formatter.rep(&long).to_string(), format!("ValueFound(\"{}\")", "a".repeat(100)));
+    }
+
+    #[test]
+    fn incomplete_utf8_not_truncated() {
+        assert_eq!(GlobalRep::ValueFound(bytes(b"ab\xe2")).to_string(), "ValueFound(0x6162e2)");
+        let formatter = MessageFormatter::new().with_max_len(Some(3));
+        assert_eq!(formatter.rep(&GlobalRep::ValueFound(bytes(b"ab\xe2\x82\xac"))).to_string(), r#"ValueFound("ab"... (5 bytes))"#);
+    }
+
+    #[test]
+    fn redaction() {
+        let req = GlobalReq::Repay { lend_key: 1, key: bytes(b"cat"), value: bytes(b"secret"), status: super::super::RepayStatus::Drop, };
+        let formatter = MessageFormatter::new().with_redact_values(true);
+        assert_eq!(formatter.req(&req).to_string(), r#"Repay { lend_key: 1, key: "cat", value: <redacted 6 bytes>, status: Drop }"#);
+    }
+    #[test]
+    fn auth_payload_always_redacted() {
+        let req = GlobalReq::Auth { mechanism: AuthMechanism::Token, payload: bytes(b"s3cret-token"), };
+        let formatters = [
+            MessageFormatter::new(),
+            MessageFormatter::new().with_max_len(None),
+            MessageFormatter::new().with_style(BytesStyle::Hex),
+        ];
+        for formatter in &formatters {
+            let out = formatter.req(&req).to_string();
+            assert_eq!(out, "Auth { mechanism: Token, payload: <redacted 12 bytes> }");
+        }
+        assert!(!req.to_string().contains("s3cret"));
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use serde_json::Value as Json;
+
+use super::{
+    PROTO_VERSION,
+    ServerErrorCode,
+    RepayStatus,
+    LendMode,
+    AddMode,
+    AuthMechanism,
+    GlobalReq,
+    GlobalRep,
+    ProtoError,
+    MessageKind,
+    FieldKind,
+};
+
+const CORPUS: &[(u32, &str)] = &[
+    (4, include_str!("../vectors/v4.json")),
+];
+
+fn hex(s: &str) -> Vec<u8> {
+    assert!(s.len().is_multiple_of(2), "odd hex string length: {:?}", s);
+    (0 .. s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i .. i + 2], 16).unwrap()).collect()
+}
+
+struct Fields<'a>(&'a Json);
+
+impl<'a> Fields<'a> {
+    fn get(&self, name: &str) -> &'a Json {
+        self.0.get(name).unwrap_or_else(|| panic!("missing field {:?} in {}", name, self.0))
+    }
+
+    fn bytes(&self, name: &str) -> Arc<[u8]> {
+        Arc::from(hex(self.get(name).as_str().unwrap()))
+    }
+
+    fn string(&self, name: &str) -> String {
+        self.get(name).as_str().unwrap().to_string()
+    }
+
+    fn u64(&self, name: &str) -> u64 {
+        self.get(name).as_u64().unwrap()
+    }
+
+    fn usize(&self, name: &str) -> usize {
+        self.u64(name) as usize
+    }
+
+    fn name(&self, name: &str) -> &'a str {
+        self.get(name).as_str().unwrap()
+    }
+
+    fn message_kind(&self, name: &str) -> MessageKind {
+        let kind = self.name(name);
+        *MessageKind::ALL.iter().find(|k| k.name() == kind).unwrap_or_else(|| panic!("unknown message kind {:?}", kind))
+    }
+
+    fn field_kind(&self, name: &str) -> FieldKind {
+        let kind = self.name(name);
+        *FieldKind::ALL.iter().find(|k| k.name() == kind).unwrap_or_else(|| panic!("unknown field kind {:?}", kind))
+    }
+}
+
+fn split(value: &Json) -> (&str, Fields<'_>) {
+    (value["variant"].as_str().unwrap(), Fields(&value["fields"]))
+}
+
+fn add_mode(name: &str) -> AddMode {
+    match name { "Head" => AddMode::Head, "Tail" => AddMode::Tail, other => panic!("unknown AddMode {:?}", other), }
+}
+
+fn lend_mode(name: &str) -> LendMode {
+    match name { "Block" => LendMode::Block, "Poll" => LendMode::Poll, other => panic!("unknown LendMode {:?}", other), }
+}
+
+fn repay_status(name: &str) -> RepayStatus {
+    match name {
+        "Penalty" => RepayStatus::Penalty,
+        "Reward" => RepayStatus::Reward,
+        "Front" => RepayStatus::Front,
+        "Drop" => RepayStatus::Drop,
+        other => panic!("unknown RepayStatus {:?}", other),
+    }
+}
+
+fn auth_mechanism(name: &str) -> AuthMechanism {
+    match name {
+        "Token" => AuthMechanism::Token,
+        "HmacSha256" => AuthMechanism::HmacSha256,
+        other => panic!("unknown AuthMechanism {:?}", other),
+    }
+}
+
+fn req(value: &Json) -> GlobalReq {
+    match split(value) {
+        ("Ping", _) => GlobalReq::Ping,
+        ("Count", _) => GlobalReq::Count,
+        ("Add", f) => GlobalReq::Add { key: f.bytes("key"), value: f.bytes("value"), mode: add_mode(f.name("mode")), },
+        ("Update", f) => GlobalReq::Update(f.bytes("key"), f.bytes("value")),
+        ("Lookup", f) => GlobalReq::Lookup(f.bytes("key")),
+        ("Remove", f) => GlobalReq::Remove(f.bytes("key")),
+        ("Lend", f) => GlobalReq::Lend { timeout: f.u64("timeout"), mode: lend_mode(f.name("mode")), },
+        ("Repay", f) => GlobalReq::Repay {
+            lend_key: f.u64("lend_key"),
+            key: f.bytes("key"),
+            value: f.bytes("value"),
+            status: repay_status(f.name("status")),
+        },
+        ("Heartbeat", f) => GlobalReq::Heartbeat { lend_key: f.u64("lend_key"), key: f.bytes("key"), timeout: f.u64("timeout"), },
+        ("Stats", _) => GlobalReq::Stats,
+        ("Flush", _) => GlobalReq::Flush,
+        ("Terminate", _) => GlobalReq::Terminate,
+        ("Info", _) => GlobalReq::Info,
+        ("Auth", f) => GlobalReq::Auth { mechanism: auth_mechanism(f.name("mechanism")), payload: f.bytes("payload"), },
+        (other, _) => panic!("unknown GlobalReq variant {:?}", other),
+    }
+}
+
+fn rep(value: &Json) -> GlobalRep {
+    match split(value) {
+        ("Pong", _) => GlobalRep::Pong,
+        ("Counted", f) => GlobalRep::Counted(f.usize("count")),
+        ("Added", _) => GlobalRep::Added,
+        ("Kept", _) => GlobalRep::Kept,
+        ("Updated", _) => GlobalRep::Updated,
+        ("NotFound", _) => GlobalRep::NotFound,
+        ("ValueFound", f) => GlobalRep::ValueFound(f.bytes("value")),
+        ("ValueNotFound", _) => GlobalRep::ValueNotFound,
+        ("Removed", _) => GlobalRep::Removed,
+        ("NotRemoved", _) => GlobalRep::NotRemoved,
+        ("Lent", f) => GlobalRep::Lent { lend_key: f.u64("lend_key"), key: f.bytes("key"), value: f.bytes("value"), },
+        ("QueueEmpty", _) => GlobalRep::QueueEmpty,
+        ("Repaid", _) => GlobalRep::Repaid,
+        ("Heartbeaten", _) => GlobalRep::Heartbeaten,
+        ("Skipped", _) => GlobalRep::Skipped,
+        ("StatsGot", f) => GlobalRep::StatsGot {
+            ping: f.usize("ping"),
+            count: f.usize("count"),
+            add: f.usize("add"),
+            update: f.usize("update"),
+            lookup: f.usize("lookup"),
+            remove: f.usize("remove"),
+            lend: f.usize("lend"),
+            repay: f.usize("repay"),
+            heartbeat: f.usize("heartbeat"),
+            stats: f.usize("stats"),
+        },
+        ("Flushed", _) => GlobalRep::Flushed,
+        ("Terminated", _) => GlobalRep::Terminated,
+        ("Error", f) => GlobalRep::Error(proto_error(f.get("error"))),
+        ("InfoGot", f) => GlobalRep::InfoGot {
+            server_version: f.string("server_version"),
+            proto_version: f.u64("proto_version") as u32,
+            uptime_ms: f.u64("uptime_ms"),
+            db_path: f.string("db_path"),
+            max_key_len: f.usize("max_key_len"),
+            max_value_len: f.usize("max_value_len"),
+            features: f.get("features").as_array().unwrap().iter().map(|s| s.as_str().unwrap().to_string()).collect(),
+        },
+        ("AuthOk", _) => GlobalRep::AuthOk,
+        ("AuthFailed", _) => GlobalRep::AuthFailed,
+        ("AuthChallenge", f) => GlobalRep::AuthChallenge(f.bytes("nonce")),
+        ("Unauthenticated", _) => GlobalRep::Unauthenticated,
+        ("Forbidden", _) => GlobalRep::Forbidden,
+        ("ServerError", f) => GlobalRep::ServerError { code: ServerErrorCode::from_code(f.u64("code") as u16), message: f.string("message"), },
+        (other, _) => panic!("unknown GlobalRep variant {:?}", other),
+    }
+}
+
+fn proto_error(value: &Json) -> ProtoError {
+    match split(value) {
+        ("NotEnoughData", f) => ProtoError::NotEnoughData {
+            message: f.message_kind("message"),
+            field: f.field_kind("field"),
+            required: f.usize("required"),
+            given: f.usize("given"),
+        },
+        ("InvalidTag", f) => ProtoError::InvalidTag { message: f.message_kind("message"), field: f.field_kind("field"), tag: f.u64("tag") as u8, },
+        ("InvalidUtf8", f) => ProtoError::InvalidUtf8 { message: f.message_kind("message"), field: f.field_kind("field"), },
+        ("DbQueueOutOfSync", f) => ProtoError::DbQueueOutOfSync(f.bytes("key")),
+        (other, _) => panic!("unknown ProtoError variant {:?}", other),
+    }
+}
+
+macro_rules! check_vector {
+    ($ty:ident, $from_json:ident, $name:expr, $frame:expr, $vector:expr) => ({
+        let (name, frame, vector) = ($name, $frame, $vector);
+        if let Some(value) = vector.get("value") {
+            let value = $from_json(value);
+            let mut area = vec![0; value.encode_len()];
+            assert!(value.encode(&mut area).is_empty(), "{}: encode_len is too large", name);
+            assert_eq!(area, frame, "{}: encoded frame differs", name);
+            assert_eq!($ty::decode(&frame), Ok((value, &[][..])), "{}: decoded value differs", name);
+        } else {
+            let error = proto_error(&vector["error"]);
+            assert_eq!($ty::decode(&frame), Err(error), "{}: decoding error differs", name);
+        }
+    })
+}
+
+fn check_corpus(corpus: &str) -> Json {
+    let corpus: Json = serde_json::from_str(corpus).unwrap();
+    for vector in corpus["vectors"].as_array().unwrap() {
+        let name = vector["name"].as_str().unwrap();
+        let frame = hex(vector["frame"].as_str().unwrap());
+        match vector["message"].as_str().unwrap() {
+            "GlobalReq" => check_vector!(GlobalReq, req, name, frame, vector),
+            "GlobalRep" => check_vector!(GlobalRep, rep, name, frame, vector),
+            "ProtoError" => check_vector!(ProtoError, proto_error, name, frame, vector),
+            other => panic!("{}: unknown message {:?}", name, other),
+        }
+    }
+    corpus
+}
+
+#[test]
+fn corpus_vectors() {
+    for &(version, corpus) in CORPUS {
+        let corpus = check_corpus(corpus);
+        assert_eq!(corpus["proto_version"].as_u64(), Some(version as u64));
+    }
+}
+
+#[test]
+fn corpus_covers_current_version() {
+    let &(version, corpus) = CORPUS.last().unwrap();
+    assert_eq!(version, PROTO_VERSION);
+    let corpus: Json = serde_json::from_str(corpus).unwrap();
+    let vectors = corpus["vectors"].as_array().unwrap();
+    let covered = |message: &str, variant: &str| vectors.iter()
+        .any(|vector| vector["message"] == message && vector["value"]["variant"] == variant);
+    for schema in super::schema::MESSAGES {
+        for variant in schema.variants {
+            assert!(covered(schema.name, variant.name), "no golden vector for {}::{}", schema.name, variant.name);
+        }
+    }
+    for variant in &["NotEnoughData", "InvalidTag", "InvalidUtf8", "DbQueueOutOfSync"] {
+        assert!(covered("ProtoError", variant), "no golden vector for ProtoError::{}", variant);
+    }
+    for tag in super::schema::proto_error_tags() {
+        assert!(vectors.iter().any(|vector| vector["message"] == "ProtoError" && vector.get("value").is_some() &&
+                                   hex(vector["frame"].as_str().unwrap())[0] == tag.tag),
+                "no golden vector for ProtoError tag {}", tag.tag);
+    }
+}
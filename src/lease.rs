@@ -0,0 +1,494 @@
+//! Lent task handles which make the lend key travel with the task.
+//!
+//! A lease is consumed by `heartbeat`, `repay` or `drop_task`, so a task
+//! can not be repaid twice. A failed request hands the lease back in
+//! `LeaseError` unless the connection is out of sync. A lease dropped without
+//! being repaid warns about it: its task stays lent until the deadline and
+//! then returns to the front of the queue. The warning goes through `log`
+//! with the `log` feature, to stderr without it, or to the function set with
+//! `set_drop_warning`.
+//!
+//! Deadlines follow a `clock::Clock`, `SystemClock` unless the lease was taken
+//! with `lease_with_clock`.
+
+use std::{
+    fmt,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use super::{
+    Key,
+    Value,
+    LendMode,
+    RepayStatus,
+    GlobalReq,
+};
+use super::client::{
+    self,
+    ClientError,
+    LentTask,
+    SyncClient,
+    Transport,
+};
+use super::clock::{
+    Clock,
+    SystemClock,
+};
+
+struct Held {
+    lend_key: u64,
+    key: Key,
+    value: Value,
+    clock: Arc<dyn Clock>,
+    deadline: Instant,
+    settled: bool,
+}
+
+impl Held {
+    fn new(task: LentTask, clock: Arc<dyn Clock>, sent_at: Instant, timeout_ms: u64) -> Held {
+        Held {
+            lend_key: task.lend_key,
+            key: task.key,
+            value: task.value,
+            clock: clock,
+            deadline: sent_at + Duration::from_millis(timeout_ms),
+            settled: false,
+        }
+    }
+
+    fn heartbeat_req(&self, timeout_ms: u64) -> GlobalReq {
+        GlobalReq::Heartbeat { lend_key: self.lend_key, key: self.key.clone(), timeout: timeout_ms, }
+    }
+
+    fn repay_req(&self, value: Value, status: RepayStatus) -> GlobalReq {
+        GlobalReq::Repay { lend_key: self.lend_key, key: self.key.clone(), value: value, status: status, }
+    }
+
+    /// Same task with the deadline moved by a successful heartbeat.
+    fn extended(&mut self, sent_at: Instant, timeout_ms: u64) -> Held {
+        self.settled = true;
+        Held {
+            lend_key: self.lend_key,
+            key: self.key.clone(),
+            value: self.value.clone(),
+            clock: self.clock.clone(),
+            deadline: sent_at + Duration::from_millis(timeout_ms),
+            settled: false,
+        }
+    }
+}
+
+static DROP_WARNING: Mutex<Option<fn(&str)>> = Mutex::new(None);
+
+/// Sends the warnings about leases dropped without repay to `warn` instead
+/// of `log` or stderr, `None` restores the default.
+pub fn set_drop_warning(warn: Option<fn(&str)>) {
+    *DROP_WARNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = warn;
+}
+
+fn drop_warning(message: &str) {
+    let warn = *DROP_WARNING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match warn {
+        Some(warn) => warn(message),
+        #[cfg(feature = "log")]
+        None => log::warn!("{}", message),
+        #[cfg(not(feature = "log"))]
+        None => eprintln!("warning: {}", message),
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        if !self.settled {
+            drop_warning(&format!(
+                "lease {} for key {:?} dropped without repay, the task stays lent until its deadline in {:?}",
+                self.lend_key,
+                String::from_utf8_lossy(&self.key),
+                self.deadline.saturating_duration_since(self.clock.now()),
+            ));
+        }
+    }
+}
+
+/// Failed lease request, see `ClientError`.
+pub struct LeaseError<L> {
+    pub error: ClientError,
+    /// The lease for another try, `None` if the connection is out of sync
+    /// (`ClientError::is_desync`).
+    pub lease: Option<Box<L>>,
+}
+
+impl<L> LeaseError<L> {
+    fn new(error: ClientError, lease: L) -> LeaseError<L> {
+        let lease = if error.is_desync() { None } else { Some(Box::new(lease)) };
+        LeaseError { error: error, lease: lease, }
+    }
+}
+
+impl<L> From<LeaseError<L>> for ClientError {
+    fn from(err: LeaseError<L>) -> ClientError {
+        err.error
+    }
+}
+
+impl<L> fmt::Debug for LeaseError<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LeaseError").field("error", &self.error).field("lease_returned", &self.lease.is_some()).finish()
+    }
+}
+
+impl<L> fmt::Display for LeaseError<L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl<L> std::error::Error for LeaseError<L> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+macro_rules! lease_accessors {
+    () => {
+        pub fn lend_key(&self) -> u64 {
+            self.held.lend_key
+        }
+
+        pub fn key(&self) -> &Key {
+            &self.held.key
+        }
+
+        /// Value of the task at the time it was lent.
+        pub fn value(&self) -> &Value {
+            &self.held.value
+        }
+
+        /// When the server puts the task back to the queue unless it is
+        /// repaid or heartbeaten. Measured from the moment the request was sent,
+        /// so the server deadline is a bit later.
+        pub fn deadline(&self) -> Instant {
+            self.held.deadline
+        }
+
+        pub fn is_expired(&self) -> bool {
+            self.held.clock.now() >= self.held.deadline
+        }
+    };
+}
+
+/// Task lent by `SyncClient::lease`, borrowing the client until it is settled.
+pub struct Lease<'a, S> where S: Transport {
+    client: &'a mut SyncClient<S>,
+    held: Held,
+}
+
+impl<'a, S> Lease<'a, S> where S: Transport {
+    lease_accessors!();
+
+    /// Extends the lease to `timeout_ms` from now. Returns `None` if the task
+    /// is not lent anymore (`Skipped`).
+    pub fn heartbeat(mut self, timeout_ms: u64) -> Result<Option<Lease<'a, S>>, LeaseError<Lease<'a, S>>> {
+        let sent_at = self.held.clock.now();
+        match self.client.request(&self.held.heartbeat_req(timeout_ms)).and_then(client::heartbeaten) {
+            Ok(true) => {
+                let held = self.held.extended(sent_at, timeout_ms);
+                Ok(Some(Lease { client: self.client, held: held, }))
+            },
+            Ok(false) => {
+                self.held.settled = true;
+                Ok(None)
+            },
+            Err(err) =>
+                Err(LeaseError::new(err, self)),
+        }
+    }
+
+    /// Returns `false` if the task is not lent anymore (e.g. its timeout has expired).
+    pub fn repay(mut self, value: Value, status: RepayStatus) -> Result<bool, LeaseError<Lease<'a, S>>> {
+        match self.client.request(&self.held.repay_req(value, status)).and_then(client::repaid) {
+            Ok(repaid) => {
+                self.held.settled = true;
+                Ok(repaid)
+            },
+            Err(err) =>
+                Err(LeaseError::new(err, self)),
+        }
+    }
+
+    /// Removes the task from the queue keeping its value in the database.
+    pub fn drop_task(self) -> Result<bool, LeaseError<Lease<'a, S>>> {
+        let value = self.held.value.clone();
+        self.repay(value, RepayStatus::Drop)
+    }
+}
+
+impl<S> SyncClient<S> where S: Transport {
+    /// Same as `lend` returning the task as a `Lease`.
+    pub fn lease(&mut self, timeout_ms: u64, mode: LendMode) -> Result<Option<Lease<'_, S>>, ClientError> {
+        self.lease_with_clock(timeout_ms, mode, Arc::new(SystemClock))
+    }
+
+    /// Same as `lease` with the deadline following `clock`.
+    pub fn lease_with_clock(&mut self, timeout_ms: u64, mode: LendMode, clock: Arc<dyn Clock>) -> Result<Option<Lease<'_, S>>, ClientError> {
+        let sent_at = clock.now();
+        Ok(self.lend(timeout_ms, mode)?.map(move |task| Lease { client: self, held: Held::new(task, clock, sent_at, timeout_ms), }))
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use self::asynchronous::AsyncLease;
+
+#[cfg(feature = "tokio")]
+mod asynchronous {
+    use std::{
+        sync::Arc,
+        time::Instant,
+    };
+
+    use super::super::{
+        Key,
+        Value,
+        LendMode,
+        RepayStatus,
+    };
+    use super::super::client::{
+        self,
+        ClientError,
+    };
+    use super::super::async_client::Client;
+    use super::super::clock::{
+        Clock,
+        SystemClock,
+    };
+    use super::{
+        Held,
+        LeaseError,
+    };
+
+    /// Task lent by `Client::lease`.
+    pub struct AsyncLease {
+        client: Client,
+        held: Held,
+    }
+
+    impl AsyncLease {
+        lease_accessors!();
+
+        /// Extends the lease to `timeout_ms` from now. Returns `None` if the
+        /// task is not lent anymore (`Skipped`).
+        pub async fn heartbeat(mut self, timeout_ms: u64) -> Result<Option<AsyncLease>, LeaseError<AsyncLease>> {
+            let sent_at = self.held.clock.now();
+            match self.client.request(&self.held.heartbeat_req(timeout_ms)).await.and_then(client::heartbeaten) {
+                Ok(true) => {
+                    let held = self.held.extended(sent_at, timeout_ms);
+                    Ok(Some(AsyncLease { client: self.client, held: held, }))
+                },
+                Ok(false) => {
+                    self.held.settled = true;
+                    Ok(None)
+                },
+                Err(err) =>
+                    Err(LeaseError::new(err, self)),
+            }
+        }
+
+        /// Returns `false` if the task is not lent anymore (e.g. its timeout has expired).
+        pub async fn repay(mut self, value: Value, status: RepayStatus) -> Result<bool, LeaseError<AsyncLease>> {
+            match self.client.request(&self.held.repay_req(value, status)).await.and_then(client::repaid) {
+                Ok(repaid) => {
+                    self.held.settled = true;
+                    Ok(repaid)
+                },
+                Err(err) =>
+                    Err(LeaseError::new(err, self)),
+            }
+        }
+
+        /// Removes the task from the queue keeping its value in the database.
+        pub async fn drop_task(self) -> Result<bool, LeaseError<AsyncLease>> {
+            let value = self.held.value.clone();
+            self.repay(value, RepayStatus::Drop).await
+        }
+    }
+
+    impl Client {
+        /// Same as `lend` returning the task as an `AsyncLease`.
+        pub async fn lease(&self, timeout_ms: u64, mode: LendMode) -> Result<Option<AsyncLease>, ClientError> {
+            self.lease_with_clock(timeout_ms, mode, Arc::new(SystemClock)).await
+        }
+
+        /// Same as `lease` with the deadline following `clock`.
+        pub async fn lease_with_clock(&self, timeout_ms: u64, mode: LendMode, clock: Arc<dyn Clock>) -> Result<Option<AsyncLease>, ClientError> {
+            let sent_at = clock.now();
+            Ok(self.lend(timeout_ms, mode).await?.map(|task| AsyncLease {
+                client: self.clone(),
+                held: Held::new(task, clock, sent_at, timeout_ms),
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+    use super::super::{LendMode, RepayStatus, ServerErrorCode, GlobalReq, GlobalRep};
+    use super::super::client::{SyncClient, ClientError, LentTask};
+    use super::super::clock::{Clock, ManualClock, SystemClock};
+    use super::{Held, set_drop_warning};
+    use super::super::test_support::{bytes, serve};
+
+    static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    fn lent(lend_key: u64) -> GlobalRep {
+        GlobalRep::Lent { lend_key: lend_key, key: bytes(b"cat"), value: bytes(b"small"), }
+    }
+
+    #[test]
+    fn heartbeat_and_repay() {
+        let (addr, _server) = serve(vec![
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Heartbeat { lend_key: 7, key: bytes(b"cat"), timeout: 60000, },
+            GlobalReq::Repay { lend_key: 7, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Reward, },
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+        ], vec![
+            lent(7),
+            GlobalRep::Heartbeaten,
+            GlobalRep::Repaid,
+            GlobalRep::QueueEmpty,
+        ]);
+        let mut client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let lease = client.lease(1000, LendMode::Poll).unwrap().unwrap();
+        assert_eq!((lease.lend_key(), &lease.key()[..], &lease.value()[..]), (7, &b"cat"[..], &b"small"[..]));
+        assert!(lease.deadline() < std::time::Instant::now() + Duration::from_millis(1000));
+        assert!(!lease.is_expired());
+        let lease = lease.heartbeat(60000).unwrap().unwrap();
+        assert!(lease.deadline() > std::time::Instant::now() + Duration::from_millis(59000));
+        assert!(lease.repay(bytes(b"big"), RepayStatus::Reward).unwrap());
+        assert!(client.lease(1000, LendMode::Poll).unwrap().is_none());
+    }
+
+    #[test]
+    fn lost() {
+        let (addr, _server) = serve(vec![
+            GlobalReq::Lend { timeout: 1, mode: LendMode::Poll, },
+            GlobalReq::Heartbeat { lend_key: 1, key: bytes(b"cat"), timeout: 1000, },
+            GlobalReq::Lend { timeout: 1, mode: LendMode::Poll, },
+            GlobalReq::Repay { lend_key: 2, key: bytes(b"cat"), value: bytes(b"small"), status: RepayStatus::Drop, },
+        ], vec![
+            lent(1),
+            GlobalRep::Skipped,
+            lent(2),
+            GlobalRep::NotFound,
+        ]);
+        let mut client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let lease = client.lease(1, LendMode::Poll).unwrap().unwrap();
+        thread::sleep(Duration::from_millis(2));
+        assert!(lease.is_expired());
+        assert!(lease.heartbeat(1000).unwrap().is_none());
+        let lease = client.lease(1, LendMode::Poll).unwrap().unwrap();
+        assert!(!lease.drop_task().unwrap());
+    }
+
+    #[test]
+    fn drop_warning() {
+        set_drop_warning(Some(|message| WARNINGS.lock().unwrap().push(message.to_string())));
+        let task = LentTask { lend_key: 4242, key: bytes(b"cat"), value: bytes(b"small"), };
+        drop(Held::new(task, Arc::new(SystemClock), Instant::now(), 1000));
+        let task = LentTask { lend_key: 4243, key: bytes(b"cat"), value: bytes(b"small"), };
+        let mut settled = Held::new(task, Arc::new(SystemClock), Instant::now(), 1000);
+        settled.settled = true;
+        drop(settled);
+        set_drop_warning(None);
+        let warnings = WARNINGS.lock().unwrap();
+        // other tests may drop leases meanwhile
+        assert!(warnings.iter().any(|warning| warning.starts_with("lease 4242 for key \"cat\" dropped without repay")));
+        assert!(!warnings.iter().any(|warning| warning.starts_with("lease 4243 ")));
+    }
+
+    #[test]
+    fn manual_clock() {
+        let (addr, _server) = serve(vec![
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Heartbeat { lend_key: 1, key: bytes(b"cat"), timeout: 1000, },
+        ], vec![
+            lent(1),
+            GlobalRep::Heartbeaten,
+        ]);
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let lease = client.lease_with_clock(1000, LendMode::Poll, Arc::new(clock.clone())).unwrap().unwrap();
+        assert_eq!(lease.deadline(), start + Duration::from_millis(1000));
+        clock.advance(Duration::from_millis(999));
+        assert!(!lease.is_expired());
+        let lease = lease.heartbeat(1000).unwrap().unwrap();
+        assert_eq!(lease.deadline(), start + Duration::from_millis(1999));
+        clock.advance(Duration::from_millis(1000));
+        assert!(lease.is_expired());
+    }
+
+    #[test]
+    fn lease_returned_on_error() {
+        let overloaded = || GlobalRep::ServerError { code: ServerErrorCode::Overloaded, message: String::new(), };
+        let (addr, _server) = serve(vec![
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Heartbeat { lend_key: 5, key: bytes(b"cat"), timeout: 1000, },
+            GlobalReq::Repay { lend_key: 5, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Front, },
+            GlobalReq::Repay { lend_key: 5, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Front, },
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Repay { lend_key: 6, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Front, },
+        ], vec![
+            lent(5),
+            overloaded(),
+            overloaded(),
+            GlobalRep::Repaid,
+            lent(6),
+            GlobalRep::Pong,
+        ]);
+        let mut client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let lease = client.lease(1000, LendMode::Poll).unwrap().unwrap();
+        let err = match lease.heartbeat(1000) {
+            Err(err) => err,
+            Ok(_) => panic!("heartbeat should fail"),
+        };
+        assert!(matches!(err.error, ClientError::Server { code: ServerErrorCode::Overloaded, .. }));
+        let err = err.lease.unwrap().repay(bytes(b"big"), RepayStatus::Front).unwrap_err();
+        assert!(err.error.to_string().contains("Overloaded"));
+        assert!(err.lease.unwrap().repay(bytes(b"big"), RepayStatus::Front).unwrap());
+        let lease = client.lease(1000, LendMode::Poll).unwrap().unwrap();
+        let err = lease.repay(bytes(b"big"), RepayStatus::Front).unwrap_err();
+        assert!(matches!(err.error, ClientError::UnexpectedReply(GlobalRep::Pong)));
+        assert!(err.lease.is_none());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn async_lease() {
+        let (addr, _server) = serve(vec![
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Heartbeat { lend_key: 3, key: bytes(b"cat"), timeout: 1000, },
+            GlobalReq::Repay { lend_key: 3, key: bytes(b"cat"), value: bytes(b"small"), status: RepayStatus::Drop, },
+        ], vec![
+            lent(3),
+            GlobalRep::Heartbeaten,
+            GlobalRep::Repaid,
+        ]);
+        let client = super::super::async_client::Client::connect(addr).await.unwrap();
+        let lease = client.lease(1000, LendMode::Poll).await.unwrap().unwrap();
+        assert_eq!(lease.lend_key(), 3);
+        let lease = lease.heartbeat(1000).await.unwrap().unwrap();
+        assert!(lease.drop_task().await.unwrap());
+    }
+}
@@ -1,6 +1,17 @@
+#![allow(
+    clippy::match_ref_pats,
+    clippy::redundant_field_names,
+    clippy::let_and_return,
+    clippy::needless_lifetimes,
+    clippy::needless_return,
+    clippy::needless_borrowed_reference,
+)]
+
 use std::{
+    fmt,
     sync::Arc,
     mem::size_of,
+    convert::TryFrom,
 };
 
 use byteorder::{
@@ -8,145 +19,418 @@ use byteorder::{
     BigEndian,
 };
 
+extern crate self as spiderq_proto;
+
+use spiderq_proto_derive::SpiderqCodec;
+
+pub mod codec;
+pub mod schema;
+pub mod text;
+pub mod format;
+pub mod client;
+pub mod pool;
+pub mod lease;
+pub mod typed;
+pub mod clock;
+pub mod server;
+pub mod retry;
+pub mod worker;
+#[cfg(feature = "tokio")]
+pub mod async_client;
+#[cfg(feature = "zmq")]
+pub mod zmq;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "arbitrary")]
+pub mod arbitrary;
+#[cfg(feature = "serde")]
+pub mod bytes_repr;
+#[cfg(test)]
+mod golden;
+#[cfg(test)]
+mod test_support;
+
 pub type Key = Arc<[u8]>;
 pub type Value = Arc<[u8]>;
 
-#[derive(Debug, PartialEq)]
+pub const PROTO_VERSION: u32 = 4;
+
+#[derive(Debug, PartialEq, SpiderqCodec)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RepayStatus {
-    Penalty,
-    Reward,
-    Front,
-    Drop,
+    #[tag(1)] Penalty,
+    #[tag(2)] Reward,
+    #[tag(3)] Front,
+    #[tag(4)] Drop,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, SpiderqCodec)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LendMode {
-    Block,
-    Poll,
+    #[tag(1)] Block,
+    #[tag(2)] Poll,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, SpiderqCodec)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AddMode {
-    Head,
-    Tail,
+    #[tag(1)] Head,
+    #[tag(2)] Tail,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, SpiderqCodec)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AuthMechanism {
+    #[tag(1)] Token,
+    #[tag(2)] HmacSha256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    Producer,
+    Worker,
+    Reader,
+    Admin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions(0);
+    pub const ALL: Permissions = Permissions(0x0F);
+
+    fn bit(permission: Permission) -> u8 {
+        match permission {
+            Permission::Producer => 0x01,
+            Permission::Worker => 0x02,
+            Permission::Reader => 0x04,
+            Permission::Admin => 0x08,
+        }
+    }
+
+    pub fn with(self, permission: Permission) -> Permissions {
+        Permissions(self.0 | Permissions::bit(permission))
+    }
+
+    pub fn contains(&self, permission: Permission) -> bool {
+        self.0 & Permissions::bit(permission) != 0
+    }
+
+    pub fn allows(&self, req: &GlobalReq) -> bool {
+        match req.required_permission() {
+            None => true,
+            Some(permission) => self.contains(permission),
+        }
+    }
+}
+
+impl From<Permission> for Permissions {
+    fn from(permission: Permission) -> Permissions {
+        Permissions::NONE.with(permission)
+    }
+}
+
+#[derive(Debug, PartialEq, SpiderqCodec)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GlobalReq {
-    Ping,
-    Count,
-    Add { key: Key, value: Value, mode: AddMode, },
-    Update(Key, Value),
-    Lookup(Key),
-    Remove(Key),
-    Lend { timeout: u64, mode: LendMode, },
-    Repay { lend_key: u64, key: Key, value: Value, status: RepayStatus, },
-    Heartbeat { lend_key: u64, key: Key, timeout: u64, },
-    Stats,
-    Flush,
-    Terminate,
+    #[tag(11)] Ping,
+    #[tag(1)] Count,
+    #[tag(2)] Add {
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = KeyLen)] key: Key,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = ValueLen)] value: Value,
+        mode: AddMode,
+    },
+    #[tag(3)] Update(
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(Key, len = KeyLen)] Key,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(Value, len = ValueLen)] Value,
+    ),
+    #[tag(9)] Lookup(#[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(Key, len = KeyLen)] Key),
+    #[tag(12)] Remove(#[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(Key, len = KeyLen)] Key),
+    #[tag(4)] Lend { timeout: u64, mode: LendMode, },
+    #[tag(5)] Repay {
+        lend_key: u64,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = KeyLen)] key: Key,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = ValueLen)] value: Value,
+        status: RepayStatus,
+    },
+    #[tag(6)] Heartbeat {
+        lend_key: u64,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = KeyLen)] key: Key,
+        timeout: u64,
+    },
+    #[tag(7)] Stats,
+    #[tag(10)] Flush,
+    #[tag(8)] Terminate,
+    #[tag(13)] Info,
+    #[tag(14)] Auth {
+        mechanism: AuthMechanism,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = PayloadLen)] payload: Value,
+    },
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ServerErrorCode {
+    Internal,
+    DiskFull,
+    KeyTooLarge,
+    ValueTooLarge,
+    QueueNotFound,
+    ShuttingDown,
+    Overloaded,
+    Unknown(UnknownCode),
+}
+
+/// Code missing from the table above, e.g. sent by a newer server. Only
+/// `ServerErrorCode::from_code` makes one, so a known code always decodes and
+/// compares as its own variant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "u16", into = "u16"))]
+pub struct UnknownCode(u16);
+
+impl UnknownCode {
+    pub fn code(&self) -> u16 {
+        self.0
+    }
+}
+
+impl TryFrom<u16> for UnknownCode {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<UnknownCode, String> {
+        match ServerErrorCode::from_code(code) {
+            ServerErrorCode::Unknown(unknown) => Ok(unknown),
+            known => Err(format!("server error code {} is known as {:?}", code, known)),
+        }
+    }
+}
+
+impl From<UnknownCode> for u16 {
+    fn from(unknown: UnknownCode) -> u16 {
+        unknown.0
+    }
+}
+
+impl ServerErrorCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            &ServerErrorCode::Internal => 1,
+            &ServerErrorCode::DiskFull => 2,
+            &ServerErrorCode::KeyTooLarge => 3,
+            &ServerErrorCode::ValueTooLarge => 4,
+            &ServerErrorCode::QueueNotFound => 5,
+            &ServerErrorCode::ShuttingDown => 6,
+            &ServerErrorCode::Overloaded => 7,
+            &ServerErrorCode::Unknown(unknown) => unknown.code(),
+        }
+    }
+
+    pub fn from_code(code: u16) -> ServerErrorCode {
+        match code {
+            1 => ServerErrorCode::Internal,
+            2 => ServerErrorCode::DiskFull,
+            3 => ServerErrorCode::KeyTooLarge,
+            4 => ServerErrorCode::ValueTooLarge,
+            5 => ServerErrorCode::QueueNotFound,
+            6 => ServerErrorCode::ShuttingDown,
+            7 => ServerErrorCode::Overloaded,
+            code => ServerErrorCode::Unknown(UnknownCode(code)),
+        }
+    }
+
+    /// Whether the request is known to be rejected before taking any effect on
+    /// server, so a client may safely send it again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            &ServerErrorCode::DiskFull | &ServerErrorCode::ShuttingDown | &ServerErrorCode::Overloaded =>
+                true,
+            &ServerErrorCode::Internal |
+            &ServerErrorCode::KeyTooLarge |
+            &ServerErrorCode::ValueTooLarge |
+            &ServerErrorCode::QueueNotFound |
+            &ServerErrorCode::Unknown(..) =>
+                false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, SpiderqCodec)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GlobalRep {
-    Pong,
-    Counted(usize),
-    Added,
-    Kept,
-    Updated,
-    NotFound,
-    ValueFound(Value),
-    ValueNotFound,
-    Removed,
-    NotRemoved,
-    Lent { lend_key: u64, key: Key, value: Value, },
-    QueueEmpty,
-    Repaid,
-    Heartbeaten,
-    Skipped,
-    StatsGot {
-        ping: usize,
-        count: usize,
-        add: usize,
-        update: usize,
-        lookup: usize,
-        remove: usize,
-        lend: usize,
-        repay: usize,
-        heartbeat: usize,
-        stats: usize,
+    #[tag(17)] Pong,
+    #[tag(1)] Counted(#[field(Count, wire = u32)] usize),
+    #[tag(2)] Added,
+    #[tag(3)] Kept,
+    #[tag(4)] Updated,
+    #[tag(5)] NotFound,
+    #[tag(13)] ValueFound(#[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(Value, len = ValueLen)] Value),
+    #[tag(14)] ValueNotFound,
+    #[tag(18)] Removed,
+    #[tag(19)] NotRemoved,
+    #[tag(6)] Lent {
+        lend_key: u64,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = KeyLen)] key: Key,
+        #[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(len = ValueLen)] value: Value,
+    },
+    #[tag(16)] QueueEmpty,
+    #[tag(7)] Repaid,
+    #[tag(8)] Heartbeaten,
+    #[tag(9)] Skipped,
+    #[tag(10)] StatsGot {
+        #[field(wire = u64)] ping: usize,
+        #[field(wire = u64)] count: usize,
+        #[field(wire = u64)] add: usize,
+        #[field(wire = u64)] update: usize,
+        #[field(wire = u64)] lookup: usize,
+        #[field(wire = u64)] remove: usize,
+        #[field(wire = u64)] lend: usize,
+        #[field(wire = u64)] repay: usize,
+        #[field(wire = u64)] heartbeat: usize,
+        #[field(wire = u64)] stats: usize,
     },
-    Flushed,
-    Terminated,
-    Error(ProtoError),
+    #[tag(15)] Flushed,
+    #[tag(12)] Terminated,
+    #[tag(11)] #[message(ProtoError)] Error(#[field(Error)] ProtoError),
+    #[tag(20)] InfoGot {
+        #[field(len = ServerVersionLen)] server_version: String,
+        proto_version: u32,
+        uptime_ms: u64,
+        #[field(len = DbPathLen)] db_path: String,
+        #[field(wire = u32)] max_key_len: usize,
+        #[field(wire = u32)] max_value_len: usize,
+        #[field(Feature, len = FeatureLen, count = FeaturesCount)] features: Vec<String>,
+    },
+    #[tag(21)] AuthOk,
+    #[tag(22)] AuthFailed,
+    #[tag(23)] AuthChallenge(#[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] #[field(Nonce, len = NonceLen)] Value),
+    #[tag(24)] Unauthenticated,
+    #[tag(25)] Forbidden,
+    #[tag(26)] ServerError { code: ServerErrorCode, #[field(len = MessageLen)] message: String, },
+}
+
+macro_rules! defkind {
+    ($kind:ident { $($variant:ident = $code:literal => $name:literal,)* }) => {
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $kind {
+            $($variant,)*
+        }
+
+        impl $kind {
+            pub const ALL: &'static [$kind] = &[$($kind::$variant,)*];
+
+            pub fn code(&self) -> u8 {
+                match self {
+                    $(&$kind::$variant => $code,)*
+                }
+            }
+
+            pub fn from_code(code: u8) -> Option<$kind> {
+                match code {
+                    $($code => Some($kind::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(&$kind::$variant => $name,)*
+                }
+            }
+        }
+    }
 }
 
+defkind!(MessageKind {
+    GlobalReq = 1 => "GlobalReq",
+    GlobalReqAdd = 2 => "GlobalReq::Add",
+    GlobalReqUpdate = 3 => "GlobalReq::Update",
+    GlobalReqLookup = 4 => "GlobalReq::Lookup",
+    GlobalReqRemove = 5 => "GlobalReq::Remove",
+    GlobalReqLend = 6 => "GlobalReq::Lend",
+    GlobalReqRepay = 7 => "GlobalReq::Repay",
+    GlobalReqHeartbeat = 8 => "GlobalReq::Heartbeat",
+    GlobalReqAuth = 9 => "GlobalReq::Auth",
+    GlobalRep = 10 => "GlobalRep",
+    GlobalRepCounted = 11 => "GlobalRep::Counted",
+    GlobalRepValueFound = 12 => "GlobalRep::ValueFound",
+    GlobalRepLent = 13 => "GlobalRep::Lent",
+    GlobalRepStatsGot = 14 => "GlobalRep::StatsGot",
+    GlobalRepInfoGot = 15 => "GlobalRep::InfoGot",
+    GlobalRepAuthChallenge = 16 => "GlobalRep::AuthChallenge",
+    ProtoError = 17 => "ProtoError",
+    ProtoErrorDbQueueOutOfSync = 18 => "ProtoError::DbQueueOutOfSync",
+    GlobalRepServerError = 19 => "GlobalRep::ServerError",
+});
+
+defkind!(FieldKind {
+    Tag = 1 => "tag",
+    KeyLen = 2 => "key_len",
+    Key = 3 => "key",
+    ValueLen = 4 => "value_len",
+    Value = 5 => "value",
+    Mode = 6 => "mode",
+    Timeout = 7 => "timeout",
+    LendKey = 8 => "lend_key",
+    Status = 9 => "status",
+    Count = 10 => "count",
+    Ping = 11 => "ping",
+    Add = 12 => "add",
+    Update = 13 => "update",
+    Lookup = 14 => "lookup",
+    Remove = 15 => "remove",
+    Lend = 16 => "lend",
+    Repay = 17 => "repay",
+    Heartbeat = 18 => "heartbeat",
+    Stats = 19 => "stats",
+    Required = 20 => "required",
+    Given = 21 => "given",
+    InvalidTag = 22 => "invalid_tag",
+    Message = 23 => "message",
+    Field = 24 => "field",
+    ServerVersionLen = 25 => "server_version_len",
+    ServerVersion = 26 => "server_version",
+    ProtoVersion = 27 => "proto_version",
+    UptimeMs = 28 => "uptime_ms",
+    DbPathLen = 29 => "db_path_len",
+    DbPath = 30 => "db_path",
+    MaxKeyLen = 31 => "max_key_len",
+    MaxValueLen = 32 => "max_value_len",
+    FeaturesCount = 33 => "features_count",
+    FeatureLen = 34 => "feature_len",
+    Feature = 35 => "feature",
+    Mechanism = 36 => "mechanism",
+    PayloadLen = 37 => "payload_len",
+    Payload = 38 => "payload",
+    NonceLen = 39 => "nonce_len",
+    Nonce = 40 => "nonce",
+    Code = 41 => "code",
+    MessageLen = 42 => "message_len",
+    Error = 43 => "error",
+});
+
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ProtoError {
-    NotEnoughDataForGlobalReqTag { required: usize, given: usize, },
-    InvalidGlobalReqTag(u8),
-    NotEnoughDataForGlobalReqAddKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqAddKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqAddValueLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqAddValue { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqAddMode { required: usize, given: usize, },
-    InvalidGlobalReqAddModeTag(u8),
-    NotEnoughDataForGlobalReqLendTimeout { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqLendMode { required: usize, given: usize, },
-    InvalidGlobalReqLendModeTag(u8),
-    NotEnoughDataForGlobalReqRepayLendKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRepayKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRepayKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRepayValueLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRepayValue { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRepayRepayStatus { required: usize, given: usize, },
-    InvalidGlobalReqRepayRepayStatusTag(u8),
-    NotEnoughDataForGlobalRepTag { required: usize, given: usize, },
-    InvalidGlobalRepTag(u8),
-    NotEnoughDataForGlobalRepCountCount { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepLentLendKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepLentKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepLentKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepLentValueLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepLentValue { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsCount { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsPing { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsAdd { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsUpdate { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsLookup { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsRemove { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsLend { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsRepay { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsHeartbeat { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepStatsStats { required: usize, given: usize, },
-    NotEnoughDataForProtoErrorTag { required: usize, given: usize, },
-    InvalidProtoErrorTag(u8),
-    NotEnoughDataForProtoErrorRequired { required: usize, given: usize, },
-    NotEnoughDataForProtoErrorGiven { required: usize, given: usize, },
-    NotEnoughDataForProtoErrorInvalidTag { required: usize, given: usize, },
-    DbQueueOutOfSync(Key),
-    NotEnoughDataForProtoErrorDbQueueOutOfSyncKeyLen { required: usize, given: usize, },
-    NotEnoughDataForProtoErrorDbQueueOutOfSyncKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqUpdateKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqUpdateKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqUpdateValueLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqUpdateValue { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqHeartbeatLendKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqHeartbeatKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqHeartbeatKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqHeartbeatTimeout { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqLookupKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqLookupKey { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepValueFoundValueLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalRepValueFoundValue { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRemoveKeyLen { required: usize, given: usize, },
-    NotEnoughDataForGlobalReqRemoveKey { required: usize, given: usize, },
+    NotEnoughData { message: MessageKind, field: FieldKind, required: usize, given: usize, },
+    InvalidTag { message: MessageKind, field: FieldKind, tag: u8, },
+    InvalidUtf8 { message: MessageKind, field: FieldKind, },
+    DbQueueOutOfSync(#[cfg_attr(feature = "serde", serde(with = "bytes_repr"))] Key),
 }
 
 macro_rules! try_get {
-    ($data:ident, $ty:ty, $reader:ident, $err:ident) =>
+    ($data:ident, $ty:ty, $reader:ident, $message:ident, $field:ident) =>
         (if $data.len() < size_of::<$ty>() {
-            return Err(ProtoError::$err { required: size_of::<$ty>(), given: $data.len(), })
+            return Err(ProtoError::NotEnoughData {
+                message: MessageKind::$message,
+                field: FieldKind::$field,
+                required: size_of::<$ty>(),
+                given: $data.len(),
+            })
         } else {
             (BigEndian::$reader($data), &$data[size_of::<$ty>() ..])
         })
@@ -176,12 +460,12 @@ impl U8Support for BigEndian {
 }
 
 macro_rules! try_get_vec {
-    ($buf:expr, $err_len:ident, $err_val:ident) => ({
+    ($buf:expr, $message:ident, $field_len:ident, $field:ident) => ({
         let buf = $buf;
-        let (len, buf) = try_get!(buf, u32, read_u32, $err_len);
+        let (len, buf) = try_get!(buf, u32, read_u32, $message, $field_len);
         let len = len as usize;
         if buf.len() < len {
-            return Err(ProtoError::$err_val { required: len, given: buf.len(), })
+            return Err(ProtoError::NotEnoughData { message: MessageKind::$message, field: FieldKind::$field, required: len, given: buf.len(), })
         } else {
             (Arc::from(&buf[0 .. len]), &buf[len ..])
         }
@@ -200,574 +484,333 @@ macro_rules! put_vec_adv {
 }
 
 impl GlobalReq {
-    pub fn decode<'a>(data: &'a [u8]) -> Result<(GlobalReq, &'a [u8]), ProtoError> {
-        match try_get!(data, u8, read_u8, NotEnoughDataForGlobalReqTag) {
-            (1, buf) =>
-                Ok((GlobalReq::Count, buf)),
-            (2, buf) => {
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqAddKeyLen, NotEnoughDataForGlobalReqAddKey);
-                let (value, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqAddValueLen, NotEnoughDataForGlobalReqAddValue);
-                let (mode, buf) = match try_get!(buf, u8, read_u8, NotEnoughDataForGlobalReqAddMode) {
-                    (1, buf) => (AddMode::Head, buf),
-                    (2, buf) => (AddMode::Tail, buf),
-                    (mode_tag, _) => return Err(ProtoError::InvalidGlobalReqAddModeTag(mode_tag)),
-                };
-
-                Ok((GlobalReq::Add { key: key, value: value, mode: mode, }, buf))
-            },
-            (3, buf) => {
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqUpdateKeyLen, NotEnoughDataForGlobalReqUpdateKey);
-                let (value, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqUpdateValueLen, NotEnoughDataForGlobalReqUpdateValue);
-                Ok((GlobalReq::Update(key, value), buf))
-            },
-            (4, buf) => {
-                let (timeout, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalReqLendTimeout);
-                let (mode, buf) = match try_get!(buf, u8, read_u8, NotEnoughDataForGlobalReqLendMode) {
-                    (1, buf) => (LendMode::Block, buf),
-                    (2, buf) => (LendMode::Poll, buf),
-                    (mode_tag, _) => return Err(ProtoError::InvalidGlobalReqLendModeTag(mode_tag)),
-                };
-                Ok((GlobalReq::Lend { timeout: timeout, mode: mode, }, buf))
-            },
-            (5, buf) => {
-                let (lend_key, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalReqRepayLendKey);
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqRepayKeyLen, NotEnoughDataForGlobalReqRepayKey);
-                let (value, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqRepayValueLen, NotEnoughDataForGlobalReqRepayValue);
-                let (status, buf) = match try_get!(buf, u8, read_u8, NotEnoughDataForGlobalReqRepayRepayStatus) {
-                    (1, buf) => (RepayStatus::Penalty, buf),
-                    (2, buf) => (RepayStatus::Reward, buf),
-                    (3, buf) => (RepayStatus::Front, buf),
-                    (4, buf) => (RepayStatus::Drop, buf),
-                    (status_tag, _) => return Err(ProtoError::InvalidGlobalReqRepayRepayStatusTag(status_tag)),
-                };
-                Ok((GlobalReq::Repay { lend_key: lend_key, key: key, value: value, status: status, }, buf))
-            },
-            (6, buf) => {
-                let (lend_key, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalReqHeartbeatLendKey);
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqHeartbeatKeyLen, NotEnoughDataForGlobalReqHeartbeatKey);
-                let (timeout, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalReqHeartbeatTimeout);
-                Ok((GlobalReq::Heartbeat { lend_key: lend_key, key: key, timeout: timeout, }, buf))
-            },
-            (7, buf) =>
-                Ok((GlobalReq::Stats, buf)),
-            (8, buf) =>
-                Ok((GlobalReq::Terminate, buf)),
-            (9, buf) => {
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqLookupKeyLen, NotEnoughDataForGlobalReqLookupKey);
-                Ok((GlobalReq::Lookup(key), buf))
-            },
-            (10, buf) =>
-                Ok((GlobalReq::Flush, buf)),
-            (11, buf) =>
-                Ok((GlobalReq::Ping, buf)),
-            (12, buf) => {
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalReqRemoveKeyLen, NotEnoughDataForGlobalReqRemoveKey);
-                Ok((GlobalReq::Remove(key), buf))
-            },
-            (tag, _) =>
-                return Err(ProtoError::InvalidGlobalReqTag(tag)),
+    pub fn required_permission(&self) -> Option<Permission> {
+        match self {
+            &GlobalReq::Ping | &GlobalReq::Info | &GlobalReq::Auth { .. } =>
+                None,
+            &GlobalReq::Add { .. } | &GlobalReq::Update(..) =>
+                Some(Permission::Producer),
+            &GlobalReq::Lend { .. } | &GlobalReq::Repay { .. } | &GlobalReq::Heartbeat { .. } =>
+                Some(Permission::Worker),
+            &GlobalReq::Lookup(..) | &GlobalReq::Count | &GlobalReq::Stats =>
+                Some(Permission::Reader),
+            &GlobalReq::Terminate | &GlobalReq::Flush | &GlobalReq::Remove(..) =>
+                Some(Permission::Admin),
         }
     }
 
-    pub fn encode_len(&self) -> usize {
-        size_of::<u8>() + match self {
-            &GlobalReq::Ping | &GlobalReq::Count | &GlobalReq::Stats | &GlobalReq::Terminate | &GlobalReq::Flush => 0,
-            &GlobalReq::Add { key: ref k, value: ref v, .. } => size_of::<u32>() * 2 + k.len() + v.len() + size_of::<u8>(),
-            &GlobalReq::Update(ref key, ref value) => size_of::<u32>() * 2 + key.len() + value.len(),
-            &GlobalReq::Lend { .. } => size_of::<u64>() + size_of::<u8>(),
-            &GlobalReq::Repay { key: ref rkey, value: ref rvalue, .. } =>
-                size_of::<u64>() + size_of::<u32>() * 2 + rkey.len() + rvalue.len() + size_of::<u8>(),
-            &GlobalReq::Heartbeat { key: ref k, .. } => size_of::<u64>() + size_of::<u32>() + k.len() + size_of::<u64>(),
-            &GlobalReq::Lookup(ref key) => size_of::<u32>() + key.len(),
-            &GlobalReq::Remove(ref key) => size_of::<u32>() + key.len(),
-        }
+    pub fn requires_auth(&self) -> bool {
+        self.required_permission().is_some()
     }
 
-    pub fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
+    pub fn idempotency(&self) -> Idempotency {
         match self {
-            &GlobalReq::Count =>
-                put_adv!(area, u8, write_u8, 1),
-            &GlobalReq::Add { key: ref k, value: ref v, mode: ref m, } => {
-                let area = put_adv!(area, u8, write_u8, 2);
-                let area = put_vec_adv!(area, k);
-                let area = put_vec_adv!(area, v);
-                put_adv!(area, u8, write_u8, match m {
-                    &AddMode::Head => 1,
-                    &AddMode::Tail => 2,
-                })
-            },
-            &GlobalReq::Update(ref key, ref value) => {
-                let area = put_adv!(area, u8, write_u8, 3);
-                let area = put_vec_adv!(area, key);
-                let area = put_vec_adv!(area, value);
-                area
-            },
-            &GlobalReq::Lend { timeout: t, mode: ref m, } => {
-                let area = put_adv!(area, u8, write_u8, 4);
-                let area = put_adv!(area, u64, write_u64, t);
-                put_adv!(area, u8, write_u8, match m {
-                    &LendMode::Block => 1,
-                    &LendMode::Poll => 2,
-                })
-            },
-            &GlobalReq::Repay { lend_key: rlend_key, key: ref rkey, value: ref rvalue, status: ref rstatus } => {
-                let area = put_adv!(area, u8, write_u8, 5);
-                let area = put_adv!(area, u64, write_u64, rlend_key);
-                let area = put_vec_adv!(area, rkey);
-                let area = put_vec_adv!(area, rvalue);
-                put_adv!(area, u8, write_u8, match rstatus {
-                    &RepayStatus::Penalty => 1,
-                    &RepayStatus::Reward => 2,
-                    &RepayStatus::Front => 3,
-                    &RepayStatus::Drop => 4,
-                })
-            },
-            &GlobalReq::Heartbeat { lend_key: l, key: ref k, timeout: t, } => {
-                let area = put_adv!(area, u8, write_u8, 6);
-                let area = put_adv!(area, u64, write_u64, l);
-                let area = put_vec_adv!(area, k);
-                put_adv!(area, u64, write_u64, t)
-            },
-            &GlobalReq::Stats =>
-                put_adv!(area, u8, write_u8, 7),
-            &GlobalReq::Terminate =>
-                put_adv!(area, u8, write_u8, 8),
-            &GlobalReq::Lookup(ref key) => {
-                let area = put_adv!(area, u8, write_u8, 9);
-                let area = put_vec_adv!(area, key);
-                area
-            },
+            &GlobalReq::Ping | &GlobalReq::Count | &GlobalReq::Lookup(..) | &GlobalReq::Stats | &GlobalReq::Info =>
+                Idempotency::ReadOnly,
+            // repeated `Add` gets `Kept`, `Remove` gets `NotRemoved` and `Repay` gets `NotFound`
+            &GlobalReq::Add { .. } |
+            &GlobalReq::Update(..) |
+            &GlobalReq::Remove(..) |
+            &GlobalReq::Repay { .. } |
+            &GlobalReq::Heartbeat { .. } |
             &GlobalReq::Flush =>
-                put_adv!(area, u8, write_u8, 10),
-            &GlobalReq::Ping =>
-                put_adv!(area, u8, write_u8, 11),
-            &GlobalReq::Remove(ref key) => {
-                let area = put_adv!(area, u8, write_u8, 12);
-                let area = put_vec_adv!(area, key);
-                area
-            },
+                Idempotency::Idempotent,
+            // repeated `Lend` lends another task, `Auth` steps depend on the session state
+            &GlobalReq::Lend { .. } | &GlobalReq::Terminate | &GlobalReq::Auth { .. } =>
+                Idempotency::NonIdempotent,
         }
     }
 }
 
-impl GlobalRep {
-    pub fn decode<'a>(data: &'a [u8]) -> Result<(GlobalRep, &'a [u8]), ProtoError> {
-        match try_get!(data, u8, read_u8, NotEnoughDataForGlobalRepTag) {
-            (1, buf) => {
-                let (count, buf) = try_get!(buf, u32, read_u32, NotEnoughDataForGlobalRepCountCount);
-                Ok((GlobalRep::Counted(count as usize), buf))
-            },
-            (2, buf) =>
-                Ok((GlobalRep::Added, buf)),
-            (3, buf) =>
-                Ok((GlobalRep::Kept, buf)),
-            (4, buf) =>
-                Ok((GlobalRep::Updated, buf)),
-            (5, buf) =>
-                Ok((GlobalRep::NotFound, buf)),
-            (6, buf) => {
-                let (lend_key, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepLentLendKey);
-                let (key, buf) = try_get_vec!(buf, NotEnoughDataForGlobalRepLentKeyLen, NotEnoughDataForGlobalRepLentKey);
-                let (value, buf) = try_get_vec!(buf, NotEnoughDataForGlobalRepLentValueLen, NotEnoughDataForGlobalRepLentValue);
-                Ok((GlobalRep::Lent { lend_key: lend_key, key: key, value: value, }, buf))
-            },
-            (7, buf) =>
-                Ok((GlobalRep::Repaid, buf)),
-            (8, buf) =>
-                Ok((GlobalRep::Heartbeaten, buf)),
-            (9, buf) =>
-                Ok((GlobalRep::Skipped, buf)),
-            (10, buf) => {
-                let (stats_ping, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsPing);
-                let (stats_count, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsCount);
-                let (stats_add, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsAdd);
-                let (stats_update, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsUpdate);
-                let (stats_lookup, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsLookup);
-                let (stats_remove, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsRemove);
-                let (stats_lend, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsLend);
-                let (stats_repay, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsRepay);
-                let (stats_heartbeat, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsHeartbeat);
-                let (stats_stats, buf) = try_get!(buf, u64, read_u64, NotEnoughDataForGlobalRepStatsStats);
-                Ok((GlobalRep::StatsGot {
-                    ping: stats_ping as usize,
-                    count: stats_count as usize,
-                    add: stats_add as usize,
-                    update: stats_update as usize,
-                    lookup: stats_lookup as usize,
-                    remove: stats_remove as usize,
-                    lend: stats_lend as usize,
-                    repay: stats_repay as usize,
-                    heartbeat: stats_heartbeat as usize,
-                    stats: stats_stats as usize,
-                }, buf))
-            },
-            (11, buf) => {
-                let (err, buf) = ProtoError::decode(buf)?;
-                Ok((GlobalRep::Error(err), buf))
-            },
-            (12, buf) =>
-                Ok((GlobalRep::Terminated, buf)),
-            (13, buf) => {
-                let (value, buf) = try_get_vec!(buf, NotEnoughDataForGlobalRepValueFoundValueLen, NotEnoughDataForGlobalRepValueFoundValue);
-                Ok((GlobalRep::ValueFound(value), buf))
-            },
-            (14, buf) =>
-                Ok((GlobalRep::ValueNotFound, buf)),
-            (15, buf) =>
-                Ok((GlobalRep::Flushed, buf)),
-            (16, buf) =>
-                Ok((GlobalRep::QueueEmpty, buf)),
-            (17, buf) =>
-                Ok((GlobalRep::Pong, buf)),
-            (18, buf) =>
-                Ok((GlobalRep::Removed, buf)),
-            (19, buf) =>
-                Ok((GlobalRep::NotRemoved, buf)),
-            (tag, _) =>
-                return Err(ProtoError::InvalidGlobalRepTag(tag)),
-        }
-    }
+/// Whether a request may be sent again when it is unknown if the server got it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Idempotency {
+    /// Does not change the server state.
+    ReadOnly,
+    /// Sending it again has the same effect as sending it once, although the
+    /// reply may differ (e.g. `Kept` instead of `Added`).
+    Idempotent,
+    NonIdempotent,
+}
 
-    pub fn encode_len(&self) -> usize {
-        size_of::<u8>() + match self {
-            &GlobalRep::Counted(..) => size_of::<u32>(),
-            &GlobalRep::Added |
-            &GlobalRep::Kept |
-            &GlobalRep::Updated |
-            &GlobalRep::NotFound |
-            &GlobalRep::Repaid |
-            &GlobalRep::Heartbeaten |
-            &GlobalRep::Skipped |
-            &GlobalRep::Terminated |
-            &GlobalRep::ValueNotFound |
-            &GlobalRep::Flushed |
-            &GlobalRep::Pong |
-            &GlobalRep::Removed |
-            &GlobalRep::NotRemoved |
-            &GlobalRep::QueueEmpty => 0,
-            &GlobalRep::Lent { key: ref rkey, value: ref rvalue, .. } => size_of::<u64>() + size_of::<u32>() * 2 + rkey.len() + rvalue.len(),
-            &GlobalRep::StatsGot { .. } => size_of::<u64>() * 10,
-            &GlobalRep::Error(ref err) => err.encode_len(),
-            &GlobalRep::ValueFound(ref value) => size_of::<u32>() + value.len(),
+impl Idempotency {
+    pub fn is_retry_safe(&self) -> bool {
+        match self {
+            &Idempotency::ReadOnly | &Idempotency::Idempotent => true,
+            &Idempotency::NonIdempotent => false,
         }
     }
+}
 
-    pub fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
-        match self {
-            &GlobalRep::Counted(count) => {
-                let area = put_adv!(area, u8, write_u8, 1);
-                put_adv!(area, u32, write_u32, count as u32)
-            },
-            &GlobalRep::Added =>
-                put_adv!(area, u8, write_u8, 2),
-            &GlobalRep::Kept =>
-                put_adv!(area, u8, write_u8, 3),
-            &GlobalRep::Updated =>
-                put_adv!(area, u8, write_u8, 4),
-            &GlobalRep::NotFound =>
-                put_adv!(area, u8, write_u8, 5),
-            &GlobalRep::Lent { lend_key: rlend_key, key: ref rkey, value: ref rvalue, } => {
-                let area = put_adv!(area, u8, write_u8, 6);
-                let area = put_adv!(area, u64, write_u64, rlend_key);
-                let area = put_vec_adv!(area, rkey);
-                let area = put_vec_adv!(area, rvalue);
-                area
-            },
-            &GlobalRep::Repaid =>
-                put_adv!(area, u8, write_u8, 7),
-            &GlobalRep::Heartbeaten =>
-                put_adv!(area, u8, write_u8, 8),
-            &GlobalRep::Skipped =>
-                put_adv!(area, u8, write_u8, 9),
-            &GlobalRep::StatsGot {
-                ping: stats_ping,
-                count: stats_count,
-                add: stats_add,
-                update: stats_update,
-                lookup: stats_lookup,
-                remove: stats_remove,
-                lend: stats_lend,
-                repay: stats_repay,
-                heartbeat: stats_heartbeat,
-                stats: stats_stats,
-            } => {
-                let area = put_adv!(area, u8, write_u8, 10);
-                let area = put_adv!(area, u64, write_u64, stats_ping as u64);
-                let area = put_adv!(area, u64, write_u64, stats_count as u64);
-                let area = put_adv!(area, u64, write_u64, stats_add as u64);
-                let area = put_adv!(area, u64, write_u64, stats_update as u64);
-                let area = put_adv!(area, u64, write_u64, stats_lookup as u64);
-                let area = put_adv!(area, u64, write_u64, stats_remove as u64);
-                let area = put_adv!(area, u64, write_u64, stats_lend as u64);
-                let area = put_adv!(area, u64, write_u64, stats_repay as u64);
-                let area = put_adv!(area, u64, write_u64, stats_heartbeat as u64);
-                let area = put_adv!(area, u64, write_u64, stats_stats as u64);
-                area
-            },
-            &GlobalRep::Error(ref err) => {
-                let area = put_adv!(area, u8, write_u8, 11);
-                err.encode(area)
-            },
-            &GlobalRep::Terminated =>
-                put_adv!(area, u8, write_u8, 12),
-            &GlobalRep::ValueFound(ref value) => {
-                let area = put_adv!(area, u8, write_u8, 13);
-                let area = put_vec_adv!(area, value);
-                area
-            },
-            &GlobalRep::ValueNotFound =>
-                put_adv!(area, u8, write_u8, 14),
-            &GlobalRep::Flushed =>
-                put_adv!(area, u8, write_u8, 15),
-            &GlobalRep::QueueEmpty =>
-                put_adv!(area, u8, write_u8, 16),
-            &GlobalRep::Pong =>
-                put_adv!(area, u8, write_u8, 17),
-            &GlobalRep::Removed =>
-                put_adv!(area, u8, write_u8, 18),
-            &GlobalRep::NotRemoved =>
-                put_adv!(area, u8, write_u8, 19),
-        }
+const LEGACY_NOT_ENOUGH_DATA_TAGS: &[(u8, MessageKind, FieldKind)] = &[
+    (1, MessageKind::GlobalReq, FieldKind::Tag),
+    (3, MessageKind::GlobalReqAdd, FieldKind::KeyLen),
+    (4, MessageKind::GlobalReqAdd, FieldKind::Key),
+    (5, MessageKind::GlobalReqAdd, FieldKind::ValueLen),
+    (6, MessageKind::GlobalReqAdd, FieldKind::Value),
+    (7, MessageKind::GlobalReqLend, FieldKind::Timeout),
+    (8, MessageKind::GlobalReqRepay, FieldKind::LendKey),
+    (9, MessageKind::GlobalReqRepay, FieldKind::KeyLen),
+    (10, MessageKind::GlobalReqRepay, FieldKind::Key),
+    (11, MessageKind::GlobalReqRepay, FieldKind::ValueLen),
+    (12, MessageKind::GlobalReqRepay, FieldKind::Value),
+    (13, MessageKind::GlobalReqRepay, FieldKind::Status),
+    (15, MessageKind::GlobalRep, FieldKind::Tag),
+    (17, MessageKind::GlobalRepCounted, FieldKind::Count),
+    (18, MessageKind::GlobalRepLent, FieldKind::LendKey),
+    (19, MessageKind::GlobalRepLent, FieldKind::KeyLen),
+    (20, MessageKind::GlobalRepLent, FieldKind::Key),
+    (21, MessageKind::GlobalRepLent, FieldKind::ValueLen),
+    (22, MessageKind::GlobalRepLent, FieldKind::Value),
+    (23, MessageKind::GlobalRepStatsGot, FieldKind::Count),
+    (24, MessageKind::GlobalRepStatsGot, FieldKind::Add),
+    (25, MessageKind::GlobalRepStatsGot, FieldKind::Update),
+    (26, MessageKind::GlobalRepStatsGot, FieldKind::Lookup),
+    (27, MessageKind::GlobalRepStatsGot, FieldKind::Lend),
+    (28, MessageKind::GlobalRepStatsGot, FieldKind::Repay),
+    (29, MessageKind::GlobalRepStatsGot, FieldKind::Heartbeat),
+    (30, MessageKind::GlobalRepStatsGot, FieldKind::Stats),
+    (31, MessageKind::ProtoError, FieldKind::Tag),
+    (33, MessageKind::ProtoError, FieldKind::Required),
+    (34, MessageKind::ProtoError, FieldKind::Given),
+    (35, MessageKind::ProtoError, FieldKind::InvalidTag),
+    (37, MessageKind::ProtoErrorDbQueueOutOfSync, FieldKind::KeyLen),
+    (38, MessageKind::ProtoErrorDbQueueOutOfSync, FieldKind::Key),
+    (39, MessageKind::GlobalReqUpdate, FieldKind::KeyLen),
+    (40, MessageKind::GlobalReqUpdate, FieldKind::Key),
+    (41, MessageKind::GlobalReqUpdate, FieldKind::ValueLen),
+    (42, MessageKind::GlobalReqUpdate, FieldKind::Value),
+    (43, MessageKind::GlobalReqHeartbeat, FieldKind::LendKey),
+    (44, MessageKind::GlobalReqHeartbeat, FieldKind::KeyLen),
+    (45, MessageKind::GlobalReqHeartbeat, FieldKind::Key),
+    (46, MessageKind::GlobalReqHeartbeat, FieldKind::Timeout),
+    (47, MessageKind::GlobalReqLookup, FieldKind::KeyLen),
+    (48, MessageKind::GlobalReqLookup, FieldKind::Key),
+    (49, MessageKind::GlobalRepValueFound, FieldKind::ValueLen),
+    (50, MessageKind::GlobalRepValueFound, FieldKind::Value),
+    (51, MessageKind::GlobalReqLend, FieldKind::Mode),
+    (53, MessageKind::GlobalReqAdd, FieldKind::Mode),
+    (55, MessageKind::GlobalRepStatsGot, FieldKind::Ping),
+    (56, MessageKind::GlobalRepStatsGot, FieldKind::Remove),
+    (57, MessageKind::GlobalReqRemove, FieldKind::KeyLen),
+    (58, MessageKind::GlobalReqRemove, FieldKind::Key),
+    (59, MessageKind::GlobalRepInfoGot, FieldKind::ServerVersionLen),
+    (60, MessageKind::GlobalRepInfoGot, FieldKind::ServerVersion),
+    (62, MessageKind::GlobalRepInfoGot, FieldKind::ProtoVersion),
+    (63, MessageKind::GlobalRepInfoGot, FieldKind::UptimeMs),
+    (64, MessageKind::GlobalRepInfoGot, FieldKind::DbPathLen),
+    (65, MessageKind::GlobalRepInfoGot, FieldKind::DbPath),
+    (67, MessageKind::GlobalRepInfoGot, FieldKind::MaxKeyLen),
+    (68, MessageKind::GlobalRepInfoGot, FieldKind::MaxValueLen),
+    (69, MessageKind::GlobalRepInfoGot, FieldKind::FeaturesCount),
+    (70, MessageKind::GlobalRepInfoGot, FieldKind::FeatureLen),
+    (71, MessageKind::GlobalRepInfoGot, FieldKind::Feature),
+    (73, MessageKind::GlobalReqAuth, FieldKind::Mechanism),
+    (75, MessageKind::GlobalReqAuth, FieldKind::PayloadLen),
+    (76, MessageKind::GlobalReqAuth, FieldKind::Payload),
+    (77, MessageKind::GlobalRepAuthChallenge, FieldKind::NonceLen),
+    (78, MessageKind::GlobalRepAuthChallenge, FieldKind::Nonce),
+];
+
+const LEGACY_INVALID_TAG_TAGS: &[(u8, MessageKind, FieldKind)] = &[
+    (2, MessageKind::GlobalReq, FieldKind::Tag),
+    (14, MessageKind::GlobalReqRepay, FieldKind::Status),
+    (16, MessageKind::GlobalRep, FieldKind::Tag),
+    (32, MessageKind::ProtoError, FieldKind::Tag),
+    (52, MessageKind::GlobalReqLend, FieldKind::Mode),
+    (54, MessageKind::GlobalReqAdd, FieldKind::Mode),
+    (74, MessageKind::GlobalReqAuth, FieldKind::Mechanism),
+];
+
+const LEGACY_INVALID_UTF8_TAGS: &[(u8, MessageKind, FieldKind)] = &[
+    (61, MessageKind::GlobalRepInfoGot, FieldKind::ServerVersion),
+    (66, MessageKind::GlobalRepInfoGot, FieldKind::DbPath),
+    (72, MessageKind::GlobalRepInfoGot, FieldKind::Feature),
+];
+
+const PROTO_ERROR_DB_QUEUE_OUT_OF_SYNC_TAG: u8 = 36;
+const PROTO_ERROR_NOT_ENOUGH_DATA_TAG: u8 = 79;
+const PROTO_ERROR_INVALID_TAG_TAG: u8 = 80;
+const PROTO_ERROR_INVALID_UTF8_TAG: u8 = 81;
+
+fn legacy_tag(table: &[(u8, MessageKind, FieldKind)], message: MessageKind, field: FieldKind) -> Option<u8> {
+    table.iter().find(|&&(_, m, f)| m == message && f == field).map(|&(tag, _, _)| tag)
+}
+
+fn kinds_encode_len(table: &[(u8, MessageKind, FieldKind)], message: MessageKind, field: FieldKind) -> usize {
+    match legacy_tag(table, message, field) {
+        Some(..) => 0,
+        None => size_of::<u8>() * 2,
     }
 }
 
+fn legacy_kinds(table: &[(u8, MessageKind, FieldKind)], tag: u8) -> Option<(MessageKind, FieldKind)> {
+    table.iter().find(|&&(t, _, _)| t == tag).map(|&(_, m, f)| (m, f))
+}
+
 macro_rules! decode_not_enough {
-    ($buf:ident, $pe_type:ident) => ({
-        let (required, given_buf) = try_get!($buf, u32, read_u32, NotEnoughDataForProtoErrorRequired);
-        let (given, rest) = try_get!(given_buf, u32, read_u32, NotEnoughDataForProtoErrorGiven);
-        Ok((ProtoError::$pe_type { required: required as usize, given: given as usize, }, rest))
+    ($buf:ident, $message:expr, $field:expr) => ({
+        let (required, given_buf) = try_get!($buf, u32, read_u32, ProtoError, Required);
+        let (given, rest) = try_get!(given_buf, u32, read_u32, ProtoError, Given);
+        Ok((ProtoError::NotEnoughData { message: $message, field: $field, required: required as usize, given: given as usize, }, rest))
     })
 }
 
 macro_rules! encode_not_enough {
-    ($area:ident, $tag:expr, $required:expr, $given: expr) => ({
-        let area = put_adv!($area, u8, write_u8, $tag);
-        let area = put_adv!(area, u32, write_u32, $required as u32);
+    ($area:ident, $required:expr, $given: expr) => ({
+        let area = put_adv!($area, u32, write_u32, $required as u32);
         put_adv!(area, u32, write_u32, $given as u32)
     })
 }
 
 macro_rules! decode_tag {
-    ($buf:ident, $pe_type:ident) => ({
-        let (tag, rest) = try_get!($buf, u8, read_u8, NotEnoughDataForProtoErrorInvalidTag);
-        Ok((ProtoError::$pe_type(tag), rest))
+    ($buf:ident, $message:expr, $field:expr) => ({
+        let (tag, rest) = try_get!($buf, u8, read_u8, ProtoError, InvalidTag);
+        Ok((ProtoError::InvalidTag { message: $message, field: $field, tag: tag, }, rest))
     })
 }
 
-macro_rules! encode_tag {
-    ($area:ident, $tag:expr, $invalid_tag:expr) => ({
-        let area = put_adv!($area, u8, write_u8, $tag);
-        put_adv!(area, u8, write_u8, $invalid_tag)
+macro_rules! decode_kinds {
+    ($buf:ident, $table:ident, $error_tag:expr) => ({
+        let (message, buf) = match try_get!($buf, u8, read_u8, ProtoError, Message) {
+            (code, buf) => match MessageKind::from_code(code) {
+                Some(message) => (message, buf),
+                None => return Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Message, tag: code, }),
+            },
+        };
+        let (field, buf) = match try_get!(buf, u8, read_u8, ProtoError, Field) {
+            (code, buf) => match FieldKind::from_code(code) {
+                Some(field) => (field, buf),
+                None => return Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Field, tag: code, }),
+            },
+        };
+        if legacy_tag($table, message, field).is_some() {
+            // there is a legacy tag for this kinds pair: generic encoding is not canonical
+            return Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Tag, tag: $error_tag, });
+        }
+        (message, field, buf)
+    })
+}
+
+macro_rules! encode_kinds {
+    ($area:ident, $error_tag:expr, $message:expr, $field:expr) => ({
+        let area = put_adv!($area, u8, write_u8, $error_tag);
+        let area = put_adv!(area, u8, write_u8, $message.code());
+        put_adv!(area, u8, write_u8, $field.code())
     })
 }
 
 impl ProtoError {
     pub fn decode<'a>(data: &'a [u8]) -> Result<(ProtoError, &'a [u8]), ProtoError> {
-        match try_get!(data, u8, read_u8, NotEnoughDataForProtoErrorTag) {
-            (1, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqTag),
-            (2, buf) => decode_tag!(buf, InvalidGlobalReqTag),
-            (3, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqAddKeyLen),
-            (4, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqAddKey),
-            (5, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqAddValueLen),
-            (6, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqAddValue),
-            (7, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqLendTimeout),
-            (8, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRepayLendKey),
-            (9, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRepayKeyLen),
-            (10, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRepayKey),
-            (11, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRepayValueLen),
-            (12, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRepayValue),
-            (13, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRepayRepayStatus),
-            (14, buf) => decode_tag!(buf, InvalidGlobalReqRepayRepayStatusTag),
-            (15, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepTag),
-            (16, buf) => decode_tag!(buf, InvalidGlobalRepTag),
-            (17, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepCountCount),
-            (18, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepLentLendKey),
-            (19, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepLentKeyLen),
-            (20, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepLentKey),
-            (21, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepLentValueLen),
-            (22, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepLentValue),
-            (23, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsCount),
-            (24, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsAdd),
-            (25, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsUpdate),
-            (26, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsLookup),
-            (27, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsLend),
-            (28, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsRepay),
-            (29, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsHeartbeat),
-            (30, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsStats),
-            (31, buf) => decode_not_enough!(buf, NotEnoughDataForProtoErrorTag),
-            (32, buf) => decode_tag!(buf, InvalidProtoErrorTag),
-            (33, buf) => decode_not_enough!(buf, NotEnoughDataForProtoErrorRequired),
-            (34, buf) => decode_not_enough!(buf, NotEnoughDataForProtoErrorGiven),
-            (35, buf) => decode_not_enough!(buf, NotEnoughDataForProtoErrorInvalidTag),
-            (36, buf) => {
-                let (key, buf) =
-                    try_get_vec!(buf, NotEnoughDataForProtoErrorDbQueueOutOfSyncKeyLen, NotEnoughDataForProtoErrorDbQueueOutOfSyncKey);
+        let (tag, buf) = try_get!(data, u8, read_u8, ProtoError, Tag);
+        if let Some((message, field)) = legacy_kinds(LEGACY_NOT_ENOUGH_DATA_TAGS, tag) {
+            return decode_not_enough!(buf, message, field);
+        }
+        if let Some((message, field)) = legacy_kinds(LEGACY_INVALID_TAG_TAGS, tag) {
+            return decode_tag!(buf, message, field);
+        }
+        if let Some((message, field)) = legacy_kinds(LEGACY_INVALID_UTF8_TAGS, tag) {
+            return Ok((ProtoError::InvalidUtf8 { message: message, field: field, }, buf));
+        }
+        match tag {
+            PROTO_ERROR_DB_QUEUE_OUT_OF_SYNC_TAG => {
+                let (key, buf) = try_get_vec!(buf, ProtoErrorDbQueueOutOfSync, KeyLen, Key);
                 Ok((ProtoError::DbQueueOutOfSync(key), buf))
             },
-            (37, buf) => decode_not_enough!(buf, NotEnoughDataForProtoErrorDbQueueOutOfSyncKeyLen),
-            (38, buf) => decode_not_enough!(buf, NotEnoughDataForProtoErrorDbQueueOutOfSyncKey),
-            (39, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqUpdateKeyLen),
-            (40, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqUpdateKey),
-            (41, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqUpdateValueLen),
-            (42, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqUpdateValue),
-            (43, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqHeartbeatLendKey),
-            (44, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqHeartbeatKeyLen),
-            (45, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqHeartbeatKey),
-            (46, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqHeartbeatTimeout),
-            (47, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqLookupKeyLen),
-            (48, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqLookupKey),
-            (49, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepValueFoundValueLen),
-            (50, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepValueFoundValue),
-            (51, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqLendMode),
-            (52, buf) => decode_tag!(buf, InvalidGlobalReqLendModeTag),
-            (53, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqAddMode),
-            (54, buf) => decode_tag!(buf, InvalidGlobalReqAddModeTag),
-            (55, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsPing),
-            (56, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalRepStatsRemove),
-            (57, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRemoveKeyLen),
-            (58, buf) => decode_not_enough!(buf, NotEnoughDataForGlobalReqRemoveKey),
-            (tag, _) => return Err(ProtoError::InvalidProtoErrorTag(tag)),
+            PROTO_ERROR_NOT_ENOUGH_DATA_TAG => {
+                let (message, field, buf) = decode_kinds!(buf, LEGACY_NOT_ENOUGH_DATA_TAGS, tag);
+                decode_not_enough!(buf, message, field)
+            },
+            PROTO_ERROR_INVALID_TAG_TAG => {
+                let (message, field, buf) = decode_kinds!(buf, LEGACY_INVALID_TAG_TAGS, tag);
+                decode_tag!(buf, message, field)
+            },
+            PROTO_ERROR_INVALID_UTF8_TAG => {
+                let (message, field, buf) = decode_kinds!(buf, LEGACY_INVALID_UTF8_TAGS, tag);
+                Ok((ProtoError::InvalidUtf8 { message: message, field: field, }, buf))
+            },
+            tag =>
+                Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Tag, tag: tag, }),
         }
     }
 
     pub fn encode_len(&self) -> usize {
         size_of::<u8>() + match self {
-            &ProtoError::NotEnoughDataForGlobalReqTag { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqAddKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqAddKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqAddValueLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqAddValue { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqLendTimeout { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRepayLendKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRepayKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRepayKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRepayValueLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRepayValue { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRepayRepayStatus { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepTag { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepCountCount { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepLentLendKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepLentKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepLentKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepLentValueLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepLentValue { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsPing { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsCount { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsAdd { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsUpdate { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsLookup { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsRemove { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsLend { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsRepay { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsHeartbeat { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepStatsStats { .. } |
-            &ProtoError::NotEnoughDataForProtoErrorTag { .. } |
-            &ProtoError::NotEnoughDataForProtoErrorRequired { .. } |
-            &ProtoError::NotEnoughDataForProtoErrorGiven { .. } |
-            &ProtoError::NotEnoughDataForProtoErrorInvalidTag { .. } |
-            &ProtoError::NotEnoughDataForProtoErrorDbQueueOutOfSyncKeyLen { .. } |
-            &ProtoError::NotEnoughDataForProtoErrorDbQueueOutOfSyncKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqUpdateKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqUpdateKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqUpdateValueLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqUpdateValue { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatLendKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatTimeout { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqLookupKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqLookupKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRemoveKeyLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqRemoveKey { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepValueFoundValueLen { .. } |
-            &ProtoError::NotEnoughDataForGlobalRepValueFoundValue { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqLendMode { .. } |
-            &ProtoError::NotEnoughDataForGlobalReqAddMode { .. } =>
-                size_of::<u32>() + size_of::<u32>(),
-            &ProtoError::InvalidGlobalRepTag(..) |
-            &ProtoError::InvalidGlobalReqTag(..) |
-            &ProtoError::InvalidGlobalReqRepayRepayStatusTag(..) |
-            &ProtoError::InvalidProtoErrorTag(..) |
-            &ProtoError::InvalidGlobalReqLendModeTag(..) |
-            &ProtoError::InvalidGlobalReqAddModeTag(..) =>
-                size_of::<u8>(),
+            &ProtoError::NotEnoughData { message, field, .. } =>
+                kinds_encode_len(LEGACY_NOT_ENOUGH_DATA_TAGS, message, field) + size_of::<u32>() + size_of::<u32>(),
+            &ProtoError::InvalidTag { message, field, .. } =>
+                kinds_encode_len(LEGACY_INVALID_TAG_TAGS, message, field) + size_of::<u8>(),
+            &ProtoError::InvalidUtf8 { message, field, } =>
+                kinds_encode_len(LEGACY_INVALID_UTF8_TAGS, message, field),
             &ProtoError::DbQueueOutOfSync(ref key) => size_of::<u32>() + key.len(),
-
         }
     }
 
     pub fn encode<'b>(&self, area: &'b mut [u8]) -> &'b mut [u8] {
         match self {
-            &ProtoError::NotEnoughDataForGlobalReqTag { required: r, given: g, } => encode_not_enough!(area, 1, r, g),
-            &ProtoError::InvalidGlobalReqTag(tag) => encode_tag!(area, 2, tag),
-            &ProtoError::NotEnoughDataForGlobalReqAddKeyLen { required: r, given: g, } => encode_not_enough!(area, 3, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqAddKey { required: r, given: g, } => encode_not_enough!(area, 4, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqAddValueLen { required: r, given: g, } => encode_not_enough!(area, 5, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqAddValue { required: r, given: g, } => encode_not_enough!(area, 6, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqLendTimeout { required: r, given: g, } => encode_not_enough!(area, 7, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRepayLendKey { required: r, given: g, } => encode_not_enough!(area, 8, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRepayKeyLen { required: r, given: g, } => encode_not_enough!(area, 9, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRepayKey { required: r, given: g, } => encode_not_enough!(area, 10, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRepayValueLen { required: r, given: g, } => encode_not_enough!(area, 11, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRepayValue { required: r, given: g, } => encode_not_enough!(area, 12, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRepayRepayStatus { required: r, given: g, } => encode_not_enough!(area, 13, r, g),
-            &ProtoError::InvalidGlobalReqRepayRepayStatusTag(tag) => encode_tag!(area, 14, tag),
-            &ProtoError::NotEnoughDataForGlobalRepTag { required: r, given: g, } => encode_not_enough!(area, 15, r, g),
-            &ProtoError::InvalidGlobalRepTag(tag) => encode_tag!(area, 16, tag),
-            &ProtoError::NotEnoughDataForGlobalRepCountCount { required: r, given: g, } => encode_not_enough!(area, 17, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepLentLendKey { required: r, given: g, } => encode_not_enough!(area, 18, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepLentKeyLen { required: r, given: g, } => encode_not_enough!(area, 19, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepLentKey { required: r, given: g, } => encode_not_enough!(area, 20, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepLentValueLen { required: r, given: g, } => encode_not_enough!(area, 21, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepLentValue { required: r, given: g, } => encode_not_enough!(area, 22, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsCount { required: r, given: g, } => encode_not_enough!(area, 23, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsAdd { required: r, given: g, } => encode_not_enough!(area, 24, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsUpdate { required: r, given: g, } => encode_not_enough!(area, 25, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsLookup { required: r, given: g, } => encode_not_enough!(area, 26, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsLend { required: r, given: g, } => encode_not_enough!(area, 27, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsRepay { required: r, given: g, } => encode_not_enough!(area, 28, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsHeartbeat { required: r, given: g, } => encode_not_enough!(area, 29, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsStats { required: r, given: g, } => encode_not_enough!(area, 30, r, g),
-            &ProtoError::NotEnoughDataForProtoErrorTag { required: r, given: g, } => encode_not_enough!(area, 31, r, g),
-            &ProtoError::InvalidProtoErrorTag(tag) => encode_tag!(area, 32, tag),
-            &ProtoError::NotEnoughDataForProtoErrorRequired { required: r, given: g, } => encode_not_enough!(area, 33, r, g),
-            &ProtoError::NotEnoughDataForProtoErrorGiven { required: r, given: g, } => encode_not_enough!(area, 34, r, g),
-            &ProtoError::NotEnoughDataForProtoErrorInvalidTag { required: r, given: g, } => encode_not_enough!(area, 35, r, g),
+            &ProtoError::NotEnoughData { message, field, required: r, given: g, } => {
+                let area = match legacy_tag(LEGACY_NOT_ENOUGH_DATA_TAGS, message, field) {
+                    Some(tag) => put_adv!(area, u8, write_u8, tag),
+                    None => encode_kinds!(area, PROTO_ERROR_NOT_ENOUGH_DATA_TAG, message, field),
+                };
+                encode_not_enough!(area, r, g)
+            },
+            &ProtoError::InvalidTag { message, field, tag: invalid_tag, } => {
+                let area = match legacy_tag(LEGACY_INVALID_TAG_TAGS, message, field) {
+                    Some(tag) => put_adv!(area, u8, write_u8, tag),
+                    None => encode_kinds!(area, PROTO_ERROR_INVALID_TAG_TAG, message, field),
+                };
+                put_adv!(area, u8, write_u8, invalid_tag)
+            },
+            &ProtoError::InvalidUtf8 { message, field, } =>
+                match legacy_tag(LEGACY_INVALID_UTF8_TAGS, message, field) {
+                    Some(tag) => put_adv!(area, u8, write_u8, tag),
+                    None => encode_kinds!(area, PROTO_ERROR_INVALID_UTF8_TAG, message, field),
+                },
             &ProtoError::DbQueueOutOfSync(ref key) => {
-                let area = put_adv!(area, u8, write_u8, 36);
+                let area = put_adv!(area, u8, write_u8, PROTO_ERROR_DB_QUEUE_OUT_OF_SYNC_TAG);
                 let area = put_vec_adv!(area, key);
                 area
             },
-            &ProtoError::NotEnoughDataForProtoErrorDbQueueOutOfSyncKeyLen { required: r, given: g, } => encode_not_enough!(area, 37, r, g),
-            &ProtoError::NotEnoughDataForProtoErrorDbQueueOutOfSyncKey { required: r, given: g, } => encode_not_enough!(area, 38, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqUpdateKeyLen { required: r, given: g, } => encode_not_enough!(area, 39, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqUpdateKey { required: r, given: g, } => encode_not_enough!(area, 40, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqUpdateValueLen { required: r, given: g, } => encode_not_enough!(area, 41, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqUpdateValue { required: r, given: g, } => encode_not_enough!(area, 42, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatLendKey { required: r, given: g, } => encode_not_enough!(area, 43, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatKeyLen { required: r, given: g, } => encode_not_enough!(area, 44, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatKey { required: r, given: g, } => encode_not_enough!(area, 45, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqHeartbeatTimeout { required: r, given: g, } => encode_not_enough!(area, 46, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqLookupKeyLen { required: r, given: g, } => encode_not_enough!(area, 47, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqLookupKey { required: r, given: g, } => encode_not_enough!(area, 48, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepValueFoundValueLen { required: r, given: g, } => encode_not_enough!(area, 49, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepValueFoundValue { required: r, given: g, } => encode_not_enough!(area, 50, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqLendMode { required: r, given: g, } => encode_not_enough!(area, 51, r, g),
-            &ProtoError::InvalidGlobalReqLendModeTag(tag) => encode_tag!(area, 52, tag),
-            &ProtoError::NotEnoughDataForGlobalReqAddMode { required: r, given: g, } => encode_not_enough!(area, 53, r, g),
-            &ProtoError::InvalidGlobalReqAddModeTag(tag) => encode_tag!(area, 54, tag),
-            &ProtoError::NotEnoughDataForGlobalRepStatsPing { required: r, given: g, } => encode_not_enough!(area, 55, r, g),
-            &ProtoError::NotEnoughDataForGlobalRepStatsRemove { required: r, given: g, } => encode_not_enough!(area, 56, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRemoveKeyLen { required: r, given: g, } => encode_not_enough!(area, 57, r, g),
-            &ProtoError::NotEnoughDataForGlobalReqRemoveKey { required: r, given: g, } => encode_not_enough!(area, 58, r, g),
+        }
+    }
+
+    pub fn field_path(&self) -> Option<String> {
+        match self {
+            &ProtoError::NotEnoughData { message, field, .. } |
+            &ProtoError::InvalidTag { message, field, .. } |
+            &ProtoError::InvalidUtf8 { message, field, } =>
+                Some(format!("{}.{}", message.name(), field.name())),
+            &ProtoError::DbQueueOutOfSync(..) =>
+                None,
         }
     }
 }
 
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ProtoError::NotEnoughData { message, field, required, given, } =>
+                write!(f, "not enough data for `{}.{}`: {} bytes required, {} given", message.name(), field.name(), required, given),
+            &ProtoError::InvalidTag { message, field, tag, } =>
+                write!(f, "invalid tag {} for `{}.{}`", tag, message.name(), field.name()),
+            &ProtoError::InvalidUtf8 { message, field, } =>
+                write!(f, "invalid UTF-8 string in `{}.{}`", message.name(), field.name()),
+            &ProtoError::DbQueueOutOfSync(ref key) =>
+                write!(f, "kv database and queue are out of sync for key {:?}", String::from_utf8_lossy(key)),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
 #[cfg(test)]
 mod test {
-    use std::sync::Arc;
-    use super::{Key, Value, RepayStatus, LendMode, AddMode, GlobalReq, GlobalRep, ProtoError};
+    use std::{
+        sync::Arc,
+        convert::TryFrom,
+    };
+    use super::{Key, Value, ServerErrorCode, UnknownCode, RepayStatus, LendMode, AddMode, AuthMechanism, Permission, Permissions, Idempotency, GlobalReq, GlobalRep, ProtoError, MessageKind, FieldKind};
 
     macro_rules! defassert_encode_decode {
         ($name:ident, $ty:ty, $class:ident) => (fn $name(r: $ty) {
@@ -784,8 +827,8 @@ mod test {
     defassert_encode_decode!(assert_encode_decode_rep, GlobalRep, GlobalRep);
 
     fn dummy_key_value() -> (Key, Value) {
-        (Arc::new("some key".as_bytes().to_owned()),
-         Arc::new("some value".as_bytes().to_owned()))
+        (Arc::from("some key".as_bytes()),
+         Arc::from("some value".as_bytes()))
     }
 
     #[test]
@@ -878,6 +921,23 @@ mod test {
         assert_encode_decode_req(GlobalReq::Terminate);
     }
 
+    #[test]
+    fn globalreq_info() {
+        assert_encode_decode_req(GlobalReq::Info);
+    }
+
+    #[test]
+    fn globalreq_auth_token() {
+        let (_, value) = dummy_key_value();
+        assert_encode_decode_req(GlobalReq::Auth { mechanism: AuthMechanism::Token, payload: value, });
+    }
+
+    #[test]
+    fn globalreq_auth_hmacsha256() {
+        let (_, value) = dummy_key_value();
+        assert_encode_decode_req(GlobalReq::Auth { mechanism: AuthMechanism::HmacSha256, payload: value, });
+    }
+
     #[test]
     fn globalrep_pong() {
         assert_encode_decode_rep(GlobalRep::Pong);
@@ -971,189 +1031,309 @@ mod test {
         assert_encode_decode_rep(GlobalRep::Terminated);
     }
 
+    #[test]
+    fn globalrep_infogot() {
+        assert_encode_decode_rep(GlobalRep::InfoGot {
+            server_version: "spiderq 0.3.0".to_string(),
+            proto_version: 177,
+            uptime_ms: 277,
+            db_path: "/var/lib/spiderq".to_string(),
+            max_key_len: 377,
+            max_value_len: 477,
+            features: vec!["stats".to_string(), "info".to_string()],
+        });
+    }
+
+    #[test]
+    fn globalrep_infogot_no_features() {
+        assert_encode_decode_rep(GlobalRep::InfoGot {
+            server_version: String::new(),
+            proto_version: 177,
+            uptime_ms: 277,
+            db_path: String::new(),
+            max_key_len: 0,
+            max_value_len: 0,
+            features: vec![],
+        });
+    }
+
+    #[test]
+    fn globalrep_authok() {
+        assert_encode_decode_rep(GlobalRep::AuthOk);
+    }
+
+    #[test]
+    fn globalrep_authfailed() {
+        assert_encode_decode_rep(GlobalRep::AuthFailed);
+    }
+
+    #[test]
+    fn globalrep_authchallenge() {
+        let (_, value) = dummy_key_value();
+        assert_encode_decode_rep(GlobalRep::AuthChallenge(value));
+    }
+
+    #[test]
+    fn globalrep_unauthenticated() {
+        assert_encode_decode_rep(GlobalRep::Unauthenticated);
+    }
+
+    #[test]
+    fn globalrep_forbidden() {
+        assert_encode_decode_rep(GlobalRep::Forbidden);
+    }
+
+    #[test]
+    fn globalrep_servererror() {
+        assert_encode_decode_rep(GlobalRep::ServerError { code: ServerErrorCode::DiskFull, message: "no space left on device".to_string(), });
+    }
+
+    #[test]
+    fn globalrep_servererror_unknown_code() {
+        assert_encode_decode_rep(GlobalRep::ServerError { code: ServerErrorCode::from_code(177), message: String::new(), });
+    }
+
+    #[test]
+    fn globalrep_servererror_not_enough_data() {
+        assert_eq!(
+            GlobalRep::decode(&[26, 0, 2, 0, 0, 0, 5, b'd']),
+            Err(ProtoError::NotEnoughData { message: MessageKind::GlobalRepServerError, field: FieldKind::Message, required: 5, given: 1, }));
+    }
+
+    #[test]
+    fn server_error_codes() {
+        for code in 0 .. 16 {
+            assert_eq!(ServerErrorCode::from_code(code).code(), code);
+            assert_eq!(UnknownCode::try_from(code).is_ok(), !(1 ..= 7).contains(&code));
+            assert_encode_decode_rep(GlobalRep::ServerError { code: ServerErrorCode::from_code(code), message: String::new(), });
+        }
+        assert_eq!(ServerErrorCode::from_code(3), ServerErrorCode::KeyTooLarge);
+        assert!(ServerErrorCode::ShuttingDown.is_retryable());
+        assert!(!ServerErrorCode::ValueTooLarge.is_retryable());
+        assert!(!ServerErrorCode::from_code(177).is_retryable());
+    }
+
+    #[test]
+    fn required_permission() {
+        let (key, value) = dummy_key_value();
+        assert_eq!(GlobalReq::Ping.required_permission(), None);
+        assert_eq!(GlobalReq::Update(key.clone(), value).required_permission(), Some(Permission::Producer));
+        assert_eq!(GlobalReq::Lend { timeout: 177, mode: LendMode::Poll, }.required_permission(), Some(Permission::Worker));
+        assert_eq!(GlobalReq::Stats.required_permission(), Some(Permission::Reader));
+        assert_eq!(GlobalReq::Remove(key).required_permission(), Some(Permission::Admin));
+    }
+
+    #[test]
+    fn idempotency() {
+        let (key, value) = dummy_key_value();
+        assert_eq!(GlobalReq::Lookup(key.clone()).idempotency(), Idempotency::ReadOnly);
+        assert_eq!(GlobalReq::Add { key: key.clone(), value: value.clone(), mode: AddMode::Tail, }.idempotency(), Idempotency::Idempotent);
+        assert_eq!(GlobalReq::Repay { lend_key: 177, key: key, value: value, status: RepayStatus::Drop, }.idempotency(), Idempotency::Idempotent);
+        assert_eq!(GlobalReq::Lend { timeout: 177, mode: LendMode::Poll, }.idempotency(), Idempotency::NonIdempotent);
+        assert!(GlobalReq::Stats.idempotency().is_retry_safe());
+        assert!(!GlobalReq::Terminate.idempotency().is_retry_safe());
+    }
+
+    #[test]
+    fn permissions_allows() {
+        let worker = Permissions::from(Permission::Worker).with(Permission::Reader);
+        assert!(worker.allows(&GlobalReq::Ping));
+        assert!(worker.allows(&GlobalReq::Count));
+        assert!(worker.allows(&GlobalReq::Lend { timeout: 177, mode: LendMode::Block, }));
+        assert!(!worker.allows(&GlobalReq::Terminate));
+        assert!(!Permissions::NONE.allows(&GlobalReq::Count));
+        assert!(Permissions::ALL.allows(&GlobalReq::Flush));
+    }
+
+    #[test]
+    fn globalrep_infogot_invalid_utf8() {
+        let frame = [20, 0, 0, 0, 1, 0xFF];
+        assert_eq!(GlobalRep::decode(&frame), Err(ProtoError::InvalidUtf8 { message: MessageKind::GlobalRepInfoGot, field: FieldKind::ServerVersion, }));
+    }
+
     #[test]
     fn globalrep_error_notenoughdataforglobalreqtag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqTag { required: 177, given: 277, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReq, field: FieldKind::Tag, required: 177, given: 277, }));
     }
 
     #[test]
     fn globalrep_error_invalidglobalreqtag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidGlobalReqTag(177)));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::GlobalReq, field: FieldKind::Tag, tag: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqaddkeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqAddKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqaddkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqAddKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqaddvaluelen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqAddValueLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::ValueLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqaddvalue() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqAddValue { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::Value, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqlendtimeout() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqLendTimeout { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqLend, field: FieldKind::Timeout, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqrepaylendkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRepayLendKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRepay, field: FieldKind::LendKey, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqrepaykeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRepayKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRepay, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqrepaykey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRepayKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRepay, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqrepayvaluelen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRepayValueLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRepay, field: FieldKind::ValueLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqrepayvalue() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRepayValue { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRepay, field: FieldKind::Value, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqrepayrepaystatus() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRepayRepayStatus { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRepay, field: FieldKind::Status, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_invalidglobalreqrepayrepaystatustag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidGlobalReqRepayRepayStatusTag(177)));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::GlobalReqRepay, field: FieldKind::Status, tag: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreptag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepTag { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRep, field: FieldKind::Tag, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_invalidglobalreptag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidGlobalRepTag(177)));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::GlobalRep, field: FieldKind::Tag, tag: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepcountcount() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepCountCount { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepCounted, field: FieldKind::Count, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreplentlendkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepLentLendKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepLent, field: FieldKind::LendKey, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreplentkeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepLentKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepLent, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreplentkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepLentKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepLent, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreplentvaluelen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepLentValueLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepLent, field: FieldKind::ValueLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreplentvalue() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepLentValue { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepLent, field: FieldKind::Value, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatscount() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsCount { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Count, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatsadd() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsAdd { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Add, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatsupdate() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsUpdate { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Update, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatslookup() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsLookup { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Lookup, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatslend() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsLend { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Lend, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatsrepay() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsRepay { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Repay, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatsheartbeat() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsHeartbeat { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Heartbeat, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrepstatsstats() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalRepStatsStats { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepStatsGot, field: FieldKind::Stats, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforprotoerrortag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForProtoErrorTag { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::ProtoError, field: FieldKind::Tag, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_invalidprotoerrortag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidProtoErrorTag(177)));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Tag, tag: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforprotoerrorrequired() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForProtoErrorRequired { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::ProtoError, field: FieldKind::Required, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforprotoerrorgiven() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForProtoErrorGiven { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::ProtoError, field: FieldKind::Given, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforprotoerrorinvalidtag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForProtoErrorInvalidTag { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::ProtoError, field: FieldKind::InvalidTag, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforprotoerrordbqueueoutofsynckey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForProtoErrorDbQueueOutOfSyncKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::ProtoErrorDbQueueOutOfSync, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforprotoerrordbqueueoutofsynckeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForProtoErrorDbQueueOutOfSyncKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::ProtoErrorDbQueueOutOfSync, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
@@ -1164,81 +1344,263 @@ mod test {
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrequpdatekeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqUpdateKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqUpdate, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrequpdatekey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqUpdateKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqUpdate, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrequpdatevaluelen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqUpdateValueLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqUpdate, field: FieldKind::ValueLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalrequpdatevalue() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqUpdateValue { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqUpdate, field: FieldKind::Value, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqheartbeatlendkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqHeartbeatLendKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqHeartbeat, field: FieldKind::LendKey, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqheartbeatkeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqHeartbeatKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqHeartbeat, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqheartbeatkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqHeartbeatKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqHeartbeat, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqheartbeattimeout() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqHeartbeatTimeout { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqHeartbeat, field: FieldKind::Timeout, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_invalidglobalreqlendmodetag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidGlobalReqLendModeTag(177)));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::GlobalReqLend, field: FieldKind::Mode, tag: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqlendmode() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqLendMode { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqLend, field: FieldKind::Mode, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_invalidglobalreqaddmodetag() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidGlobalReqAddModeTag(177)));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::GlobalReqAdd, field: FieldKind::Mode, tag: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqaddmode() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqAddMode { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::Mode, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqlookupkeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqLookupKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqLookup, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqlookupkey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqLookupKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqLookup, field: FieldKind::Key, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqremovekeylen() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRemoveKeyLen { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRemove, field: FieldKind::KeyLen, required: 177, given: 177, }));
     }
 
     #[test]
     fn globalrep_error_notenoughdataforglobalreqremovekey() {
-        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughDataForGlobalReqRemoveKey { required: 177, given: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqRemove, field: FieldKind::Key, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotserverversionlen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::ServerVersionLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotserverversion() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::ServerVersion, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_invalidglobalrepinfogotserverversionutf8() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidUtf8 { message: MessageKind::GlobalRepInfoGot, field: FieldKind::ServerVersion, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotprotoversion() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::ProtoVersion, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotuptimems() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::UptimeMs, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotdbpathlen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::DbPathLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotdbpath() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::DbPath, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_invalidglobalrepinfogotdbpathutf8() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidUtf8 { message: MessageKind::GlobalRepInfoGot, field: FieldKind::DbPath, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotmaxkeylen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::MaxKeyLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotmaxvaluelen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::MaxValueLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotfeaturescount() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::FeaturesCount, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotfeaturelen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::FeatureLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepinfogotfeature() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepInfoGot, field: FieldKind::Feature, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_invalidglobalrepinfogotfeatureutf8() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidUtf8 { message: MessageKind::GlobalRepInfoGot, field: FieldKind::Feature, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalreqauthmechanism() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAuth, field: FieldKind::Mechanism, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_invalidglobalreqauthmechanismtag() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::GlobalReqAuth, field: FieldKind::Mechanism, tag: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalreqauthpayloadlen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAuth, field: FieldKind::PayloadLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalreqauthpayload() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalReqAuth, field: FieldKind::Payload, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepauthchallengenoncelen() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepAuthChallenge, field: FieldKind::NonceLen, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn globalrep_error_notenoughdataforglobalrepauthchallengenonce() {
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::NotEnoughData { message: MessageKind::GlobalRepAuthChallenge, field: FieldKind::Nonce, required: 177, given: 177, }));
+    }
+
+    #[test]
+    fn protoerror_display_not_enough_data() {
+        let err = ProtoError::NotEnoughData { message: MessageKind::GlobalRepLent, field: FieldKind::ValueLen, required: 4, given: 2, };
+        assert_eq!(err.field_path(), Some("GlobalRep::Lent.value_len".to_string()));
+        assert_eq!(err.to_string(), "not enough data for `GlobalRep::Lent.value_len`: 4 bytes required, 2 given");
+    }
+
+    #[test]
+    fn protoerror_display_invalid_tag() {
+        let err = ProtoError::InvalidTag { message: MessageKind::GlobalReqLend, field: FieldKind::Mode, tag: 7, };
+        assert_eq!(err.field_path(), Some("GlobalReq::Lend.mode".to_string()));
+        assert_eq!(err.to_string(), "invalid tag 7 for `GlobalReq::Lend.mode`");
+    }
+
+    #[test]
+    fn protoerror_display_invalid_utf8() {
+        let err = ProtoError::InvalidUtf8 { message: MessageKind::GlobalRepInfoGot, field: FieldKind::DbPath, };
+        assert_eq!(err.to_string(), "invalid UTF-8 string in `GlobalRep::InfoGot.db_path`");
+    }
+
+    #[test]
+    fn protoerror_display_db_queue_out_of_sync() {
+        let (key, _) = dummy_key_value();
+        let err = ProtoError::DbQueueOutOfSync(key);
+        assert_eq!(err.field_path(), None);
+        assert_eq!(err.to_string(), "kv database and queue are out of sync for key \"some key\"");
+    }
+
+    #[test]
+    fn protoerror_boxed_error() {
+        fn decode(frame: &[u8]) -> Result<GlobalReq, Box<dyn std::error::Error>> {
+            let (req, _) = GlobalReq::decode(frame)?;
+            Ok(req)
+        }
+        assert_eq!(decode(&[2, 0, 0]).unwrap_err().to_string(), "not enough data for `GlobalReq::Add.key_len`: 4 bytes required, 2 given");
+    }
+
+    #[test]
+    fn protoerror_legacy_tag_encoding() {
+        let err = ProtoError::NotEnoughData { message: MessageKind::GlobalReqAdd, field: FieldKind::KeyLen, required: 4, given: 2, };
+        let mut area = vec![0; err.encode_len()];
+        err.encode(&mut area);
+        assert_eq!(area, [3, 0, 0, 0, 4, 0, 0, 0, 2]);
+        let err = ProtoError::InvalidTag { message: MessageKind::GlobalReqLend, field: FieldKind::Mode, tag: 7, };
+        let mut area = vec![0; err.encode_len()];
+        err.encode(&mut area);
+        assert_eq!(area, [52, 7]);
+    }
+
+    #[test]
+    fn protoerror_generic_encoding() {
+        let err = ProtoError::NotEnoughData { message: MessageKind::GlobalReqLookup, field: FieldKind::Value, required: 4, given: 2, };
+        let mut area = vec![0; err.encode_len()];
+        err.encode(&mut area);
+        assert_eq!(area, [79, MessageKind::GlobalReqLookup.code(), FieldKind::Value.code(), 0, 0, 0, 4, 0, 0, 0, 2]);
+        assert_encode_decode_rep(GlobalRep::Error(err));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Message, tag: 177, }));
+        assert_encode_decode_rep(GlobalRep::Error(ProtoError::InvalidUtf8 { message: MessageKind::GlobalReqAdd, field: FieldKind::Key, }));
+    }
+
+    #[test]
+    fn protoerror_generic_encoding_non_canonical() {
+        let frame = [79, MessageKind::GlobalReqAdd.code(), FieldKind::KeyLen.code(), 0, 0, 0, 4, 0, 0, 0, 2];
+        assert_eq!(ProtoError::decode(&frame), Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Tag, tag: 79, }));
+    }
+
+    #[test]
+    fn protoerror_generic_encoding_invalid_kind() {
+        assert_eq!(ProtoError::decode(&[80, 0, 1, 7]), Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Message, tag: 0, }));
+        assert_eq!(ProtoError::decode(&[80, 1, 0, 7]), Err(ProtoError::InvalidTag { message: MessageKind::ProtoError, field: FieldKind::Field, tag: 0, }));
+    }
+
+    #[test]
+    fn kinds_codes_roundtrip() {
+        for message in MessageKind::ALL {
+            assert_eq!(MessageKind::from_code(message.code()), Some(*message));
+        }
+        for field in FieldKind::ALL {
+            assert_eq!(FieldKind::from_code(field.code()), Some(*field));
+        }
     }
 }
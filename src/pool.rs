@@ -0,0 +1,348 @@
+//! Bounded pool of `SyncClient` connections.
+//!
+//! Idle connections are checked with `Ping` on checkout, and every checkout
+//! and release closes all the idle connections older than `idle_timeout`,
+//! not only the one about to be reused. Connections which
+//! failed an i/o operation or got an undecodable reply (see
+//! `SyncClient::is_broken`) are evicted instead of being returned to the pool.
+
+use std::{
+    io,
+    fmt,
+    ops::{
+        Deref,
+        DerefMut,
+    },
+    net::{
+        TcpStream,
+        SocketAddr,
+    },
+    sync::{
+        Mutex,
+        Condvar,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use super::client::{
+    SyncClient,
+    Transport,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PoolConfig {
+    /// Maximum number of connections, both idle and checked out.
+    pub max_size: usize,
+    /// Idle connections older than this are closed instead of being reused.
+    pub idle_timeout: Duration,
+    /// How long a checkout waits for a connection when the pool is exhausted, `None` waits forever.
+    pub checkout_timeout: Option<Duration>,
+    /// Whether idle connections are checked with `Ping` on checkout.
+    pub health_check: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            max_size: 16,
+            idle_timeout: Duration::from_secs(60),
+            checkout_timeout: Some(Duration::from_secs(5)),
+            health_check: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PoolError {
+    Connect(io::Error),
+    /// No connection became available within `PoolConfig::checkout_timeout`.
+    Timeout,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &PoolError::Connect(ref err) => write!(f, "connect failed: {}", err),
+            &PoolError::Timeout => write!(f, "timed out waiting for a pooled connection"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+struct Slots<S> {
+    idle: Vec<(SyncClient<S>, Instant)>,
+    /// Connections either idle, checked out or being connected.
+    total: usize,
+}
+
+impl<S> Slots<S> {
+    /// Closes idle connections older than `idle_timeout`, returns how many.
+    fn expire(&mut self, idle_timeout: Duration) -> usize {
+        let before = self.idle.len();
+        self.idle.retain(|&(_, since)| since.elapsed() < idle_timeout);
+        let expired = before - self.idle.len();
+        self.total -= expired;
+        expired
+    }
+}
+
+type Connect<S> = Box<dyn Fn() -> io::Result<SyncClient<S>> + Send + Sync>;
+
+pub struct Pool<S> {
+    config: PoolConfig,
+    connect: Connect<S>,
+    slots: Mutex<Slots<S>>,
+    released: Condvar,
+}
+
+impl Pool<TcpStream> {
+    /// Pool of TCP connections using `io_timeout` for connecting, reads and writes.
+    pub fn tcp(addr: SocketAddr, io_timeout: Option<Duration>, config: PoolConfig) -> Pool<TcpStream> {
+        Pool::new(config, move || SyncClient::connect(addr, io_timeout))
+    }
+}
+
+impl<S> Pool<S> where S: Transport {
+    pub fn new<F>(config: PoolConfig, connect: F) -> Pool<S> where F: Fn() -> io::Result<SyncClient<S>> + Send + Sync + 'static {
+        Pool {
+            config: config,
+            connect: Box::new(connect),
+            slots: Mutex::new(Slots { idle: Vec::new(), total: 0, }),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    /// Number of idle and checked out connections.
+    pub fn size(&self) -> usize {
+        self.slots.lock().unwrap().total
+    }
+
+    pub fn idle(&self) -> usize {
+        self.slots.lock().unwrap().idle.len()
+    }
+
+    pub fn get(&self) -> Result<PooledClient<'_, S>, PoolError> {
+        let deadline = self.config.checkout_timeout.map(|timeout| Instant::now() + timeout);
+        let mut slots = self.slots.lock().unwrap();
+        loop {
+            slots.expire(self.config.idle_timeout);
+            if let Some((mut client, _)) = slots.idle.pop() {
+                if self.config.health_check {
+                    drop(slots);
+                    let healthy = client.ping().is_ok();
+                    slots = self.slots.lock().unwrap();
+                    if !healthy {
+                        slots.total -= 1;
+                        self.released.notify_one();
+                        continue;
+                    }
+                }
+                return Ok(PooledClient { pool: self, client: Some(client), });
+            }
+            if slots.total < self.config.max_size {
+                slots.total += 1;
+                drop(slots);
+                return match (self.connect)() {
+                    Ok(client) => Ok(PooledClient { pool: self, client: Some(client), }),
+                    Err(err) => {
+                        self.evict();
+                        Err(PoolError::Connect(err))
+                    },
+                };
+            }
+            slots = match deadline {
+                None => self.released.wait(slots).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(PoolError::Timeout);
+                    }
+                    self.released.wait_timeout(slots, deadline - now).unwrap().0
+                },
+            };
+        }
+    }
+
+    fn evict(&self) {
+        self.slots.lock().unwrap().total -= 1;
+        self.released.notify_one();
+    }
+
+    fn release(&self, client: SyncClient<S>) {
+        if client.is_broken() {
+            return self.evict();
+        }
+        let mut slots = self.slots.lock().unwrap();
+        let expired = slots.expire(self.config.idle_timeout);
+        slots.idle.push((client, Instant::now()));
+        if expired > 0 {
+            self.released.notify_all();
+        } else {
+            self.released.notify_one();
+        }
+    }
+}
+
+/// Checked out connection, returned to the pool on drop.
+pub struct PooledClient<'a, S> where S: Transport {
+    pool: &'a Pool<S>,
+    client: Option<SyncClient<S>>,
+}
+
+impl<'a, S> PooledClient<'a, S> where S: Transport {
+    /// Closes the connection instead of returning it to the pool.
+    pub fn discard(mut self) {
+        self.client.take();
+        self.pool.evict();
+    }
+}
+
+impl<'a, S> Deref for PooledClient<'a, S> where S: Transport {
+    type Target = SyncClient<S>;
+
+    fn deref(&self) -> &SyncClient<S> {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl<'a, S> DerefMut for PooledClient<'a, S> where S: Transport {
+    fn deref_mut(&mut self) -> &mut SyncClient<S> {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl<'a, S> Drop for PooledClient<'a, S> where S: Transport {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.release(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        thread,
+        time::Duration,
+        net::{TcpListener, SocketAddr},
+    };
+    use super::super::GlobalReq;
+    use super::super::client::{SyncClient, ClientError, read_frame, write_frame, DEFAULT_MAX_FRAME_LEN};
+    use super::{Pool, PoolConfig, PoolError};
+
+    /// Replies `Pong` to `Ping`, garbage to `Lookup` and closes the connection on `Terminate`.
+    fn server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    while let Ok(frame) = read_frame(&mut stream, DEFAULT_MAX_FRAME_LEN) {
+                        let reply = match GlobalReq::decode(&frame).unwrap().0 {
+                            GlobalReq::Ping => vec![17],
+                            GlobalReq::Lookup(..) => vec![0xff],
+                            GlobalReq::Terminate => break,
+                            req => panic!("unexpected request {:?}", req),
+                        };
+                        write_frame(&mut stream, &reply).unwrap();
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    fn pool(config: PoolConfig) -> (Pool<std::net::TcpStream>, Arc<AtomicUsize>) {
+        let addr = server();
+        let connects = Arc::new(AtomicUsize::new(0));
+        let counter = connects.clone();
+        let pool = Pool::new(config, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            SyncClient::connect(addr, Some(Duration::from_secs(5)))
+        });
+        (pool, connects)
+    }
+
+    #[test]
+    fn reuse_and_size_bound() {
+        let (pool, connects) = pool(PoolConfig { max_size: 2, checkout_timeout: Some(Duration::from_millis(20)), ..PoolConfig::default() });
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        match pool.get() {
+            Err(PoolError::Timeout) => (),
+            other => panic!("unexpected checkout result {:?}", other.map(|_| ())),
+        }
+        drop(first);
+        let mut third = pool.get().unwrap();
+        third.ping().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.size(), 2);
+        drop(second);
+        drop(third);
+        assert_eq!(pool.idle(), 2);
+    }
+
+    #[test]
+    fn idle_timeout() {
+        {
+            let (pool, connects) = pool(PoolConfig { idle_timeout: Duration::from_millis(10), ..PoolConfig::default() });
+            drop(pool.get().unwrap());
+            thread::sleep(Duration::from_millis(30));
+            drop(pool.get().unwrap());
+            assert_eq!(connects.load(Ordering::SeqCst), 2);
+            assert_eq!(pool.size(), 1);
+        }
+        // only the older of two idle connections has expired, it is closed even though the newer one is reused
+        let (pool, connects) = pool(PoolConfig { idle_timeout: Duration::from_millis(50), ..PoolConfig::default() });
+        let (older, newer) = (pool.get().unwrap(), pool.get().unwrap());
+        drop(older);
+        thread::sleep(Duration::from_millis(70));
+        drop(newer);
+        let client = pool.get().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!((pool.size(), pool.idle()), (1, 0));
+        drop(client);
+    }
+
+    #[test]
+    fn desync_eviction() {
+        let (pool, connects) = pool(PoolConfig::default());
+        {
+            let mut client = pool.get().unwrap();
+            match client.lookup(Arc::from(&b"cat"[..])) {
+                Err(ClientError::Decode(..)) => (),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+        assert_eq!(pool.size(), 0);
+        pool.get().unwrap().ping().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn health_check() {
+        let (pool, connects) = pool(PoolConfig::default());
+        {
+            let mut client = pool.get().unwrap();
+            // the server closes the connection without a reply; nobody notices until the next use
+            write_frame(client.get_mut(), &[8]).unwrap();
+        }
+        thread::sleep(Duration::from_millis(20));
+        pool.get().unwrap().ping().unwrap();
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.size(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt::{
+    self,
+    Write,
+};
+
+use super::GlobalRep;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+pub const METRIC_UP: &str = "spiderq_up";
+pub const METRIC_REQUESTS_TOTAL: &str = "spiderq_requests_total";
+pub const METRIC_QUEUE_TASKS: &str = "spiderq_queue_tasks";
+
+const FAMILIES: [(&str, &str, &str); 2] = [
+    (METRIC_REQUESTS_TOTAL, "Count of requests processed after server startup.", "counter"),
+    (METRIC_QUEUE_TASKS, "Pending tasks count in queue.", "gauge"),
+];
+
+fn family(rep: &GlobalRep) -> Option<&'static str> {
+    match rep {
+        &GlobalRep::StatsGot { .. } => Some(METRIC_REQUESTS_TOTAL),
+        &GlobalRep::Counted(..) => Some(METRIC_QUEUE_TASKS),
+        _ => None,
+    }
+}
+
+/// Renders `reps` as a single snapshot: a series may only appear once, so
+/// of several replies of the same metric family the last one wins. Every
+/// family gets a single `# HELP`/`# TYPE` header.
+pub fn render<'a, I>(reps: I) -> String where I: IntoIterator<Item = &'a GlobalRep> {
+    let reps: Vec<_> = reps.into_iter().collect();
+    let mut out = String::new();
+    for &(name, help, kind) in &FAMILIES {
+        if let Some(rep) = reps.iter().rev().find(|rep| family(rep) == Some(name)) {
+            write_header(&mut out, name, help, kind).unwrap();
+            write_rep(&mut out, rep).unwrap();
+        }
+    }
+    out
+}
+
+pub fn write_header<W>(w: &mut W, name: &str, help: &str, kind: &str) -> fmt::Result where W: Write {
+    writeln!(w, "# HELP {} {}", name, help)?;
+    writeln!(w, "# TYPE {} {}", name, kind)
+}
+
+/// Writes the samples of `rep` without the family header, see `write_header`.
+pub fn write_rep<W>(w: &mut W, rep: &GlobalRep) -> fmt::Result where W: Write {
+    match rep {
+        &GlobalRep::StatsGot { ping, count, add, update, lookup, remove, lend, repay, heartbeat, stats, } => {
+            for &(request, value) in &[
+                ("ping", ping),
+                ("count", count),
+                ("add", add),
+                ("update", update),
+                ("lookup", lookup),
+                ("remove", remove),
+                ("lend", lend),
+                ("repay", repay),
+                ("heartbeat", heartbeat),
+                ("stats", stats),
+            ] {
+                writeln!(w, "{}{{request=\"{}\"}} {}", METRIC_REQUESTS_TOTAL, request, value)?;
+            }
+            Ok(())
+        },
+        &GlobalRep::Counted(count) =>
+            writeln!(w, "{} {}", METRIC_QUEUE_TASKS, count),
+        _ =>
+            Ok(()),
+    }
+}
+
+pub fn write_up<W>(w: &mut W, up: bool) -> fmt::Result where W: Write {
+    write_header(w, METRIC_UP, "Whether the last spiderq poll succeeded.", "gauge")?;
+    writeln!(w, "{} {}", METRIC_UP, if up { 1 } else { 0 })
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::GlobalRep;
+    use super::{render, write_up};
+
+    #[test]
+    fn stats_got() {
+        let rep = GlobalRep::StatsGot {
+            ping: 1, count: 2, add: 3, update: 4, lookup: 5, remove: 6, lend: 7, repay: 8, heartbeat: 9, stats: 10,
+        };
+        assert_eq!(render(&[rep]), "\
+# HELP spiderq_requests_total Count of requests processed after server startup.
+# TYPE spiderq_requests_total counter
+spiderq_requests_total{request=\"ping\"} 1
+spiderq_requests_total{request=\"count\"} 2
+spiderq_requests_total{request=\"add\"} 3
+spiderq_requests_total{request=\"update\"} 4
+spiderq_requests_total{request=\"lookup\"} 5
+spiderq_requests_total{request=\"remove\"} 6
+spiderq_requests_total{request=\"lend\"} 7
+spiderq_requests_total{request=\"repay\"} 8
+spiderq_requests_total{request=\"heartbeat\"} 9
+spiderq_requests_total{request=\"stats\"} 10
+");
+    }
+
+    #[test]
+    fn counted() {
+        assert_eq!(render(&[GlobalRep::Counted(17)]), "\
+# HELP spiderq_queue_tasks Pending tasks count in queue.
+# TYPE spiderq_queue_tasks gauge
+spiderq_queue_tasks 17
+");
+    }
+
+    #[test]
+    fn single_snapshot() {
+        let stats = |ping| GlobalRep::StatsGot {
+            ping: ping, count: 0, add: 0, update: 0, lookup: 0, remove: 0, lend: 0, repay: 0, heartbeat: 0, stats: 0,
+        };
+        let out = render(&[GlobalRep::Counted(1), stats(1), GlobalRep::Counted(2), stats(2)]);
+        assert_eq!(out.matches("# TYPE spiderq_requests_total ").count(), 1);
+        assert_eq!(out.matches("# HELP spiderq_queue_tasks ").count(), 1);
+        assert_eq!(out.matches("spiderq_requests_total{request=\"ping\"}").count(), 1);
+        assert!(out.contains("spiderq_requests_total{request=\"ping\"} 2\n"));
+        assert!(out.starts_with("# HELP spiderq_requests_total "));
+        assert!(out.ends_with("\
+# TYPE spiderq_queue_tasks gauge
+spiderq_queue_tasks 2
+"));
+    }
+
+    #[test]
+    fn other_reps_skipped() {
+        assert_eq!(render(&[GlobalRep::Pong, GlobalRep::Added]), "");
+    }
+
+    #[test]
+    fn up() {
+        let mut out = String::new();
+        write_up(&mut out, false).unwrap();
+        assert_eq!(out, "\
+# HELP spiderq_up Whether the last spiderq poll succeeded.
+# TYPE spiderq_up gauge
+spiderq_up 0
+");
+    }
+}
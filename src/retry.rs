@@ -0,0 +1,265 @@
+//! Retrying requests with exponential backoff.
+//!
+//! A request is sent again only when that can not apply it twice: after a
+//! retryable `ServerError` (see `ServerErrorCode::is_retryable`), when it was
+//! not sent at all, or when it is retry safe according to
+//! `GlobalReq::idempotency` and its reply was lost.
+
+use std::{
+    io,
+    thread,
+    hash::{
+        Hasher,
+        BuildHasher,
+    },
+    collections::hash_map::RandomState,
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+use super::{
+    GlobalReq,
+    GlobalRep,
+};
+use super::client::{
+    ClientError,
+    Transport,
+};
+use super::pool::{
+    Pool,
+    PoolError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Total number of attempts including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every next one.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Whether delays are picked at random between zero and the exponential
+    /// delay ("full jitter"), so that clients failed together do not retry together.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(2),
+            jitter: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Reply(GlobalRep),
+    /// `Repay` got `NotFound` after an attempt whose reply was lost: most
+    /// likely that attempt was applied and the task is already repaid.
+    ProbablyRepaid,
+}
+
+enum Failure {
+    /// The request never reached the server.
+    NotSent(ClientError),
+    Failed(ClientError),
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (starting from 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        let delay = self.base_delay.checked_mul(factor).map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        if self.jitter {
+            delay.mul_f64(random_fraction())
+        } else {
+            delay
+        }
+    }
+
+    /// Sends `req` with `send` until it succeeds, fails in a way that is not
+    /// safe to retry, or `max_attempts` is reached. `send` gets a fresh
+    /// connection for every attempt; `ClientError::Broken` means the request
+    /// was not sent.
+    pub fn run<F>(&self, req: &GlobalReq, mut send: F) -> Result<Outcome, ClientError> where F: FnMut(&GlobalReq) -> Result<GlobalRep, ClientError> {
+        self.attempts(req, |req| send(req).map_err(|err| match err {
+            ClientError::Broken => Failure::NotSent(err),
+            err => Failure::Failed(err),
+        }))
+    }
+
+    /// Same as `run` taking a connection from `pool` for every attempt.
+    pub fn run_pooled<S>(&self, pool: &Pool<S>, req: &GlobalReq) -> Result<Outcome, ClientError> where S: Transport {
+        self.attempts(req, |req| {
+            let mut client = pool.get().map_err(|err| Failure::NotSent(match err {
+                PoolError::Connect(err) => ClientError::Io(err),
+                err @ PoolError::Timeout => ClientError::Io(io::Error::new(io::ErrorKind::TimedOut, err)),
+            }))?;
+            client.request(req).map_err(|err| match err {
+                ClientError::Broken => Failure::NotSent(err),
+                err => Failure::Failed(err),
+            })
+        })
+    }
+
+    fn attempts<F>(&self, req: &GlobalReq, mut send: F) -> Result<Outcome, ClientError> where F: FnMut(&GlobalReq) -> Result<GlobalRep, Failure> {
+        let retry_safe = req.idempotency().is_retry_safe();
+        let mut reply_lost = false;
+        let mut attempt = 1;
+        loop {
+            let last = attempt >= self.max_attempts;
+            match send(req) {
+                Ok(GlobalRep::ServerError { ref code, .. }) if code.is_retryable() && !last =>
+                    (),
+                Ok(GlobalRep::NotFound) if reply_lost && is_repay(req) =>
+                    return Ok(Outcome::ProbablyRepaid),
+                Ok(rep) =>
+                    return Ok(Outcome::Reply(rep)),
+                Err(Failure::NotSent(err)) =>
+                    if last {
+                        return Err(err);
+                    },
+                Err(Failure::Failed(err)) =>
+                    if err.is_desync() && retry_safe && !last {
+                        reply_lost = true;
+                    } else {
+                        return Err(err);
+                    },
+            }
+            thread::sleep(self.backoff(attempt));
+            attempt += 1;
+        }
+    }
+}
+
+fn is_repay(req: &GlobalReq) -> bool {
+    matches!(req, &GlobalReq::Repay { .. })
+}
+
+/// Uniform in `[0, 1)`, good enough for jitter without pulling a rng crate.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_nanos()).unwrap_or(0));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io,
+        sync::Arc,
+        time::Duration,
+    };
+    use super::super::{GlobalReq, GlobalRep, LendMode, RepayStatus, ServerErrorCode, ProtoError, MessageKind, FieldKind};
+    use super::super::client::{ClientError, SyncClient};
+    use super::super::pool::{Pool, PoolConfig};
+    use super::{RetryPolicy, Outcome};
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy { max_attempts: 3, base_delay: Duration::from_millis(1), ..RetryPolicy::default() }
+    }
+
+    fn lost() -> ClientError {
+        ClientError::Io(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+    }
+
+    fn repay() -> GlobalReq {
+        GlobalReq::Repay { lend_key: 1, key: Arc::from(&b"cat"[..]), value: Arc::from(&b"big"[..]), status: RepayStatus::Front, }
+    }
+
+    /// Plays `replies` back and returns the number of attempts made.
+    fn run(req: &GlobalReq, replies: Vec<Result<GlobalRep, ClientError>>) -> (Result<Outcome, ClientError>, usize) {
+        let mut replies = replies.into_iter();
+        let mut attempts = 0;
+        let result = policy().run(req, |_| {
+            attempts += 1;
+            replies.next().unwrap()
+        });
+        (result, attempts)
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy { base_delay: Duration::from_millis(10), max_delay: Duration::from_millis(50), jitter: false, ..RetryPolicy::default() };
+        let delays: Vec<_> = (1 .. 6).map(|retry| policy.backoff(retry).as_millis()).collect();
+        assert_eq!(delays, vec![10, 20, 40, 50, 50]);
+        assert_eq!(policy.backoff(100), Duration::from_millis(50));
+        let policy = RetryPolicy { jitter: true, ..policy };
+        for retry in 1 .. 6 {
+            assert!(policy.backoff(retry) <= Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn lost_reply() {
+        let (result, attempts) = run(&GlobalReq::Count, vec![Err(lost()), Err(lost()), Ok(GlobalRep::Counted(3))]);
+        assert_eq!(result.unwrap(), Outcome::Reply(GlobalRep::Counted(3)));
+        assert_eq!(attempts, 3);
+        let (result, attempts) = run(&GlobalReq::Count, vec![Err(lost()), Err(lost()), Err(lost())]);
+        assert!(matches!(result, Err(ClientError::Io(..))));
+        assert_eq!(attempts, 3);
+        // another lend would lend another task
+        let lend = GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, };
+        let (result, attempts) = run(&lend, vec![Err(lost())]);
+        assert!(matches!(result, Err(ClientError::Io(..))));
+        assert_eq!(attempts, 1);
+        let (result, attempts) = run(&lend, vec![Err(ClientError::Broken), Ok(GlobalRep::QueueEmpty)]);
+        assert_eq!(result.unwrap(), Outcome::Reply(GlobalRep::QueueEmpty));
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn server_errors() {
+        let overloaded = || GlobalRep::ServerError { code: ServerErrorCode::Overloaded, message: String::new(), };
+        let lend = GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, };
+        let (result, attempts) = run(&lend, vec![Ok(overloaded()), Ok(GlobalRep::QueueEmpty)]);
+        assert_eq!(result.unwrap(), Outcome::Reply(GlobalRep::QueueEmpty));
+        assert_eq!(attempts, 2);
+        let (result, attempts) = run(&lend, vec![Ok(overloaded()), Ok(overloaded()), Ok(overloaded())]);
+        assert_eq!(result.unwrap(), Outcome::Reply(overloaded()));
+        assert_eq!(attempts, 3);
+        let internal = GlobalRep::ServerError { code: ServerErrorCode::Internal, message: String::new(), };
+        let (result, attempts) = run(&GlobalReq::Count, vec![Ok(internal)]);
+        assert!(matches!(result.unwrap(), Outcome::Reply(GlobalRep::ServerError { code: ServerErrorCode::Internal, .. })));
+        assert_eq!(attempts, 1);
+        let rejected = ClientError::Rejected(ProtoError::InvalidTag { message: MessageKind::GlobalReq, field: FieldKind::Tag, tag: 99, });
+        let (result, attempts) = run(&GlobalReq::Count, vec![Err(rejected)]);
+        assert!(matches!(result, Err(ClientError::Rejected(..))));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn probably_repaid() {
+        let (result, _) = run(&repay(), vec![Err(lost()), Ok(GlobalRep::NotFound)]);
+        assert_eq!(result.unwrap(), Outcome::ProbablyRepaid);
+        let (result, _) = run(&repay(), vec![Ok(GlobalRep::NotFound)]);
+        assert_eq!(result.unwrap(), Outcome::Reply(GlobalRep::NotFound));
+        // the previous attempt was not sent at all, so the lend has really expired
+        let (result, _) = run(&repay(), vec![Err(ClientError::Broken), Ok(GlobalRep::NotFound)]);
+        assert_eq!(result.unwrap(), Outcome::Reply(GlobalRep::NotFound));
+        let invalid = ClientError::Decode(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalRepLent,
+            field: FieldKind::LendKey,
+            required: 8,
+            given: 0,
+        }.into());
+        let (result, _) = run(&repay(), vec![Err(invalid), Ok(GlobalRep::Repaid)]);
+        assert_eq!(result.unwrap(), Outcome::Reply(GlobalRep::Repaid));
+    }
+
+    #[test]
+    fn pool_errors() {
+        let pool = Pool::new(PoolConfig::default(), || -> io::Result<SyncClient<std::net::TcpStream>> {
+            Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused"))
+        });
+        match policy().run_pooled(&pool, &GlobalReq::Ping) {
+            Err(ClientError::Io(ref err)) if err.kind() == io::ErrorKind::ConnectionRefused => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,612 @@
+use std::{
+    fmt::{
+        self,
+        Write,
+    },
+    sync::Arc,
+};
+
+use super::{
+    LEGACY_NOT_ENOUGH_DATA_TAGS,
+    LEGACY_INVALID_TAG_TAGS,
+    LEGACY_INVALID_UTF8_TAGS,
+    PROTO_ERROR_DB_QUEUE_OUT_OF_SYNC_TAG,
+    PROTO_ERROR_NOT_ENOUGH_DATA_TAG,
+    PROTO_ERROR_INVALID_TAG_TAG,
+    PROTO_ERROR_INVALID_UTF8_TAG,
+    PROTO_VERSION,
+    ServerErrorCode,
+    AddMode,
+    LendMode,
+    RepayStatus,
+    AuthMechanism,
+    GlobalReq,
+    GlobalRep,
+    ProtoError,
+    MessageKind,
+    FieldKind,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum WireType {
+    U8,
+    U16,
+    U32,
+    U64,
+    /// `uint32_t` length followed by the bytes.
+    Bytes,
+    /// Same as `Bytes`, but the contents must be valid UTF-8.
+    Utf8,
+    /// `uint32_t` count followed by the items.
+    List(&'static WireType),
+    /// `uint8_t` tag.
+    Enum(&'static EnumSchema),
+    /// Nested `ProtoError`, see `proto_error_tags()`.
+    ProtoError,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EnumSchema {
+    pub name: &'static str,
+    pub variants: &'static [EnumVariantSchema],
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EnumVariantSchema {
+    pub name: &'static str,
+    pub tag: u8,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FieldSchema {
+    /// `None` for tuple variants.
+    pub name: Option<&'static str>,
+    pub rust_type: &'static str,
+    pub wire: WireType,
+    pub kind: FieldKind,
+    pub len: FieldKind,
+    pub count: FieldKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VariantSchema {
+    pub name: &'static str,
+    pub tag: u8,
+    /// Kind used in decoding errors for the variant fields, `None` if there are no fields.
+    pub message: Option<MessageKind>,
+    pub fields: &'static [FieldSchema],
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MessageSchema {
+    pub name: &'static str,
+    pub message: MessageKind,
+    /// Sorted by tag.
+    pub variants: &'static [VariantSchema],
+}
+
+pub const MESSAGES: &[&MessageSchema] = &[&GlobalReq::SCHEMA, &GlobalRep::SCHEMA];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProtoErrorEncoding {
+    /// `message` and `field` are implied by the tag.
+    Legacy(MessageKind, FieldKind),
+    /// `message:uint8_t field:uint8_t` kind codes follow the tag.
+    Generic,
+    /// Error without message and field kinds.
+    Plain,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProtoErrorTag {
+    pub tag: u8,
+    pub error: &'static str,
+    pub encoding: ProtoErrorEncoding,
+}
+
+impl ProtoErrorTag {
+    /// Fields following the tag and the kinds (if any).
+    pub fn payload(&self) -> &'static [(&'static str, WireType)] {
+        match self.error {
+            "NotEnoughData" => &[("required", WireType::U32), ("given", WireType::U32)],
+            "InvalidTag" => &[("invalid_tag", WireType::U8)],
+            "DbQueueOutOfSync" => &[("key", WireType::Bytes)],
+            _ => &[],
+        }
+    }
+}
+
+/// All `ProtoError` tags sorted by tag value.
+pub fn proto_error_tags() -> Vec<ProtoErrorTag> {
+    let mut tags = Vec::new();
+    for &(error, table, generic_tag) in &[
+        ("NotEnoughData", LEGACY_NOT_ENOUGH_DATA_TAGS, PROTO_ERROR_NOT_ENOUGH_DATA_TAG),
+        ("InvalidTag", LEGACY_INVALID_TAG_TAGS, PROTO_ERROR_INVALID_TAG_TAG),
+        ("InvalidUtf8", LEGACY_INVALID_UTF8_TAGS, PROTO_ERROR_INVALID_UTF8_TAG),
+    ] {
+        for &(tag, message, field) in table {
+            tags.push(ProtoErrorTag { tag: tag, error: error, encoding: ProtoErrorEncoding::Legacy(message, field), });
+        }
+        tags.push(ProtoErrorTag { tag: generic_tag, error: error, encoding: ProtoErrorEncoding::Generic, });
+    }
+    tags.push(ProtoErrorTag {
+        tag: PROTO_ERROR_DB_QUEUE_OUT_OF_SYNC_TAG,
+        error: "DbQueueOutOfSync",
+        encoding: ProtoErrorEncoding::Plain,
+    });
+    tags.sort_by_key(|tag| tag.tag);
+    tags
+}
+
+fn c_type(wire: &WireType) -> &'static str {
+    match wire {
+        &WireType::U8 | &WireType::Enum(..) => "uint8_t",
+        &WireType::U16 => "uint16_t",
+        &WireType::U32 => "uint32_t",
+        &WireType::U64 => "uint64_t",
+        &WireType::Bytes | &WireType::Utf8 => "uint8_t[]",
+        &WireType::List(..) => "list",
+        &WireType::ProtoError => "ProtoError",
+    }
+}
+
+fn wire_format(name: &str, len: &str, count: &str, wire: &WireType) -> String {
+    match wire {
+        &WireType::Bytes | &WireType::Utf8 =>
+            format!("{}:uint32_t {}:uint8_t[]", len, name),
+        &WireType::List(item) =>
+            format!("{}:uint32_t ({})*", count, wire_format(name, len, count, item)),
+        wire =>
+            format!("{}:{}", name, c_type(wire)),
+    }
+}
+
+fn wire_description(wire: &WireType) -> String {
+    match wire {
+        &WireType::Utf8 =>
+            "`uint8_t[]` (UTF-8)".to_string(),
+        &WireType::List(item) =>
+            format!("list of {}", wire_description(item)),
+        &WireType::Enum(schema) =>
+            format!("`uint8_t` (`{}`: {})", schema.name, schema.variants.iter()
+                    .map(|variant| format!("`0x{:02X}` for `{}`", variant.tag, variant.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")),
+        &WireType::ProtoError =>
+            "`ProtoError`, see below".to_string(),
+        wire =>
+            format!("`{}`", c_type(wire)),
+    }
+}
+
+fn hex(frame: &[u8]) -> String {
+    frame.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn key(s: &str) -> Arc<[u8]> {
+    Arc::from(s.as_bytes())
+}
+
+fn req_examples() -> Vec<(&'static str, GlobalReq)> {
+    vec![
+        ("Count()", GlobalReq::Count),
+        ("Add(\"cat\", \"small\", Tail)", GlobalReq::Add { key: key("cat"), value: key("small"), mode: AddMode::Tail, }),
+        ("Update(\"cat\", \"small\")", GlobalReq::Update(key("cat"), key("small"))),
+        ("Lend(1000, Block)", GlobalReq::Lend { timeout: 1000, mode: LendMode::Block, }),
+        ("Repay(1, \"cat\", \"big\", Reward)", GlobalReq::Repay {
+            lend_key: 1, key: key("cat"), value: key("big"), status: RepayStatus::Reward,
+        }),
+        ("Heartbeat(1, \"cat\", 2000)", GlobalReq::Heartbeat { lend_key: 1, key: key("cat"), timeout: 2000, }),
+        ("Stats()", GlobalReq::Stats),
+        ("Terminate()", GlobalReq::Terminate),
+        ("Lookup(\"cat\")", GlobalReq::Lookup(key("cat"))),
+        ("Flush()", GlobalReq::Flush),
+        ("Ping()", GlobalReq::Ping),
+        ("Remove(\"cat\")", GlobalReq::Remove(key("cat"))),
+        ("Info()", GlobalReq::Info),
+        ("Auth(Token, \"cat\")", GlobalReq::Auth { mechanism: AuthMechanism::Token, payload: key("cat"), }),
+    ]
+}
+
+fn rep_examples() -> Vec<(&'static str, GlobalRep)> {
+    vec![
+        ("Counted(10)", GlobalRep::Counted(10)),
+        ("Added()", GlobalRep::Added),
+        ("Kept()", GlobalRep::Kept),
+        ("Updated()", GlobalRep::Updated),
+        ("NotFound()", GlobalRep::NotFound),
+        ("Lent(1, \"cat\", \"small\")", GlobalRep::Lent { lend_key: 1, key: key("cat"), value: key("small"), }),
+        ("Repaid()", GlobalRep::Repaid),
+        ("Heartbeaten()", GlobalRep::Heartbeaten),
+        ("Skipped()", GlobalRep::Skipped),
+        ("StatsGot(1, 2, 3, 4, 5, 6, 7, 8, 9, 10)", GlobalRep::StatsGot {
+            ping: 1, count: 2, add: 3, update: 4, lookup: 5, remove: 6, lend: 7, repay: 8, heartbeat: 9, stats: 10,
+        }),
+        ("Error(InvalidTag(GlobalReq.tag, 99))", GlobalRep::Error(ProtoError::InvalidTag {
+            message: MessageKind::GlobalReq, field: FieldKind::Tag, tag: 99,
+        })),
+        ("Terminated()", GlobalRep::Terminated),
+        ("ValueFound(\"small\")", GlobalRep::ValueFound(key("small"))),
+        ("ValueNotFound()", GlobalRep::ValueNotFound),
+        ("Flushed()", GlobalRep::Flushed),
+        ("QueueEmpty()", GlobalRep::QueueEmpty),
+        ("Pong", GlobalRep::Pong),
+        ("Removed()", GlobalRep::Removed),
+        ("NotRemoved()", GlobalRep::NotRemoved),
+        ("InfoGot(\"0.3\", 1, 1000, \"/db\", 1024, 65536, [\"info\"])", GlobalRep::InfoGot {
+            server_version: "0.3".to_string(),
+            proto_version: 1,
+            uptime_ms: 1000,
+            db_path: "/db".to_string(),
+            max_key_len: 1024,
+            max_value_len: 65536,
+            features: vec!["info".to_string()],
+        }),
+        ("AuthOk()", GlobalRep::AuthOk),
+        ("AuthFailed()", GlobalRep::AuthFailed),
+        ("AuthChallenge(\"cat\")", GlobalRep::AuthChallenge(key("cat"))),
+        ("Unauthenticated()", GlobalRep::Unauthenticated),
+        ("Forbidden()", GlobalRep::Forbidden),
+        ("ServerError(DiskFull, \"full\")", GlobalRep::ServerError { code: ServerErrorCode::DiskFull, message: "full".to_string(), }),
+    ]
+}
+
+fn proto_error_examples() -> Vec<(&'static str, ProtoError)> {
+    vec![
+        ("NotEnoughData(GlobalReq::Add.key, 3, 1)", ProtoError::NotEnoughData {
+            message: MessageKind::GlobalReqAdd, field: FieldKind::Key, required: 3, given: 1,
+        }),
+        ("NotEnoughData(GlobalRep::ServerError.code, 2, 0)", ProtoError::NotEnoughData {
+            message: MessageKind::GlobalRepServerError, field: FieldKind::Code, required: 2, given: 0,
+        }),
+        ("InvalidTag(GlobalReq::Add.mode, 3)", ProtoError::InvalidTag {
+            message: MessageKind::GlobalReqAdd, field: FieldKind::Mode, tag: 3,
+        }),
+        ("InvalidUtf8(GlobalRep::InfoGot.db_path)", ProtoError::InvalidUtf8 {
+            message: MessageKind::GlobalRepInfoGot, field: FieldKind::DbPath,
+        }),
+        ("DbQueueOutOfSync(\"cat\")", ProtoError::DbQueueOutOfSync(key("cat"))),
+    ]
+}
+
+/// Example frames of every `GlobalReq` and `GlobalRep` variant: `(message name, example label, frame)`.
+pub fn example_frames() -> Vec<(&'static str, &'static str, Vec<u8>)> {
+    let mut frames = Vec::new();
+    for (label, req) in req_examples() {
+        let mut frame = vec![0; req.encode_len()];
+        req.encode(&mut frame);
+        frames.push((GlobalReq::SCHEMA.name, label, frame));
+    }
+    for (label, rep) in rep_examples() {
+        let mut frame = vec![0; rep.encode_len()];
+        rep.encode(&mut frame);
+        frames.push((GlobalRep::SCHEMA.name, label, frame));
+    }
+    frames
+}
+
+pub fn markdown() -> String {
+    let mut out = String::new();
+    write_markdown(&mut out).unwrap();
+    out
+}
+
+pub fn write_markdown<W>(w: &mut W) -> fmt::Result where W: Write {
+    writeln!(w, "# spiderq protocol reference")?;
+    writeln!(w)?;
+    writeln!(w, "Generated from `spiderq_proto::schema` with `cargo run --bin spiderq_spec -- markdown`, do not edit by hand.")?;
+    writeln!(w)?;
+    writeln!(w, "Protocol version: `{}`.", PROTO_VERSION)?;
+    writeln!(w)?;
+    writeln!(w, "Every frame starts with a `uint8_t` tag. All numeric values are encoded in network byte order (`big endian`). \
+                 Fields shown as `uint8_t[]` are prefixed with a `uint32_t` length, lists are prefixed with a `uint32_t` count. \
+                 Rust `usize` fields are encoded with the width shown and are truncated if they do not fit.")?;
+    let frames = example_frames();
+    for schema in MESSAGES {
+        writeln!(w)?;
+        writeln!(w, "## {}", schema.name)?;
+        for variant in schema.variants {
+            writeln!(w)?;
+            writeln!(w, "### {}::{}", schema.name, variant.name)?;
+            writeln!(w)?;
+            writeln!(w, "* Tag: `0x{:02X}`", variant.tag)?;
+            if !variant.fields.is_empty() {
+                writeln!(w, "* Fields:")?;
+                for field in variant.fields {
+                    writeln!(w, " * `{}`: {}, Rust type `{}`",
+                             field.name.unwrap_or_else(|| field.kind.name()),
+                             wire_description(&field.wire),
+                             field.rust_type)?;
+                }
+            }
+            write!(w, "* Format: <pre>0x{:02X}:uint8_t", variant.tag)?;
+            for field in variant.fields {
+                write!(w, " {}", wire_format(field.kind.name(), field.len.name(), field.count.name(), &field.wire))?;
+            }
+            writeln!(w, "</pre>")?;
+            for &(message, label, ref frame) in frames.iter() {
+                if message == schema.name && frame[0] == variant.tag {
+                    writeln!(w, "* Valid frame example for `{}`: <pre>{}</pre>", label, hex(frame))?;
+                }
+            }
+        }
+    }
+
+    writeln!(w)?;
+    writeln!(w, "## ProtoError")?;
+    writeln!(w)?;
+    writeln!(w, "Decoding errors are sent in `GlobalRep::Error` replies. \
+                 Frequent errors have a dedicated tag which implies the message and field where decoding failed, \
+                 any other message and field pair is sent with a generic tag followed by `MessageKind` and `FieldKind` codes.")?;
+    writeln!(w)?;
+    writeln!(w, "* Format of legacy tags: <pre>tag:uint8_t payload</pre>")?;
+    writeln!(w, "* Format of generic tags: <pre>tag:uint8_t message:uint8_t field:uint8_t payload</pre>")?;
+    writeln!(w, "* Payload of `NotEnoughData`: <pre>required:uint32_t given:uint32_t</pre>")?;
+    writeln!(w, "* Payload of `InvalidTag`: <pre>invalid_tag:uint8_t</pre>")?;
+    writeln!(w, "* Payload of `InvalidUtf8` is empty.")?;
+    writeln!(w, "* Format of `DbQueueOutOfSync`: <pre>0x{:02X}:uint8_t key_len:uint32_t key:uint8_t[]</pre>", PROTO_ERROR_DB_QUEUE_OUT_OF_SYNC_TAG)?;
+    for (label, error) in proto_error_examples() {
+        let mut frame = vec![0; error.encode_len()];
+        error.encode(&mut frame);
+        writeln!(w, "* Valid frame example for `{}`: <pre>{}</pre>", label, hex(&frame))?;
+    }
+    writeln!(w)?;
+    writeln!(w, "| Tag | Error | Message | Field |")?;
+    writeln!(w, "|-----|-------|---------|-------|")?;
+    for tag in proto_error_tags() {
+        let (message, field) = match tag.encoding {
+            ProtoErrorEncoding::Legacy(message, field) => (format!("`{}`", message.name()), format!("`{}`", field.name())),
+            ProtoErrorEncoding::Generic => ("generic".to_string(), "generic".to_string()),
+            ProtoErrorEncoding::Plain => ("-".to_string(), "-".to_string()),
+        };
+        writeln!(w, "| `0x{:02X}` | `{}` | {} | {} |", tag.tag, tag.error, message, field)?;
+    }
+
+    writeln!(w)?;
+    writeln!(w, "### MessageKind codes")?;
+    writeln!(w)?;
+    writeln!(w, "| Code | Name |")?;
+    writeln!(w, "|------|------|")?;
+    for kind in MessageKind::ALL {
+        writeln!(w, "| `0x{:02X}` | `{}` |", kind.code(), kind.name())?;
+    }
+    writeln!(w)?;
+    writeln!(w, "### FieldKind codes")?;
+    writeln!(w)?;
+    writeln!(w, "| Code | Name |")?;
+    writeln!(w, "|------|------|")?;
+    for kind in FieldKind::ALL {
+        writeln!(w, "| `0x{:02X}` | `{}` |", kind.code(), kind.name())?;
+    }
+    Ok(())
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+impl Json {
+    fn str(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+
+    fn write<W>(&self, w: &mut W, indent: usize) -> fmt::Result where W: Write {
+        match self {
+            &Json::Null =>
+                write!(w, "null"),
+            &Json::Bool(value) =>
+                write!(w, "{}", value),
+            &Json::Number(value) =>
+                write!(w, "{}", value),
+            &Json::String(ref value) =>
+                write_json_str(w, value),
+            &Json::Array(ref items) if items.is_empty() =>
+                write!(w, "[]"),
+            &Json::Array(ref items) => {
+                writeln!(w, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    write!(w, "{:1$}", "", indent + 2)?;
+                    item.write(w, indent + 2)?;
+                    writeln!(w, "{}", if i + 1 < items.len() { "," } else { "" })?;
+                }
+                write!(w, "{:1$}]", "", indent)
+            },
+            &Json::Object(ref entries) => {
+                writeln!(w, "{{")?;
+                for (i, &(name, ref value)) in entries.iter().enumerate() {
+                    write!(w, "{:1$}", "", indent + 2)?;
+                    write_json_str(w, name)?;
+                    write!(w, ": ")?;
+                    value.write(w, indent + 2)?;
+                    writeln!(w, "{}", if i + 1 < entries.len() { "," } else { "" })?;
+                }
+                write!(w, "{:1$}}}", "", indent)
+            },
+        }
+    }
+}
+
+/// JSON string literal: `"`, `\` and control characters escaped as RFC 8259
+/// requires.
+fn write_json_str<W>(w: &mut W, value: &str) -> fmt::Result where W: Write {
+    w.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            c if c < '\u{20}' => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+fn wire_json(wire: &WireType) -> Json {
+    match wire {
+        &WireType::U8 => Json::Object(vec![("type", Json::str("u8"))]),
+        &WireType::U16 => Json::Object(vec![("type", Json::str("u16"))]),
+        &WireType::U32 => Json::Object(vec![("type", Json::str("u32"))]),
+        &WireType::U64 => Json::Object(vec![("type", Json::str("u64"))]),
+        &WireType::Bytes => Json::Object(vec![("type", Json::str("bytes"))]),
+        &WireType::Utf8 => Json::Object(vec![("type", Json::str("utf8"))]),
+        &WireType::List(item) => Json::Object(vec![("type", Json::str("list")), ("item", wire_json(item))]),
+        &WireType::Enum(schema) => Json::Object(vec![
+            ("type", Json::str("enum")),
+            ("name", Json::str(schema.name)),
+            ("variants", Json::Array(schema.variants.iter().map(|variant| Json::Object(vec![
+                ("name", Json::str(variant.name)),
+                ("tag", Json::Number(variant.tag as u64)),
+            ])).collect())),
+        ]),
+        &WireType::ProtoError => Json::Object(vec![("type", Json::str("proto_error"))]),
+    }
+}
+
+fn field_json(field: &FieldSchema) -> Json {
+    let mut entries = vec![
+        ("name", field.name.map(Json::str).unwrap_or(Json::Null)),
+        ("kind", Json::str(field.kind.name())),
+        ("rust_type", Json::str(field.rust_type)),
+        ("wire", wire_json(&field.wire)),
+    ];
+    if let WireType::List(..) = field.wire {
+        entries.push(("count_kind", Json::str(field.count.name())));
+    }
+    if let WireType::Bytes | WireType::Utf8 | WireType::List(&WireType::Bytes) | WireType::List(&WireType::Utf8) = field.wire {
+        entries.push(("len_kind", Json::str(field.len.name())));
+    }
+    Json::Object(entries)
+}
+
+fn schema_json() -> Json {
+    Json::Object(vec![
+        ("proto_version", Json::Number(PROTO_VERSION as u64)),
+        ("messages", Json::Array(MESSAGES.iter().map(|schema| Json::Object(vec![
+            ("name", Json::str(schema.name)),
+            ("message_kind", Json::str(schema.message.name())),
+            ("variants", Json::Array(schema.variants.iter().map(|variant| Json::Object(vec![
+                ("name", Json::str(variant.name)),
+                ("tag", Json::Number(variant.tag as u64)),
+                ("message_kind", variant.message.map(|message| Json::str(message.name())).unwrap_or(Json::Null)),
+                ("fields", Json::Array(variant.fields.iter().map(field_json).collect())),
+            ])).collect())),
+        ])).collect())),
+        ("proto_error_tags", Json::Array(proto_error_tags().into_iter().map(|tag| {
+            let mut entries = vec![
+                ("tag", Json::Number(tag.tag as u64)),
+                ("error", Json::str(tag.error)),
+            ];
+            match tag.encoding {
+                ProtoErrorEncoding::Legacy(message, field) => {
+                    entries.push(("message_kind", Json::str(message.name())));
+                    entries.push(("field_kind", Json::str(field.name())));
+                },
+                ProtoErrorEncoding::Generic =>
+                    entries.push(("generic", Json::Bool(true))),
+                ProtoErrorEncoding::Plain =>
+                    (),
+            }
+            entries.push(("payload", Json::Array(tag.payload().iter().map(|&(name, ref wire)| Json::Object(vec![
+                ("name", Json::str(name)),
+                ("wire", wire_json(wire)),
+            ])).collect())));
+            Json::Object(entries)
+        }).collect())),
+        ("message_kinds", Json::Array(MessageKind::ALL.iter().map(|kind| Json::Object(vec![
+            ("code", Json::Number(kind.code() as u64)),
+            ("name", Json::str(kind.name())),
+        ])).collect())),
+        ("field_kinds", Json::Array(FieldKind::ALL.iter().map(|kind| Json::Object(vec![
+            ("code", Json::Number(kind.code() as u64)),
+            ("name", Json::str(kind.name())),
+        ])).collect())),
+    ])
+}
+
+pub fn json() -> String {
+    let mut out = String::new();
+    schema_json().write(&mut out, 0).unwrap();
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{GlobalReq, GlobalRep, MessageKind, FieldKind};
+    use super::{MESSAGES, WireType, Json, proto_error_tags, example_frames, markdown, json};
+
+    #[test]
+    fn counted_schema() {
+        let variant = GlobalRep::SCHEMA.variants.iter().find(|variant| variant.name == "Counted").unwrap();
+        assert_eq!(variant.tag, 1);
+        assert_eq!(variant.message, Some(MessageKind::GlobalRepCounted));
+        assert_eq!(variant.fields[0].rust_type, "usize");
+        assert_eq!(variant.fields[0].wire, WireType::U32);
+        assert_eq!(variant.fields[0].kind, FieldKind::Count);
+    }
+
+    #[test]
+    fn variants_sorted_and_exemplified() {
+        let frames = example_frames();
+        for schema in MESSAGES {
+            assert!(schema.variants.windows(2).all(|pair| pair[0].tag < pair[1].tag));
+            for variant in schema.variants {
+                assert_eq!(frames.iter().filter(|&&(name, _, ref frame)| name == schema.name && frame[0] == variant.tag).count(), 1,
+                           "{}::{} must have exactly one example", schema.name, variant.name);
+            }
+        }
+    }
+
+    #[test]
+    fn examples_decode() {
+        for (message, _, frame) in example_frames() {
+            let rest_len = if message == GlobalReq::SCHEMA.name {
+                GlobalReq::decode(&frame).unwrap().1.len()
+            } else {
+                GlobalRep::decode(&frame).unwrap().1.len()
+            };
+            assert_eq!(rest_len, 0);
+        }
+    }
+
+    #[test]
+    fn proto_error_tags_unique() {
+        let tags = proto_error_tags();
+        assert_eq!(tags.iter().map(|tag| tag.tag).collect::<Vec<_>>(), (1 ..= 81).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn readme_examples_match() {
+        let generated = markdown();
+        let marker = "Valid frame example for `";
+        for example in include_str!("../README.md").split(marker).skip(1) {
+            let example = &example[.. example.find("</pre>").unwrap() + "</pre>".len()];
+            assert!(generated.contains(&format!("{}{}", marker, example)), "README example differs: {}", example);
+        }
+    }
+
+    #[test]
+    fn json_string_escapes() {
+        let mut out = String::new();
+        Json::Object(vec![("a\"b", Json::str("tab\t \\ \u{1} \u{1f} \u{7f} é \u{1F600}"))]).write(&mut out, 0).unwrap();
+        assert_eq!(out, "{\n  \"a\\\"b\": \"tab\\t \\\\ \\u0001 \\u001f \u{7f} é \u{1F600}\"\n}");
+        let value: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(value["a\"b"], "tab\t \\ \u{1} \u{1f} \u{7f} é \u{1F600}");
+    }
+
+    #[test]
+    fn generated_files_up_to_date() {
+        assert!(markdown() == include_str!("../PROTOCOL.md"),
+                "PROTOCOL.md is stale, run `cargo run --bin spiderq_spec -- markdown > PROTOCOL.md`");
+        assert!(json() == include_str!("../schema.json"),
+                "schema.json is stale, run `cargo run --bin spiderq_spec -- json > schema.json`");
+    }
+}
@@ -0,0 +1,672 @@
+//! In-memory reference implementation of spiderq queue semantics.
+//!
+//! Every task has an accumulated priority (starting from 0) and is lent in
+//! priority order, first in first out among equal priorities:
+//!
+//! * `Add` with `Tail` queues a new task behind the tasks with priority 0.
+//! * Putting a task "at the front" (`Add` with `Head`, `Repay` with `Front`
+//!   and an expired lease) raises its priority to the highest priority ever
+//!   queued (at least 0) if that is higher and queues it before the tasks
+//!   with that priority. Using the highest priority ever queued rather than
+//!   the first queued task keeps a task put at the front of an empty queue
+//!   ahead of tasks added later.
+//! * `Repay` with `Penalty` or `Reward` decreases or increases the priority by
+//!   one and queues the task behind the tasks with the resulting priority.
+//! * `Repay` with `Drop` keeps the entry but never lends it again.
+//!
+//! Lease deadlines follow a `clock::Clock`, so tests can drive expiry with a
+//! `clock::ManualClock` (see `Server::with_clock`). A blocking `Lend` waits
+//! in real time but is woken up by `ManualClock::advance` to look for expired
+//! leases.
+//!
+//! Authentication is per connection and belongs in front of the engine (see
+//! `auth::AuthSession`); the engine itself answers `Auth` with `AuthFailed`.
+
+use std::{
+    cmp::Reverse,
+    collections::{
+        HashMap,
+        BTreeMap,
+        BTreeSet,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        Condvar,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+
+use super::{
+    Key,
+    Value,
+    AddMode,
+    LendMode,
+    RepayStatus,
+    ServerErrorCode,
+    GlobalReq,
+    GlobalRep,
+    PROTO_VERSION,
+};
+use super::clock::{
+    Clock,
+    SystemClock,
+};
+use super::codec;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ServerConfig {
+    /// Longer keys are rejected with `KeyTooLarge`, `0` for no limit.
+    pub max_key_len: usize,
+    /// Longer values are rejected with `ValueTooLarge`, `0` for no limit.
+    pub max_value_len: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Place {
+    /// Position in `State::queue`.
+    Queued(i64),
+    Lent(u64),
+    Dropped,
+}
+
+struct Entry {
+    value: Value,
+    priority: i64,
+    place: Place,
+}
+
+struct Lease {
+    key: Key,
+    deadline: Instant,
+}
+
+#[derive(Default)]
+struct Counters {
+    ping: usize,
+    count: usize,
+    add: usize,
+    update: usize,
+    lookup: usize,
+    remove: usize,
+    lend: usize,
+    repay: usize,
+    heartbeat: usize,
+    stats: usize,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    /// Queued keys by (priority descending, position).
+    queue: BTreeMap<(Reverse<i64>, i64), Key>,
+    leases: HashMap<u64, Lease>,
+    deadlines: BTreeSet<(Instant, u64)>,
+    head_position: i64,
+    tail_position: i64,
+    /// Highest priority ever queued, at least 0.
+    max_priority: i64,
+    last_lend_key: u64,
+    counters: Counters,
+    terminated: bool,
+}
+
+enum End {
+    Front,
+    Back,
+}
+
+impl State {
+    /// Queues the entry of `key`, which must not be queued already.
+    fn enqueue(&mut self, key: Key, at: End) {
+        let entry = self.entries.get_mut(&key).unwrap();
+        let position = match at {
+            End::Front => {
+                entry.priority = entry.priority.max(self.max_priority);
+                self.head_position -= 1;
+                self.head_position
+            },
+            End::Back => {
+                self.tail_position += 1;
+                self.tail_position
+            },
+        };
+        entry.place = Place::Queued(position);
+        self.max_priority = self.max_priority.max(entry.priority);
+        self.queue.insert((Reverse(entry.priority), position), key);
+    }
+
+    /// Takes the entry of `key` out of the queue or ends its lease.
+    fn detach(&mut self, key: &Key) {
+        let entry = &self.entries[key];
+        match entry.place {
+            Place::Queued(position) => {
+                self.queue.remove(&(Reverse(entry.priority), position));
+            },
+            Place::Lent(lend_key) => {
+                let lease = self.leases.remove(&lend_key).unwrap();
+                self.deadlines.remove(&(lease.deadline, lend_key));
+            },
+            Place::Dropped =>
+                (),
+        }
+    }
+
+    /// Puts tasks with leases expired at `now` back to the front of the
+    /// queue, returns whether there were any.
+    fn expire(&mut self, now: Instant) -> bool {
+        let mut expired = false;
+        while let Some(&(deadline, lend_key)) = self.deadlines.iter().next() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.remove(&(deadline, lend_key));
+            let lease = self.leases.remove(&lend_key).unwrap();
+            self.enqueue(lease.key, End::Front);
+            expired = true;
+        }
+        expired
+    }
+
+    /// The lease of `lend_key` if it was lent for `key`.
+    fn lease(&self, lend_key: u64, key: &Key) -> Option<&Lease> {
+        self.leases.get(&lend_key).filter(|lease| &lease.key == key)
+    }
+
+    fn lend(&mut self, timeout_ms: u64, now: Instant) -> Option<GlobalRep> {
+        let (&position, _) = self.queue.iter().next()?;
+        let key = self.queue.remove(&position).unwrap();
+        self.last_lend_key += 1;
+        let lend_key = self.last_lend_key;
+        let deadline = now + Duration::from_millis(timeout_ms);
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.place = Place::Lent(lend_key);
+        let value = entry.value.clone();
+        self.leases.insert(lend_key, Lease { key: key.clone(), deadline: deadline, });
+        self.deadlines.insert((deadline, lend_key));
+        Some(GlobalRep::Lent { lend_key: lend_key, key: key, value: value, })
+    }
+
+    fn repay(&mut self, lend_key: u64, key: Key, value: Value, status: RepayStatus) -> GlobalRep {
+        if self.lease(lend_key, &key).is_none() {
+            return GlobalRep::NotFound;
+        }
+        self.detach(&key);
+        let entry = self.entries.get_mut(&key).unwrap();
+        entry.value = value;
+        match status {
+            RepayStatus::Penalty => {
+                entry.priority = entry.priority.saturating_sub(1);
+                self.enqueue(key, End::Back);
+            },
+            RepayStatus::Reward => {
+                entry.priority = entry.priority.saturating_add(1);
+                self.enqueue(key, End::Back);
+            },
+            RepayStatus::Front =>
+                self.enqueue(key, End::Front),
+            RepayStatus::Drop =>
+                entry.place = Place::Dropped,
+        }
+        GlobalRep::Repaid
+    }
+
+    fn heartbeat(&mut self, lend_key: u64, key: Key, timeout_ms: u64, now: Instant) -> GlobalRep {
+        let deadline = match self.lease(lend_key, &key) {
+            Some(lease) => lease.deadline,
+            None => return GlobalRep::Skipped,
+        };
+        let extended = now + Duration::from_millis(timeout_ms);
+        self.deadlines.remove(&(deadline, lend_key));
+        self.deadlines.insert((extended, lend_key));
+        self.leases.get_mut(&lend_key).unwrap().deadline = extended;
+        GlobalRep::Heartbeaten
+    }
+}
+
+pub struct Server {
+    config: ServerConfig,
+    clock: Arc<dyn Clock>,
+    started: Instant,
+    state: Arc<Mutex<State>>,
+    /// Notified when a task may have become available or the server terminates.
+    available: Arc<Condvar>,
+}
+
+impl Default for Server {
+    fn default() -> Server {
+        Server::new(ServerConfig::default())
+    }
+}
+
+impl Server {
+    pub fn new(config: ServerConfig) -> Server {
+        Server::with_clock(config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(config: ServerConfig, clock: Arc<dyn Clock>) -> Server {
+        let state = Arc::new(Mutex::new(State::default()));
+        let available = Arc::new(Condvar::new());
+        let (weak_state, weak_available) = (Arc::downgrade(&state), Arc::downgrade(&available));
+        clock.on_advance(Box::new(move || match (weak_state.upgrade(), weak_available.upgrade()) {
+            (Some(state), Some(available)) => {
+                // with the lock held a blocking lend is either waiting or yet to read the clock
+                let _state = state.lock().unwrap();
+                available.notify_all();
+                true
+            },
+            _ =>
+                false,
+        }));
+        Server {
+            config: config,
+            started: clock.now(),
+            clock: clock,
+            state: state,
+            available: available,
+        }
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.state.lock().unwrap().terminated
+    }
+
+    /// Decodes a request frame and encodes the reply, answering undecodable
+    /// requests with `GlobalRep::Error` and frames with trailing bytes with
+    /// `GlobalRep::ServerError`.
+    pub fn handle_frame(&self, frame: &[u8]) -> Vec<u8> {
+        let rep = match codec::decode_req(frame) {
+            Ok(req) => self.handle(req),
+            Err(err) => err.into_rep(),
+        };
+        let mut area = vec![0; rep.encode_len()];
+        rep.encode(&mut area);
+        area
+    }
+
+    /// Processes a request. `Lend` with `LendMode::Block` blocks the calling
+    /// thread until a task is available or the server is terminated.
+    pub fn handle(&self, req: GlobalReq) -> GlobalRep {
+        let mut state = self.state.lock().unwrap();
+        if state.terminated {
+            return GlobalRep::ServerError { code: ServerErrorCode::ShuttingDown, message: "server is terminated".to_string(), };
+        }
+        if state.expire(self.clock.now()) {
+            self.available.notify_all();
+        }
+        if let Some(rep) = self.check_limits(&req) {
+            return rep;
+        }
+        match req {
+            GlobalReq::Ping => {
+                state.counters.ping += 1;
+                GlobalRep::Pong
+            },
+            GlobalReq::Count => {
+                state.counters.count += 1;
+                GlobalRep::Counted(state.queue.len())
+            },
+            GlobalReq::Add { key, value, mode, } => {
+                state.counters.add += 1;
+                if state.entries.contains_key(&key) {
+                    return GlobalRep::Kept;
+                }
+                state.entries.insert(key.clone(), Entry { value: value, priority: 0, place: Place::Dropped, });
+                state.enqueue(key, match mode { AddMode::Head => End::Front, AddMode::Tail => End::Back, });
+                self.available.notify_all();
+                GlobalRep::Added
+            },
+            GlobalReq::Update(key, value) => {
+                state.counters.update += 1;
+                match state.entries.get_mut(&key) {
+                    Some(entry) => {
+                        entry.value = value;
+                        GlobalRep::Updated
+                    },
+                    None => GlobalRep::NotFound,
+                }
+            },
+            GlobalReq::Lookup(key) => {
+                state.counters.lookup += 1;
+                match state.entries.get(&key) {
+                    Some(entry) => GlobalRep::ValueFound(entry.value.clone()),
+                    None => GlobalRep::ValueNotFound,
+                }
+            },
+            GlobalReq::Remove(key) => {
+                state.counters.remove += 1;
+                if !state.entries.contains_key(&key) {
+                    return GlobalRep::NotRemoved;
+                }
+                state.detach(&key);
+                state.entries.remove(&key);
+                GlobalRep::Removed
+            },
+            GlobalReq::Lend { timeout, mode, } => {
+                state.counters.lend += 1;
+                loop {
+                    let now = self.clock.now();
+                    state.expire(now);
+                    if state.terminated {
+                        return GlobalRep::ServerError { code: ServerErrorCode::ShuttingDown, message: "server is terminated".to_string(), };
+                    }
+                    if let Some(rep) = state.lend(timeout, now) {
+                        return rep;
+                    }
+                    if mode == LendMode::Poll {
+                        return GlobalRep::QueueEmpty;
+                    }
+                    // a lease expiring is only notified about by clocks not following real time
+                    state = match state.deadlines.iter().next() {
+                        Some(&(deadline, _)) => self.available.wait_timeout(state, deadline.saturating_duration_since(now)).unwrap().0,
+                        None => self.available.wait(state).unwrap(),
+                    };
+                }
+            },
+            GlobalReq::Repay { lend_key, key, value, status, } => {
+                state.counters.repay += 1;
+                let rep = state.repay(lend_key, key, value, status);
+                self.available.notify_all();
+                rep
+            },
+            GlobalReq::Heartbeat { lend_key, key, timeout, } => {
+                state.counters.heartbeat += 1;
+                state.heartbeat(lend_key, key, timeout, self.clock.now())
+            },
+            GlobalReq::Stats => {
+                state.counters.stats += 1;
+                let counters = &state.counters;
+                GlobalRep::StatsGot {
+                    ping: counters.ping,
+                    count: counters.count,
+                    add: counters.add,
+                    update: counters.update,
+                    lookup: counters.lookup,
+                    remove: counters.remove,
+                    lend: counters.lend,
+                    repay: counters.repay,
+                    heartbeat: counters.heartbeat,
+                    stats: counters.stats,
+                }
+            },
+            GlobalReq::Flush =>
+                GlobalRep::Flushed,
+            GlobalReq::Terminate => {
+                state.terminated = true;
+                self.available.notify_all();
+                GlobalRep::Terminated
+            },
+            GlobalReq::Info =>
+                GlobalRep::InfoGot {
+                    server_version: format!("spiderq_proto {} in-memory", env!("CARGO_PKG_VERSION")),
+                    proto_version: PROTO_VERSION,
+                    uptime_ms: self.clock.now().saturating_duration_since(self.started).as_millis() as u64,
+                    db_path: String::new(),
+                    max_key_len: self.config.max_key_len,
+                    max_value_len: self.config.max_value_len,
+                    features: Vec::new(),
+                },
+            GlobalReq::Auth { .. } =>
+                GlobalRep::AuthFailed,
+        }
+    }
+
+    fn check_limits(&self, req: &GlobalReq) -> Option<GlobalRep> {
+        let (key, value) = match req {
+            &GlobalReq::Add { ref key, ref value, .. } | &GlobalReq::Update(ref key, ref value) => (key, Some(value)),
+            &GlobalReq::Repay { ref key, ref value, .. } => (key, Some(value)),
+            _ => return None,
+        };
+        let exceeds = |len: usize, max: usize| max != 0 && len > max;
+        if exceeds(key.len(), self.config.max_key_len) {
+            let message = format!("key is {} bytes long, the limit is {}", key.len(), self.config.max_key_len);
+            return Some(GlobalRep::ServerError { code: ServerErrorCode::KeyTooLarge, message: message, });
+        }
+        match value {
+            Some(value) if exceeds(value.len(), self.config.max_value_len) => {
+                let message = format!("value is {} bytes long, the limit is {}", value.len(), self.config.max_value_len);
+                Some(GlobalRep::ServerError { code: ServerErrorCode::ValueTooLarge, message: message, })
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+    use super::super::{Key, AddMode, LendMode, RepayStatus, ServerErrorCode, GlobalReq, GlobalRep, ProtoError, MessageKind, FieldKind};
+    use super::super::clock::ManualClock;
+    use super::{Server, ServerConfig};
+
+    fn bytes(value: &str) -> Key {
+        Arc::from(value.as_bytes())
+    }
+
+    fn add(server: &Server, key: &str, mode: AddMode) -> GlobalRep {
+        server.handle(GlobalReq::Add { key: bytes(key), value: bytes(key), mode: mode, })
+    }
+
+    fn lend(server: &Server, timeout: u64) -> Option<(u64, String)> {
+        match server.handle(GlobalReq::Lend { timeout: timeout, mode: LendMode::Poll, }) {
+            GlobalRep::Lent { lend_key, key, .. } => Some((lend_key, String::from_utf8(key.to_vec()).unwrap())),
+            GlobalRep::QueueEmpty => None,
+            rep => panic!("unexpected reply {:?}", rep),
+        }
+    }
+
+    fn repay(server: &Server, lend_key: u64, key: &str, status: RepayStatus) -> GlobalRep {
+        server.handle(GlobalReq::Repay { lend_key: lend_key, key: bytes(key), value: bytes(key), status: status, })
+    }
+
+    /// Lends everything queued, returning the keys in order.
+    fn drain(server: &Server) -> Vec<String> {
+        let mut keys = Vec::new();
+        while let Some((_, key)) = lend(server, 60000) {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn entries() {
+        let server = Server::default();
+        assert_eq!(add(&server, "cat", AddMode::Tail), GlobalRep::Added);
+        assert_eq!(add(&server, "cat", AddMode::Head), GlobalRep::Kept);
+        assert_eq!(server.handle(GlobalReq::Update(bytes("cat"), bytes("big"))), GlobalRep::Updated);
+        assert_eq!(server.handle(GlobalReq::Update(bytes("dog"), bytes("big"))), GlobalRep::NotFound);
+        assert_eq!(server.handle(GlobalReq::Lookup(bytes("cat"))), GlobalRep::ValueFound(bytes("big")));
+        assert_eq!(server.handle(GlobalReq::Count), GlobalRep::Counted(1));
+        assert_eq!(server.handle(GlobalReq::Remove(bytes("cat"))), GlobalRep::Removed);
+        assert_eq!(server.handle(GlobalReq::Remove(bytes("cat"))), GlobalRep::NotRemoved);
+        assert_eq!(server.handle(GlobalReq::Lookup(bytes("cat"))), GlobalRep::ValueNotFound);
+        assert_eq!(server.handle(GlobalReq::Count), GlobalRep::Counted(0));
+        assert_eq!(lend(&server, 1000), None);
+    }
+
+    #[test]
+    fn add_modes() {
+        let server = Server::default();
+        for (key, mode) in [("a", AddMode::Tail), ("b", AddMode::Tail), ("c", AddMode::Head), ("d", AddMode::Head)] {
+            add(&server, key, mode);
+        }
+        assert_eq!(drain(&server), vec!["d", "c", "a", "b"]);
+    }
+
+    #[test]
+    fn repay_statuses() {
+        let server = Server::default();
+        for key in &["a", "b", "c", "d"] {
+            add(&server, key, AddMode::Tail);
+        }
+        let (lend_key, _) = lend(&server, 60000).unwrap();
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Penalty), GlobalRep::Repaid);
+        let (lend_key, _) = lend(&server, 60000).unwrap();
+        assert_eq!(repay(&server, lend_key, "b", RepayStatus::Reward), GlobalRep::Repaid);
+        // priorities: b = 1, c = d = 0, a = -1
+        let (lend_key, key) = lend(&server, 60000).unwrap();
+        assert_eq!(key, "b");
+        assert_eq!(repay(&server, lend_key, "b", RepayStatus::Drop), GlobalRep::Repaid);
+        let (lend_key, key) = lend(&server, 60000).unwrap();
+        assert_eq!(key, "c");
+        assert_eq!(repay(&server, lend_key, "c", RepayStatus::Penalty), GlobalRep::Repaid);
+        let (lend_key, key) = lend(&server, 60000).unwrap();
+        assert_eq!(key, "d");
+        assert_eq!(repay(&server, lend_key, "d", RepayStatus::Penalty), GlobalRep::Repaid);
+        // a, c and d have priority -1 now, a was queued first
+        let (lend_key, key) = lend(&server, 60000).unwrap();
+        assert_eq!(key, "a");
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Front), GlobalRep::Repaid);
+        assert_eq!(drain(&server), vec!["a", "c", "d"]);
+        // dropped entry stays in the database and is not lent again
+        assert_eq!(server.handle(GlobalReq::Lookup(bytes("b"))), GlobalRep::ValueFound(bytes("b")));
+        assert_eq!(add(&server, "b", AddMode::Head), GlobalRep::Kept);
+    }
+
+    #[test]
+    fn front_of_empty_queue() {
+        let clock = ManualClock::new();
+        let server = Server::with_clock(ServerConfig::default(), Arc::new(clock.clone()));
+        add(&server, "a", AddMode::Tail);
+        let (lend_key, _) = lend(&server, 60000).unwrap();
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Penalty), GlobalRep::Repaid);
+        let (lend_key, _) = lend(&server, 60000).unwrap();
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Front), GlobalRep::Repaid);
+        add(&server, "b", AddMode::Tail);
+        assert_eq!(drain(&server), vec!["a", "b"]);
+        // same for expired leases, the one which expired last goes first
+        assert_eq!(repay(&server, 3, "a", RepayStatus::Penalty), GlobalRep::Repaid);
+        assert_eq!(lend(&server, 60000), Some((5, "a".to_string())));
+        assert_eq!(repay(&server, 4, "b", RepayStatus::Reward), GlobalRep::Repaid);
+        assert_eq!(lend(&server, 60000), Some((6, "b".to_string())));
+        clock.advance(Duration::from_millis(60000));
+        add(&server, "c", AddMode::Tail);
+        assert_eq!(drain(&server), vec!["b", "a", "c"]);
+    }
+
+    #[test]
+    fn repay_checks() {
+        let server = Server::default();
+        add(&server, "a", AddMode::Tail);
+        let (lend_key, _) = lend(&server, 60000).unwrap();
+        assert_eq!(repay(&server, lend_key + 1, "a", RepayStatus::Front), GlobalRep::NotFound);
+        assert_eq!(repay(&server, lend_key, "b", RepayStatus::Front), GlobalRep::NotFound);
+        assert_eq!(server.handle(GlobalReq::Heartbeat { lend_key: lend_key, key: bytes("b"), timeout: 1000, }), GlobalRep::Skipped);
+        assert_eq!(server.handle(GlobalReq::Heartbeat { lend_key: lend_key, key: bytes("a"), timeout: 1000, }), GlobalRep::Heartbeaten);
+        assert_eq!(server.handle(GlobalReq::Count), GlobalRep::Counted(0));
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Front), GlobalRep::Repaid);
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Front), GlobalRep::NotFound);
+        // removing a lent entry ends its lease
+        let (lend_key, _) = lend(&server, 60000).unwrap();
+        assert_eq!(server.handle(GlobalReq::Remove(bytes("a"))), GlobalRep::Removed);
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Front), GlobalRep::NotFound);
+    }
+
+    #[test]
+    fn lease_expiry() {
+        let clock = ManualClock::new();
+        let server = Server::with_clock(ServerConfig::default(), Arc::new(clock.clone()));
+        add(&server, "a", AddMode::Tail);
+        add(&server, "b", AddMode::Tail);
+        let (lend_key, _) = lend(&server, 1000).unwrap();
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(server.handle(GlobalReq::Count), GlobalRep::Counted(1));
+        assert_eq!(server.handle(GlobalReq::Heartbeat { lend_key: lend_key, key: bytes("a"), timeout: 1000, }), GlobalRep::Heartbeaten);
+        clock.advance(Duration::from_millis(999));
+        assert_eq!(server.handle(GlobalReq::Count), GlobalRep::Counted(1));
+        clock.advance(Duration::from_millis(1));
+        // "a" is back at the front
+        assert_eq!(server.handle(GlobalReq::Count), GlobalRep::Counted(2));
+        assert_eq!(server.handle(GlobalReq::Heartbeat { lend_key: lend_key, key: bytes("a"), timeout: 1000, }), GlobalRep::Skipped);
+        assert_eq!(repay(&server, lend_key, "a", RepayStatus::Drop), GlobalRep::NotFound);
+        assert_eq!(drain(&server), vec!["a", "b"]);
+        match server.handle(GlobalReq::Info) {
+            GlobalRep::InfoGot { uptime_ms: 1999, .. } => (),
+            rep => panic!("unexpected reply {:?}", rep),
+        }
+    }
+
+    #[test]
+    fn blocking_lend() {
+        let server = Arc::new(Server::default());
+        let adder = server.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            add(&adder, "a", AddMode::Tail);
+        });
+        let rep = server.handle(GlobalReq::Lend { timeout: 10, mode: LendMode::Block, });
+        assert!(matches!(rep, GlobalRep::Lent { lend_key: 1, .. }));
+        handle.join().unwrap();
+        // the next one is woken up by the lease expiry
+        let rep = server.handle(GlobalReq::Lend { timeout: 1000, mode: LendMode::Block, });
+        assert!(matches!(rep, GlobalRep::Lent { lend_key: 2, .. }));
+        let terminator = server.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(terminator.handle(GlobalReq::Terminate), GlobalRep::Terminated);
+        });
+        let rep = server.handle(GlobalReq::Lend { timeout: 1000, mode: LendMode::Block, });
+        assert!(matches!(rep, GlobalRep::ServerError { code: ServerErrorCode::ShuttingDown, .. }));
+        handle.join().unwrap();
+        assert!(server.is_terminated());
+        assert!(matches!(server.handle(GlobalReq::Ping), GlobalRep::ServerError { code: ServerErrorCode::ShuttingDown, .. }));
+    }
+
+    #[test]
+    fn blocking_lend_manual_clock() {
+        let clock = ManualClock::new();
+        let server = Arc::new(Server::with_clock(ServerConfig::default(), Arc::new(clock.clone())));
+        add(&server, "a", AddMode::Tail);
+        assert_eq!(lend(&server, 60000), Some((1, "a".to_string())));
+        let lender = server.clone();
+        let handle = thread::spawn(move || lender.handle(GlobalReq::Lend { timeout: 60000, mode: LendMode::Block, }));
+        thread::sleep(Duration::from_millis(20));
+        clock.advance(Duration::from_millis(60000));
+        // woken up by the clock instead of a minute later
+        let rep = handle.join().unwrap();
+        assert!(matches!(rep, GlobalRep::Lent { lend_key: 2, .. }));
+    }
+
+    #[test]
+    fn stats_info_and_limits() {
+        let server = Server::new(ServerConfig { max_key_len: 3, max_value_len: 4, });
+        server.handle(GlobalReq::Ping);
+        add(&server, "cat", AddMode::Tail);
+        assert!(matches!(add(&server, "kitten", AddMode::Tail), GlobalRep::ServerError { code: ServerErrorCode::KeyTooLarge, .. }));
+        let rep = server.handle(GlobalReq::Update(bytes("cat"), bytes("kitten")));
+        assert!(matches!(rep, GlobalRep::ServerError { code: ServerErrorCode::ValueTooLarge, .. }));
+        assert_eq!(server.handle(GlobalReq::Stats), GlobalRep::StatsGot {
+            ping: 1, count: 0, add: 1, update: 0, lookup: 0, remove: 0, lend: 0, repay: 0, heartbeat: 0, stats: 1,
+        });
+        match server.handle(GlobalReq::Info) {
+            GlobalRep::InfoGot { max_key_len: 3, max_value_len: 4, .. } => (),
+            rep => panic!("unexpected reply {:?}", rep),
+        }
+        assert_eq!(server.handle(GlobalReq::Flush), GlobalRep::Flushed);
+    }
+
+    #[test]
+    fn frames() {
+        let server = Server::default();
+        assert_eq!(GlobalRep::decode(&server.handle_frame(&[11])).unwrap().0, GlobalRep::Pong);
+        let (rep, _) = GlobalRep::decode(&server.handle_frame(&[2, 0, 0])).unwrap();
+        assert_eq!(rep, GlobalRep::Error(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalReqAdd,
+            field: FieldKind::KeyLen,
+            required: 4,
+            given: 2,
+        }));
+        let (rep, _) = GlobalRep::decode(&server.handle_frame(&[11, 0, 0])).unwrap();
+        assert_eq!(rep, GlobalRep::ServerError {
+            code: ServerErrorCode::Internal,
+            message: "invalid request: 2 trailing bytes after the message".to_string(),
+        });
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    sync::Arc,
+    thread,
+    net::{
+        TcpListener,
+        SocketAddr,
+    },
+};
+
+use super::{
+    GlobalReq,
+    GlobalRep,
+    client::{
+        read_frame,
+        write_frame,
+        DEFAULT_MAX_FRAME_LEN,
+    },
+};
+
+pub fn bytes(value: &[u8]) -> Arc<[u8]> {
+    Arc::from(value)
+}
+
+pub fn encode_rep(rep: &GlobalRep) -> Vec<u8> {
+    let mut frame = vec![0; rep.encode_len()];
+    rep.encode(&mut frame);
+    frame
+}
+
+/// Replies with `replies` in order, checking the requests against `expected`.
+pub fn serve(expected: Vec<GlobalReq>, replies: Vec<GlobalRep>) -> (SocketAddr, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        for (expected, rep) in expected.into_iter().zip(replies) {
+            let frame = read_frame(&mut stream, DEFAULT_MAX_FRAME_LEN).unwrap();
+            assert_eq!(GlobalReq::decode(&frame).unwrap().0, expected);
+            write_frame(&mut stream, &encode_rep(&rep)).unwrap();
+        }
+        // keep the connection open until the client is done
+        let _ = read_frame(&mut stream, DEFAULT_MAX_FRAME_LEN);
+    });
+    (addr, handle)
+}
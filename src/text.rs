@@ -0,0 +1,743 @@
+//! Line based text representation of the protocol for debugging with `nc`:
+//!
+//! <pre>ADD cat small TAIL
+//! LEND 1000 POLL
+//! REPAY 1 cat big REWARD</pre>
+//!
+//! A frame is a single line of space separated words, the trailing line break
+//! is optional. The first word is the command (case insensitive), the rest are
+//! the variant fields in the binary encoding order. Byte strings which contain
+//! spaces, quotes, backslashes or non printable bytes (and empty ones) are
+//! written in double quotes with `\\`, `\"`, `\n`, `\r`, `\t` and `\xHH`
+//! escapes.
+
+use std::{
+    fmt,
+    str::FromStr,
+    sync::Arc,
+};
+
+use super::{
+    Key,
+    ServerErrorCode,
+    RepayStatus,
+    LendMode,
+    AddMode,
+    AuthMechanism,
+    GlobalReq,
+    GlobalRep,
+    ProtoError,
+    MessageKind,
+    FieldKind,
+    codec::{
+        self,
+        DecodeError,
+    },
+};
+
+#[derive(Debug, PartialEq)]
+pub enum TextError {
+    Empty,
+    Syntax { position: usize, reason: &'static str, },
+    UnknownCommand(String),
+    MissingArgument { command: &'static str, argument: &'static str, },
+    UnexpectedArgument { command: &'static str, },
+    InvalidArgument { command: &'static str, argument: &'static str, value: String, },
+}
+
+impl fmt::Display for TextError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TextError::Empty =>
+                write!(f, "empty command line"),
+            &TextError::Syntax { position, reason, } =>
+                write!(f, "syntax error at byte {}: {}", position, reason),
+            &TextError::UnknownCommand(ref command) =>
+                write!(f, "unknown command {:?}", command),
+            &TextError::MissingArgument { command, argument, } =>
+                write!(f, "missing `{}` argument for {}", argument, command),
+            &TextError::UnexpectedArgument { command, } =>
+                write!(f, "too many arguments for {}", command),
+            &TextError::InvalidArgument { command, argument, ref value, } =>
+                write!(f, "invalid `{}` argument for {}: {:?}", argument, command, value),
+        }
+    }
+}
+
+impl std::error::Error for TextError {}
+
+fn tokenize(line: &[u8]) -> Result<Vec<Vec<u8>>, TextError> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    loop {
+        while pos < line.len() && line[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos >= line.len() {
+            return Ok(tokens);
+        }
+        let mut token = Vec::new();
+        if line[pos] == b'"' {
+            pos += 1;
+            loop {
+                match line.get(pos) {
+                    None =>
+                        return Err(TextError::Syntax { position: pos, reason: "unterminated quoted string", }),
+                    Some(&b'"') =>
+                        break,
+                    Some(&b'\\') => {
+                        let (byte, len) = match (line.get(pos + 1), line.get(pos + 2 .. pos + 4)) {
+                            (Some(&b'\\'), _) => (b'\\', 2),
+                            (Some(&b'"'), _) => (b'"', 2),
+                            (Some(&b'n'), _) => (b'\n', 2),
+                            (Some(&b'r'), _) => (b'\r', 2),
+                            (Some(&b't'), _) => (b'\t', 2),
+                            (Some(&b'x'), Some(hex)) => match std::str::from_utf8(hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                                Some(byte) => (byte, 4),
+                                None => return Err(TextError::Syntax { position: pos, reason: "invalid \\x escape", }),
+                            },
+                            _ => return Err(TextError::Syntax { position: pos, reason: "invalid escape", }),
+                        };
+                        token.push(byte);
+                        pos += len;
+                    },
+                    Some(&byte) => {
+                        token.push(byte);
+                        pos += 1;
+                    },
+                }
+            }
+            pos += 1;
+            if pos < line.len() && !line[pos].is_ascii_whitespace() {
+                return Err(TextError::Syntax { position: pos, reason: "quoted string must be followed by a space", });
+            }
+        } else {
+            while pos < line.len() && !line[pos].is_ascii_whitespace() {
+                token.push(line[pos]);
+                pos += 1;
+            }
+        }
+        tokens.push(token);
+    }
+}
+
+fn is_bare(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes.iter().all(|&byte| byte.is_ascii_graphic() && byte != b'"' && byte != b'\\')
+}
+
+fn put_word(line: &mut String, bytes: &[u8]) {
+    line.push(' ');
+    if is_bare(bytes) {
+        line.extend(bytes.iter().map(|&byte| byte as char));
+        return;
+    }
+    line.push('"');
+    for &byte in bytes {
+        match byte {
+            b'\\' => line.push_str("\\\\"),
+            b'"' => line.push_str("\\\""),
+            b'\n' => line.push_str("\\n"),
+            b'\r' => line.push_str("\\r"),
+            b'\t' => line.push_str("\\t"),
+            b' ' ..= b'~' => line.push(byte as char),
+            byte => line.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    line.push('"');
+}
+
+fn put_display<T>(line: &mut String, value: T) where T: fmt::Display {
+    line.push(' ');
+    line.push_str(&value.to_string());
+}
+
+struct Args {
+    command: &'static str,
+    tokens: std::vec::IntoIter<Vec<u8>>,
+}
+
+impl Args {
+    fn next(&mut self, argument: &'static str) -> Result<Vec<u8>, TextError> {
+        self.tokens.next().ok_or(TextError::MissingArgument { command: self.command, argument: argument, })
+    }
+
+    fn invalid(&self, argument: &'static str, value: &[u8]) -> TextError {
+        TextError::InvalidArgument { command: self.command, argument: argument, value: String::from_utf8_lossy(value).into_owned(), }
+    }
+
+    fn bytes(&mut self, argument: &'static str) -> Result<Key, TextError> {
+        self.next(argument).map(Arc::from)
+    }
+
+    fn string(&mut self, argument: &'static str) -> Result<String, TextError> {
+        let token = self.next(argument)?;
+        String::from_utf8(token).map_err(|err| self.invalid(argument, err.as_bytes()))
+    }
+
+    fn number<T>(&mut self, argument: &'static str) -> Result<T, TextError> where T: FromStr {
+        let token = self.next(argument)?;
+        std::str::from_utf8(&token).ok().and_then(|value| value.parse().ok()).ok_or_else(|| self.invalid(argument, &token))
+    }
+
+    fn keyword<T, F>(&mut self, argument: &'static str, parse: F) -> Result<T, TextError> where F: FnOnce(&str) -> Option<T> {
+        let token = self.next(argument)?;
+        std::str::from_utf8(&token).ok().and_then(|value| parse(&value.to_ascii_uppercase())).ok_or_else(|| self.invalid(argument, &token))
+    }
+
+    fn named<T>(&mut self, argument: &'static str) -> Result<T, TextError> where T: FromStr {
+        let token = self.next(argument)?;
+        std::str::from_utf8(&token).ok()
+            .and_then(|pair| pair.strip_prefix(argument))
+            .and_then(|pair| pair.strip_prefix('='))
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| self.invalid(argument, &token))
+    }
+
+    fn rest(self) -> Vec<Vec<u8>> {
+        self.tokens.collect()
+    }
+
+    fn finish<T>(mut self, value: T) -> Result<T, TextError> {
+        match self.tokens.next() {
+            None => Ok(value),
+            Some(..) => Err(TextError::UnexpectedArgument { command: self.command, }),
+        }
+    }
+}
+
+const REQ_COMMANDS: &[&str] = &[
+    "PING", "COUNT", "ADD", "UPDATE", "LOOKUP", "REMOVE", "LEND", "REPAY", "HEARTBEAT", "STATS", "FLUSH", "TERMINATE", "INFO", "AUTH",
+];
+
+const REP_COMMANDS: &[&str] = &[
+    "PONG", "COUNTED", "ADDED", "KEPT", "UPDATED", "NOT_FOUND", "VALUE_FOUND", "VALUE_NOT_FOUND", "REMOVED", "NOT_REMOVED", "LENT",
+    "QUEUE_EMPTY", "REPAID", "HEARTBEATEN", "SKIPPED", "STATS_GOT", "FLUSHED", "TERMINATED", "ERROR", "INFO_GOT", "AUTH_OK",
+    "AUTH_FAILED", "AUTH_CHALLENGE", "UNAUTHENTICATED", "FORBIDDEN", "SERVER_ERROR",
+];
+
+fn split_command(line: &[u8], commands: &[&'static str]) -> Result<Args, TextError> {
+    let mut tokens = tokenize(line)?.into_iter();
+    let command = tokens.next().ok_or(TextError::Empty)?;
+    let command = String::from_utf8_lossy(&command).to_ascii_uppercase();
+    match commands.iter().find(|&&known| known == command) {
+        Some(&known) => Ok(Args { command: known, tokens: tokens, }),
+        None => Err(TextError::UnknownCommand(command)),
+    }
+}
+
+fn add_mode_word(mode: &AddMode) -> &'static str {
+    match mode { &AddMode::Head => "HEAD", &AddMode::Tail => "TAIL", }
+}
+
+fn add_mode(word: &str) -> Option<AddMode> {
+    match word { "HEAD" => Some(AddMode::Head), "TAIL" => Some(AddMode::Tail), _ => None, }
+}
+
+fn lend_mode_word(mode: &LendMode) -> &'static str {
+    match mode { &LendMode::Block => "BLOCK", &LendMode::Poll => "POLL", }
+}
+
+fn lend_mode(word: &str) -> Option<LendMode> {
+    match word { "BLOCK" => Some(LendMode::Block), "POLL" => Some(LendMode::Poll), _ => None, }
+}
+
+fn repay_status_word(status: &RepayStatus) -> &'static str {
+    match status {
+        &RepayStatus::Penalty => "PENALTY",
+        &RepayStatus::Reward => "REWARD",
+        &RepayStatus::Front => "FRONT",
+        &RepayStatus::Drop => "DROP",
+    }
+}
+
+fn repay_status(word: &str) -> Option<RepayStatus> {
+    match word {
+        "PENALTY" => Some(RepayStatus::Penalty),
+        "REWARD" => Some(RepayStatus::Reward),
+        "FRONT" => Some(RepayStatus::Front),
+        "DROP" => Some(RepayStatus::Drop),
+        _ => None,
+    }
+}
+
+fn auth_mechanism_word(mechanism: &AuthMechanism) -> &'static str {
+    match mechanism { &AuthMechanism::Token => "TOKEN", &AuthMechanism::HmacSha256 => "HMAC_SHA256", }
+}
+
+fn auth_mechanism(word: &str) -> Option<AuthMechanism> {
+    match word { "TOKEN" => Some(AuthMechanism::Token), "HMAC_SHA256" => Some(AuthMechanism::HmacSha256), _ => None, }
+}
+
+fn message_kind(word: &str) -> Option<MessageKind> {
+    MessageKind::ALL.iter().find(|kind| kind.name().eq_ignore_ascii_case(word)).cloned()
+}
+
+fn field_kind(word: &str) -> Option<FieldKind> {
+    FieldKind::ALL.iter().find(|kind| kind.name().eq_ignore_ascii_case(word)).cloned()
+}
+
+/// Parses a request line.
+pub fn parse_req(line: &[u8]) -> Result<GlobalReq, TextError> {
+    let mut args = split_command(line, REQ_COMMANDS)?;
+    let req = match args.command {
+        "PING" => GlobalReq::Ping,
+        "COUNT" => GlobalReq::Count,
+        "ADD" => GlobalReq::Add { key: args.bytes("key")?, value: args.bytes("value")?, mode: args.keyword("mode", add_mode)?, },
+        "UPDATE" => GlobalReq::Update(args.bytes("key")?, args.bytes("value")?),
+        "LOOKUP" => GlobalReq::Lookup(args.bytes("key")?),
+        "REMOVE" => GlobalReq::Remove(args.bytes("key")?),
+        "LEND" => GlobalReq::Lend { timeout: args.number("timeout")?, mode: args.keyword("mode", lend_mode)?, },
+        "REPAY" => GlobalReq::Repay {
+            lend_key: args.number("lend_key")?,
+            key: args.bytes("key")?,
+            value: args.bytes("value")?,
+            status: args.keyword("status", repay_status)?,
+        },
+        "HEARTBEAT" => GlobalReq::Heartbeat { lend_key: args.number("lend_key")?, key: args.bytes("key")?, timeout: args.number("timeout")?, },
+        "STATS" => GlobalReq::Stats,
+        "FLUSH" => GlobalReq::Flush,
+        "TERMINATE" => GlobalReq::Terminate,
+        "INFO" => GlobalReq::Info,
+        "AUTH" => GlobalReq::Auth { mechanism: args.keyword("mechanism", auth_mechanism)?, payload: args.bytes("payload")?, },
+        other => unreachable!("unhandled request command {}", other),
+    };
+    args.finish(req)
+}
+
+/// Renders a request as a line without the trailing line break.
+pub fn render_req(req: &GlobalReq) -> String {
+    let mut line = String::new();
+    match req {
+        &GlobalReq::Ping =>
+            line.push_str("PING"),
+        &GlobalReq::Count =>
+            line.push_str("COUNT"),
+        &GlobalReq::Add { ref key, ref value, ref mode, } => {
+            line.push_str("ADD");
+            put_word(&mut line, key);
+            put_word(&mut line, value);
+            put_display(&mut line, add_mode_word(mode));
+        },
+        &GlobalReq::Update(ref key, ref value) => {
+            line.push_str("UPDATE");
+            put_word(&mut line, key);
+            put_word(&mut line, value);
+        },
+        &GlobalReq::Lookup(ref key) => {
+            line.push_str("LOOKUP");
+            put_word(&mut line, key);
+        },
+        &GlobalReq::Remove(ref key) => {
+            line.push_str("REMOVE");
+            put_word(&mut line, key);
+        },
+        &GlobalReq::Lend { timeout, ref mode, } => {
+            line.push_str("LEND");
+            put_display(&mut line, timeout);
+            put_display(&mut line, lend_mode_word(mode));
+        },
+        &GlobalReq::Repay { lend_key, ref key, ref value, ref status, } => {
+            line.push_str("REPAY");
+            put_display(&mut line, lend_key);
+            put_word(&mut line, key);
+            put_word(&mut line, value);
+            put_display(&mut line, repay_status_word(status));
+        },
+        &GlobalReq::Heartbeat { lend_key, ref key, timeout, } => {
+            line.push_str("HEARTBEAT");
+            put_display(&mut line, lend_key);
+            put_word(&mut line, key);
+            put_display(&mut line, timeout);
+        },
+        &GlobalReq::Stats =>
+            line.push_str("STATS"),
+        &GlobalReq::Flush =>
+            line.push_str("FLUSH"),
+        &GlobalReq::Terminate =>
+            line.push_str("TERMINATE"),
+        &GlobalReq::Info =>
+            line.push_str("INFO"),
+        &GlobalReq::Auth { ref mechanism, ref payload, } => {
+            line.push_str("AUTH");
+            put_display(&mut line, auth_mechanism_word(mechanism));
+            put_word(&mut line, payload);
+        },
+    }
+    line
+}
+
+fn parse_proto_error(args: &mut Args) -> Result<ProtoError, TextError> {
+    let error = args.keyword("error", |word| Some(word.to_string()))?;
+    match &*error {
+        "NOT_ENOUGH_DATA" => Ok(ProtoError::NotEnoughData {
+            message: args.keyword("message", message_kind)?,
+            field: args.keyword("field", field_kind)?,
+            required: args.number("required")?,
+            given: args.number("given")?,
+        }),
+        "INVALID_TAG" => Ok(ProtoError::InvalidTag {
+            message: args.keyword("message", message_kind)?,
+            field: args.keyword("field", field_kind)?,
+            tag: args.number("tag")?,
+        }),
+        "INVALID_UTF8" => Ok(ProtoError::InvalidUtf8 {
+            message: args.keyword("message", message_kind)?,
+            field: args.keyword("field", field_kind)?,
+        }),
+        "DB_QUEUE_OUT_OF_SYNC" =>
+            Ok(ProtoError::DbQueueOutOfSync(args.bytes("key")?)),
+        _ =>
+            Err(args.invalid("error", error.as_bytes())),
+    }
+}
+
+fn put_proto_error(line: &mut String, error: &ProtoError) {
+    match error {
+        &ProtoError::NotEnoughData { message, field, required, given, } => {
+            put_display(line, "NOT_ENOUGH_DATA");
+            put_display(line, message.name());
+            put_display(line, field.name());
+            put_display(line, required);
+            put_display(line, given);
+        },
+        &ProtoError::InvalidTag { message, field, tag, } => {
+            put_display(line, "INVALID_TAG");
+            put_display(line, message.name());
+            put_display(line, field.name());
+            put_display(line, tag);
+        },
+        &ProtoError::InvalidUtf8 { message, field, } => {
+            put_display(line, "INVALID_UTF8");
+            put_display(line, message.name());
+            put_display(line, field.name());
+        },
+        &ProtoError::DbQueueOutOfSync(ref key) => {
+            put_display(line, "DB_QUEUE_OUT_OF_SYNC");
+            put_word(line, key);
+        },
+    }
+}
+
+/// Parses a reply line.
+pub fn parse_rep(line: &[u8]) -> Result<GlobalRep, TextError> {
+    let mut args = split_command(line, REP_COMMANDS)?;
+    let rep = match args.command {
+        "PONG" => GlobalRep::Pong,
+        "COUNTED" => GlobalRep::Counted(args.number("count")?),
+        "ADDED" => GlobalRep::Added,
+        "KEPT" => GlobalRep::Kept,
+        "UPDATED" => GlobalRep::Updated,
+        "NOT_FOUND" => GlobalRep::NotFound,
+        "VALUE_FOUND" => GlobalRep::ValueFound(args.bytes("value")?),
+        "VALUE_NOT_FOUND" => GlobalRep::ValueNotFound,
+        "REMOVED" => GlobalRep::Removed,
+        "NOT_REMOVED" => GlobalRep::NotRemoved,
+        "LENT" => GlobalRep::Lent { lend_key: args.number("lend_key")?, key: args.bytes("key")?, value: args.bytes("value")?, },
+        "QUEUE_EMPTY" => GlobalRep::QueueEmpty,
+        "REPAID" => GlobalRep::Repaid,
+        "HEARTBEATEN" => GlobalRep::Heartbeaten,
+        "SKIPPED" => GlobalRep::Skipped,
+        "STATS_GOT" => GlobalRep::StatsGot {
+            ping: args.named("ping")?,
+            count: args.named("count")?,
+            add: args.named("add")?,
+            update: args.named("update")?,
+            lookup: args.named("lookup")?,
+            remove: args.named("remove")?,
+            lend: args.named("lend")?,
+            repay: args.named("repay")?,
+            heartbeat: args.named("heartbeat")?,
+            stats: args.named("stats")?,
+        },
+        "FLUSHED" => GlobalRep::Flushed,
+        "TERMINATED" => GlobalRep::Terminated,
+        "ERROR" => GlobalRep::Error(parse_proto_error(&mut args)?),
+        "INFO_GOT" => {
+            let server_version = args.string("server_version")?;
+            let proto_version = args.number("proto_version")?;
+            let uptime_ms = args.number("uptime_ms")?;
+            let db_path = args.string("db_path")?;
+            let max_key_len = args.number("max_key_len")?;
+            let max_value_len = args.number("max_value_len")?;
+            let mut features = Vec::new();
+            for feature in args.rest() {
+                match String::from_utf8(feature) {
+                    Ok(feature) => features.push(feature),
+                    Err(err) => return Err(TextError::InvalidArgument {
+                        command: "INFO_GOT",
+                        argument: "feature",
+                        value: String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                    }),
+                }
+            }
+            return Ok(GlobalRep::InfoGot {
+                server_version: server_version,
+                proto_version: proto_version,
+                uptime_ms: uptime_ms,
+                db_path: db_path,
+                max_key_len: max_key_len,
+                max_value_len: max_value_len,
+                features: features,
+            });
+        },
+        "AUTH_OK" => GlobalRep::AuthOk,
+        "AUTH_FAILED" => GlobalRep::AuthFailed,
+        "AUTH_CHALLENGE" => GlobalRep::AuthChallenge(args.bytes("nonce")?),
+        "UNAUTHENTICATED" => GlobalRep::Unauthenticated,
+        "FORBIDDEN" => GlobalRep::Forbidden,
+        "SERVER_ERROR" => GlobalRep::ServerError { code: ServerErrorCode::from_code(args.number("code")?), message: args.string("message")?, },
+        other => unreachable!("unhandled reply command {}", other),
+    };
+    args.finish(rep)
+}
+
+/// Renders a reply as a line without the trailing line break.
+pub fn render_rep(rep: &GlobalRep) -> String {
+    let mut line = String::new();
+    match rep {
+        &GlobalRep::Pong =>
+            line.push_str("PONG"),
+        &GlobalRep::Counted(count) => {
+            line.push_str("COUNTED");
+            put_display(&mut line, count);
+        },
+        &GlobalRep::Added =>
+            line.push_str("ADDED"),
+        &GlobalRep::Kept =>
+            line.push_str("KEPT"),
+        &GlobalRep::Updated =>
+            line.push_str("UPDATED"),
+        &GlobalRep::NotFound =>
+            line.push_str("NOT_FOUND"),
+        &GlobalRep::ValueFound(ref value) => {
+            line.push_str("VALUE_FOUND");
+            put_word(&mut line, value);
+        },
+        &GlobalRep::ValueNotFound =>
+            line.push_str("VALUE_NOT_FOUND"),
+        &GlobalRep::Removed =>
+            line.push_str("REMOVED"),
+        &GlobalRep::NotRemoved =>
+            line.push_str("NOT_REMOVED"),
+        &GlobalRep::Lent { lend_key, ref key, ref value, } => {
+            line.push_str("LENT");
+            put_display(&mut line, lend_key);
+            put_word(&mut line, key);
+            put_word(&mut line, value);
+        },
+        &GlobalRep::QueueEmpty =>
+            line.push_str("QUEUE_EMPTY"),
+        &GlobalRep::Repaid =>
+            line.push_str("REPAID"),
+        &GlobalRep::Heartbeaten =>
+            line.push_str("HEARTBEATEN"),
+        &GlobalRep::Skipped =>
+            line.push_str("SKIPPED"),
+        &GlobalRep::StatsGot { ping, count, add, update, lookup, remove, lend, repay, heartbeat, stats, } => {
+            line.push_str("STATS_GOT");
+            for &(name, value) in &[
+                ("ping", ping), ("count", count), ("add", add), ("update", update), ("lookup", lookup),
+                ("remove", remove), ("lend", lend), ("repay", repay), ("heartbeat", heartbeat), ("stats", stats),
+            ] {
+                put_display(&mut line, format_args!("{}={}", name, value));
+            }
+        },
+        &GlobalRep::Flushed =>
+            line.push_str("FLUSHED"),
+        &GlobalRep::Terminated =>
+            line.push_str("TERMINATED"),
+        &GlobalRep::Error(ref error) => {
+            line.push_str("ERROR");
+            put_proto_error(&mut line, error);
+        },
+        &GlobalRep::InfoGot { ref server_version, proto_version, uptime_ms, ref db_path, max_key_len, max_value_len, ref features, } => {
+            line.push_str("INFO_GOT");
+            put_word(&mut line, server_version.as_bytes());
+            put_display(&mut line, proto_version);
+            put_display(&mut line, uptime_ms);
+            put_word(&mut line, db_path.as_bytes());
+            put_display(&mut line, max_key_len);
+            put_display(&mut line, max_value_len);
+            for feature in features {
+                put_word(&mut line, feature.as_bytes());
+            }
+        },
+        &GlobalRep::AuthOk =>
+            line.push_str("AUTH_OK"),
+        &GlobalRep::AuthFailed =>
+            line.push_str("AUTH_FAILED"),
+        &GlobalRep::AuthChallenge(ref nonce) => {
+            line.push_str("AUTH_CHALLENGE");
+            put_word(&mut line, nonce);
+        },
+        &GlobalRep::Unauthenticated =>
+            line.push_str("UNAUTHENTICATED"),
+        &GlobalRep::Forbidden =>
+            line.push_str("FORBIDDEN"),
+        &GlobalRep::ServerError { ref code, ref message, } => {
+            line.push_str("SERVER_ERROR");
+            put_display(&mut line, code.code());
+            put_word(&mut line, message.as_bytes());
+        },
+    }
+    line
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    Binary(DecodeError),
+    Text(TextError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &FrameError::Binary(ref err) => write!(f, "binary frame: {}", err),
+            &FrameError::Text(ref err) => write!(f, "text frame: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Frame encoding chosen per connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Binary,
+    Text,
+}
+
+impl Mode {
+    /// Guesses the mode from the first frame of a connection: binary frames
+    /// start with a small tag, text frames start with a command letter.
+    pub fn detect(frame: &[u8]) -> Mode {
+        match frame.first() {
+            Some(byte) if byte.is_ascii_alphabetic() => Mode::Text,
+            _ => Mode::Binary,
+        }
+    }
+
+    pub fn encode_req(&self, req: &GlobalReq) -> Vec<u8> {
+        match self {
+            &Mode::Binary => {
+                let mut frame = vec![0; req.encode_len()];
+                req.encode(&mut frame);
+                frame
+            },
+            &Mode::Text => {
+                let mut line = render_req(req);
+                line.push('\n');
+                line.into_bytes()
+            },
+        }
+    }
+
+    pub fn decode_req(&self, frame: &[u8]) -> Result<GlobalReq, FrameError> {
+        match self {
+            &Mode::Binary => codec::decode_req(frame).map_err(FrameError::Binary),
+            &Mode::Text => parse_req(frame).map_err(FrameError::Text),
+        }
+    }
+
+    pub fn encode_rep(&self, rep: &GlobalRep) -> Vec<u8> {
+        match self {
+            &Mode::Binary => {
+                let mut frame = vec![0; rep.encode_len()];
+                rep.encode(&mut frame);
+                frame
+            },
+            &Mode::Text => {
+                let mut line = render_rep(rep);
+                line.push('\n');
+                line.into_bytes()
+            },
+        }
+    }
+
+    pub fn decode_rep(&self, frame: &[u8]) -> Result<GlobalRep, FrameError> {
+        match self {
+            &Mode::Binary => codec::decode_rep(frame).map_err(FrameError::Binary),
+            &Mode::Text => parse_rep(frame).map_err(FrameError::Text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{RepayStatus, LendMode, AddMode, GlobalReq, GlobalRep, ProtoError, MessageKind, FieldKind};
+    use super::super::codec::DecodeError;
+    use super::super::test_support::bytes;
+    use super::{TextError, FrameError, Mode, parse_req, render_req, parse_rep, render_rep};
+
+    #[test]
+    fn parse_requests() {
+        assert_eq!(parse_req(b"ADD cat small TAIL"),
+                   Ok(GlobalReq::Add { key: bytes(b"cat"), value: bytes(b"small"), mode: AddMode::Tail, }));
+        assert_eq!(parse_req(b"lend 1000 poll\r\n"), Ok(GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, }));
+        assert_eq!(parse_req(b"REPAY 1 cat big REWARD"),
+                   Ok(GlobalReq::Repay { lend_key: 1, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Reward, }));
+        assert_eq!(parse_req(b"  PING\n"), Ok(GlobalReq::Ping));
+    }
+
+    #[test]
+    fn quoted_binary_keys() {
+        let req = GlobalReq::Update(bytes(b"a \"b\"\\"), bytes(&[0, 0xff, b'\n']));
+        let line = render_req(&req);
+        assert_eq!(line, r#"UPDATE "a \"b\"\\" "\x00\xff\n""#);
+        assert_eq!(parse_req(line.as_bytes()), Ok(req));
+        assert_eq!(render_req(&GlobalReq::Lookup(bytes(b""))), r#"LOOKUP """#);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_req(b" \n"), Err(TextError::Empty));
+        assert_eq!(parse_req(b"POP"), Err(TextError::UnknownCommand("POP".to_string())));
+        assert_eq!(parse_req(b"ADD cat"), Err(TextError::MissingArgument { command: "ADD", argument: "value", }));
+        assert_eq!(parse_req(b"PING now"), Err(TextError::UnexpectedArgument { command: "PING", }));
+        assert_eq!(parse_req(b"LEND soon POLL"),
+                   Err(TextError::InvalidArgument { command: "LEND", argument: "timeout", value: "soon".to_string(), }));
+        assert_eq!(parse_req(b"LOOKUP \"cat"), Err(TextError::Syntax { position: 11, reason: "unterminated quoted string", }));
+        assert_eq!(parse_req(b"LOOKUP \"\\x0g\""), Err(TextError::Syntax { position: 8, reason: "invalid \\x escape", }));
+    }
+
+    #[test]
+    fn replies() {
+        let rep = GlobalRep::Lent { lend_key: 7, key: bytes(b"cat"), value: bytes(b"big fish"), };
+        assert_eq!(render_rep(&rep), r#"LENT 7 cat "big fish""#);
+        assert_eq!(parse_rep(render_rep(&rep).as_bytes()), Ok(rep));
+        let rep = GlobalRep::Error(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalReqAdd,
+            field: FieldKind::KeyLen,
+            required: 4,
+            given: 1,
+        });
+        assert_eq!(render_rep(&rep), "ERROR NOT_ENOUGH_DATA GlobalReq::Add key_len 4 1");
+        assert_eq!(parse_rep(render_rep(&rep).as_bytes()), Ok(rep));
+        assert_eq!(render_rep(&GlobalRep::StatsGot {
+            ping: 1, count: 2, add: 3, update: 4, lookup: 5, remove: 6, lend: 7, repay: 8, heartbeat: 9, stats: 10,
+        }), "STATS_GOT ping=1 count=2 add=3 update=4 lookup=5 remove=6 lend=7 repay=8 heartbeat=9 stats=10");
+    }
+
+    #[test]
+    fn mode_per_connection() {
+        let req = GlobalReq::Lookup(bytes(b"cat"));
+        for &mode in &[Mode::Binary, Mode::Text] {
+            let frame = mode.encode_req(&req);
+            assert_eq!(Mode::detect(&frame), mode);
+            assert_eq!(mode.decode_req(&frame), Ok(GlobalReq::Lookup(bytes(b"cat"))));
+            assert_eq!(mode.decode_rep(&mode.encode_rep(&GlobalRep::Pong)), Ok(GlobalRep::Pong));
+        }
+        assert_eq!(Mode::Text.encode_rep(&GlobalRep::Added), b"ADDED\n");
+        assert_eq!(Mode::Binary.decode_req(&[11, 0]), Err(FrameError::Binary(DecodeError::TrailingBytes(1))));
+        assert_eq!(Mode::Binary.decode_rep(&[17, 0, 0]), Err(FrameError::Binary(DecodeError::TrailingBytes(2))));
+    }
+
+    #[cfg(feature = "arbitrary")]
+    proptest::proptest! {
+        #[test]
+        fn text_round_trip(req in proptest::prelude::any::<GlobalReq>(), rep in proptest::prelude::any::<GlobalRep>()) {
+            proptest::prop_assert_eq!(parse_req(render_req(&req).as_bytes()), Ok(req));
+            proptest::prop_assert_eq!(parse_rep(render_rep(&rep).as_bytes()), Ok(rep));
+        }
+    }
+}
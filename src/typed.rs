@@ -0,0 +1,390 @@
+//! Typed keys and values on top of `SyncClient`.
+//!
+//! A `TaskCodec` turns keys or values into the bytes stored by spiderq and
+//! back. Failures of a codec are reported as `TypedError::Encode` or
+//! `TypedError::Decode`, apart from protocol and transport errors which stay
+//! in `TypedError::Client`.
+//!
+//! A lent `TypedTask` keeps the key bytes it was stored with, and `repay` and
+//! `heartbeat` send those back: a codec may not encode a decoded key to the
+//! same bytes (e.g. JSON with a different field order).
+
+use std::{
+    fmt,
+    error::Error,
+    net::TcpStream,
+    sync::Arc,
+};
+
+use super::{
+    Key,
+    Value,
+    LendMode,
+    AddMode,
+    RepayStatus,
+};
+use super::client::{
+    ClientError,
+    LentTask,
+    SyncClient,
+    Transport,
+};
+
+pub trait TaskCodec<T> {
+    fn encode(&self, item: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode(&self, bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+#[derive(Debug)]
+pub struct CodecError {
+    pub codec: &'static str,
+    pub error: Box<dyn Error + Send + Sync>,
+}
+
+impl CodecError {
+    pub fn new<E>(codec: &'static str, error: E) -> CodecError where E: Into<Box<dyn Error + Send + Sync>> {
+        CodecError { codec: codec, error: error.into(), }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} codec: {}", self.codec, self.error)
+    }
+}
+
+impl Error for CodecError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// Bytes as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Raw;
+
+impl TaskCodec<Vec<u8>> for Raw {
+    fn encode(&self, item: &Vec<u8>) -> Result<Vec<u8>, CodecError> {
+        Ok(item.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, CodecError> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl TaskCodec<Arc<[u8]>> for Raw {
+    fn encode(&self, item: &Arc<[u8]>) -> Result<Vec<u8>, CodecError> {
+        Ok(item.to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Arc<[u8]>, CodecError> {
+        Ok(Arc::from(bytes))
+    }
+}
+
+/// `String` as UTF-8, invalid UTF-8 fails to decode.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Utf8;
+
+impl TaskCodec<String> for Utf8 {
+    fn encode(&self, item: &String) -> Result<Vec<u8>, CodecError> {
+        Ok(item.as_bytes().to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String, CodecError> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| CodecError::new("utf8", err))
+    }
+}
+
+macro_rules! serde_codec {
+    ($(#[$attr:meta])* $feature:literal, $codec:ident, $name:literal, $encode:path, $decode:path) => {
+        $(#[$attr])*
+        #[cfg(feature = $feature)]
+        #[derive(Clone, Copy, Debug, Default)]
+        pub struct $codec;
+
+        #[cfg(feature = $feature)]
+        impl<T> TaskCodec<T> for $codec where T: serde::Serialize + serde::de::DeserializeOwned {
+            fn encode(&self, item: &T) -> Result<Vec<u8>, CodecError> {
+                $encode(item).map_err(|err| CodecError::new($name, err))
+            }
+
+            fn decode(&self, bytes: &[u8]) -> Result<T, CodecError> {
+                $decode(bytes).map_err(|err| CodecError::new($name, err))
+            }
+        }
+    };
+}
+
+serde_codec!(
+    /// Any serde type as JSON, readable with other tools.
+    "serde-json", Json, "json", serde_json::to_vec, serde_json::from_slice
+);
+serde_codec!(
+    /// Any serde type with `bincode`.
+    "serde-bincode", Bincode, "bincode", bincode::serialize, bincode::deserialize
+);
+serde_codec!(
+    /// Any serde type with `postcard`, the most compact of the three.
+    "serde-postcard", Postcard, "postcard", postcard::to_allocvec, postcard::from_bytes
+);
+
+#[derive(Debug)]
+pub enum TypedError {
+    Client(ClientError),
+    Encode(CodecError),
+    Decode(CodecError),
+    /// A task was lent but its key or value could not be decoded. It stays
+    /// lent until its timeout, so it is handed over to be repaid, e.g. with `Drop`.
+    UndecodableTask(LentTask, CodecError),
+}
+
+impl TypedError {
+    pub fn is_codec(&self) -> bool {
+        !matches!(self, &TypedError::Client(..))
+    }
+}
+
+impl fmt::Display for TypedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TypedError::Client(ref err) => err.fmt(f),
+            &TypedError::Encode(ref err) => write!(f, "encode failed: {}", err),
+            &TypedError::Decode(ref err) => write!(f, "decode failed: {}", err),
+            &TypedError::UndecodableTask(ref task, ref err) => write!(f, "decode of lent task {} failed: {}", task.lend_key, err),
+        }
+    }
+}
+
+impl Error for TypedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            &TypedError::Client(ref err) => Some(err),
+            &TypedError::Encode(ref err) | &TypedError::Decode(ref err) | &TypedError::UndecodableTask(_, ref err) => Some(err),
+        }
+    }
+}
+
+impl From<ClientError> for TypedError {
+    fn from(err: ClientError) -> TypedError {
+        TypedError::Client(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypedTask<K, V> {
+    pub lend_key: u64,
+    /// Key bytes as stored by the server.
+    pub raw_key: Key,
+    pub key: K,
+    pub value: V,
+}
+
+type Codec<T> = Box<dyn TaskCodec<T> + Send + Sync>;
+
+pub struct TypedClient<K, V, S = TcpStream> {
+    client: SyncClient<S>,
+    keys: Codec<K>,
+    values: Codec<V>,
+}
+
+impl<K, V, S> TypedClient<K, V, S> where S: Transport {
+    pub fn new<KC, VC>(client: SyncClient<S>, keys: KC, values: VC) -> TypedClient<K, V, S>
+        where KC: TaskCodec<K> + Send + Sync + 'static, VC: TaskCodec<V> + Send + Sync + 'static
+    {
+        TypedClient {
+            client: client,
+            keys: Box::new(keys),
+            values: Box::new(values),
+        }
+    }
+
+    pub fn get_ref(&self) -> &SyncClient<S> {
+        &self.client
+    }
+
+    pub fn get_mut(&mut self) -> &mut SyncClient<S> {
+        &mut self.client
+    }
+
+    pub fn into_inner(self) -> SyncClient<S> {
+        self.client
+    }
+
+    fn key(&self, key: &K) -> Result<Value, TypedError> {
+        self.keys.encode(key).map(Arc::from).map_err(TypedError::Encode)
+    }
+
+    fn value(&self, value: &V) -> Result<Value, TypedError> {
+        self.values.encode(value).map(Arc::from).map_err(TypedError::Encode)
+    }
+
+    /// Returns `false` if the key already exists (the entry is kept).
+    pub fn add(&mut self, key: &K, value: &V, mode: AddMode) -> Result<bool, TypedError> {
+        let (key, value) = (self.key(key)?, self.value(value)?);
+        Ok(self.client.add(key, value, mode)?)
+    }
+
+    /// Returns `false` if there is no entry with the key.
+    pub fn update(&mut self, key: &K, value: &V) -> Result<bool, TypedError> {
+        let (key, value) = (self.key(key)?, self.value(value)?);
+        Ok(self.client.update(key, value)?)
+    }
+
+    pub fn lookup(&mut self, key: &K) -> Result<Option<V>, TypedError> {
+        let key = self.key(key)?;
+        match self.client.lookup(key)? {
+            Some(value) => self.values.decode(&value).map(Some).map_err(TypedError::Decode),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns `false` if there is no entry with the key.
+    pub fn remove(&mut self, key: &K) -> Result<bool, TypedError> {
+        let key = self.key(key)?;
+        Ok(self.client.remove(key)?)
+    }
+
+    /// Returns `None` if the queue is empty (only in `LendMode::Poll`).
+    pub fn lend(&mut self, timeout_ms: u64, mode: LendMode) -> Result<Option<TypedTask<K, V>>, TypedError> {
+        let task = match self.client.lend(timeout_ms, mode)? {
+            Some(task) => task,
+            None => return Ok(None),
+        };
+        let decoded = self.keys.decode(&task.key)
+            .and_then(|key| self.values.decode(&task.value).map(|value| (key, value)));
+        match decoded {
+            Ok((key, value)) => Ok(Some(TypedTask { lend_key: task.lend_key, raw_key: task.key, key: key, value: value, })),
+            Err(err) => Err(TypedError::UndecodableTask(task, err)),
+        }
+    }
+
+    /// Returns `false` if the task is not lent anymore (e.g. its timeout has expired).
+    pub fn repay(&mut self, task: &TypedTask<K, V>, value: &V, status: RepayStatus) -> Result<bool, TypedError> {
+        let value = self.value(value)?;
+        Ok(self.client.repay(task.lend_key, task.raw_key.clone(), value, status)?)
+    }
+
+    /// Returns `false` if the task is not lent anymore.
+    pub fn heartbeat(&mut self, task: &TypedTask<K, V>, timeout_ms: u64) -> Result<bool, TypedError> {
+        Ok(self.client.heartbeat(task.lend_key, task.raw_key.clone(), timeout_ms)?)
+    }
+
+    pub fn count(&mut self) -> Result<usize, TypedError> {
+        Ok(self.client.count()?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::Duration,
+    };
+    use super::super::{LendMode, AddMode, RepayStatus, GlobalReq, GlobalRep};
+    use super::super::client::{SyncClient, ClientError};
+    use super::super::test_support::{bytes, serve};
+    use super::{TaskCodec, CodecError, TypedClient, TypedTask, TypedError, Raw, Utf8};
+
+    /// Decodes keys case insensitively, so a decoded key does not encode back to the same bytes.
+    struct Lowercase;
+
+    impl TaskCodec<String> for Lowercase {
+        fn encode(&self, item: &String) -> Result<Vec<u8>, CodecError> {
+            Ok(item.to_lowercase().into_bytes())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<String, CodecError> {
+            Utf8.decode(bytes).map(|key| key.to_lowercase())
+        }
+    }
+
+    #[test]
+    fn builtin_codecs() {
+        assert_eq!(Utf8.encode(&"кот".to_string()).unwrap(), "кот".as_bytes());
+        assert_eq!(Utf8.decode("кот".as_bytes()).unwrap(), "кот");
+        let err = Utf8.decode(&[0xff]).unwrap_err();
+        assert_eq!(err.codec, "utf8");
+        let raw: Arc<[u8]> = Raw.decode(&[1, 2]).unwrap();
+        assert_eq!(&raw[..], &[1, 2]);
+    }
+
+    #[cfg(all(feature = "serde-json", feature = "serde-bincode", feature = "serde-postcard"))]
+    #[test]
+    fn serde_codecs() {
+        use super::{Json, Bincode, Postcard};
+
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Page {
+            url: String,
+            depth: u32,
+        }
+
+        let page = Page { url: "http://example.com".to_string(), depth: 3, };
+        assert_eq!(Json.encode(&page).unwrap(), br#"{"url":"http://example.com","depth":3}"#);
+        assert_eq!(TaskCodec::<Page>::decode(&Json, &Json.encode(&page).unwrap()).unwrap(), page);
+        assert_eq!(TaskCodec::<Page>::decode(&Bincode, &Bincode.encode(&page).unwrap()).unwrap(), page);
+        assert_eq!(TaskCodec::<Page>::decode(&Postcard, &Postcard.encode(&page).unwrap()).unwrap(), page);
+        assert_eq!(TaskCodec::<Page>::decode(&Json, b"{}").unwrap_err().codec, "json");
+    }
+
+    #[test]
+    fn typed_client() {
+        let (addr, _server) = serve(vec![
+            GlobalReq::Add { key: bytes(b"cat"), value: bytes(b"small"), mode: AddMode::Tail, },
+            GlobalReq::Lookup(bytes(b"cat")),
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Repay { lend_key: 1, key: bytes(b"cat"), value: bytes(b"big"), status: RepayStatus::Reward, },
+            GlobalReq::Lookup(bytes(b"dog")),
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Count,
+        ], vec![
+            GlobalRep::Added,
+            GlobalRep::ValueFound(bytes(b"small")),
+            GlobalRep::Lent { lend_key: 1, key: bytes(b"cat"), value: bytes(b"small"), },
+            GlobalRep::Repaid,
+            GlobalRep::ValueFound(bytes(&[0xff])),
+            GlobalRep::Lent { lend_key: 2, key: bytes(b"dog"), value: bytes(&[0xff]), },
+            GlobalRep::Error(super::super::ProtoError::DbQueueOutOfSync(bytes(b"dog"))),
+        ]);
+        let client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let mut client: TypedClient<String, String> = TypedClient::new(client, Utf8, Utf8);
+        let cat = "cat".to_string();
+        assert!(client.add(&cat, &"small".to_string(), AddMode::Tail).unwrap());
+        assert_eq!(client.lookup(&cat).unwrap(), Some("small".to_string()));
+        let task = client.lend(1000, LendMode::Poll).unwrap().unwrap();
+        assert_eq!(task, TypedTask { lend_key: 1, raw_key: bytes(b"cat"), key: cat.clone(), value: "small".to_string(), });
+        assert!(client.repay(&task, &"big".to_string(), RepayStatus::Reward).unwrap());
+        match client.lookup(&"dog".to_string()) {
+            Err(TypedError::Decode(err)) => assert_eq!(err.codec, "utf8"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match client.lend(1000, LendMode::Poll) {
+            Err(TypedError::UndecodableTask(task, err)) => assert_eq!((task.lend_key, err.codec), (2, "utf8")),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match client.count() {
+            Err(err @ TypedError::Client(ClientError::Rejected(..))) => assert!(!err.is_codec()),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    #[test]
+    fn raw_key_repaid() {
+        let (addr, _server) = serve(vec![
+            GlobalReq::Lend { timeout: 1000, mode: LendMode::Poll, },
+            GlobalReq::Heartbeat { lend_key: 1, key: bytes(b"Cat"), timeout: 1000, },
+            GlobalReq::Repay { lend_key: 1, key: bytes(b"Cat"), value: bytes(b"big"), status: RepayStatus::Front, },
+        ], vec![
+            GlobalRep::Lent { lend_key: 1, key: bytes(b"Cat"), value: bytes(b"small"), },
+            GlobalRep::Heartbeaten,
+            GlobalRep::Repaid,
+        ]);
+        let client = SyncClient::connect(addr, Some(Duration::from_secs(5))).unwrap();
+        let mut client: TypedClient<String, String> = TypedClient::new(client, Lowercase, Utf8);
+        let task = client.lend(1000, LendMode::Poll).unwrap().unwrap();
+        assert_eq!((&task.key[..], &task.raw_key[..]), ("cat", &b"Cat"[..]));
+        assert!(client.heartbeat(&task, 1000).unwrap());
+        assert!(client.repay(&task, &"big".to_string(), RepayStatus::Front).unwrap());
+    }
+}
@@ -0,0 +1,523 @@
+//! Lend → process → repay loop with automatic heartbeats.
+//!
+//! Every worker thread lends tasks with `LendMode::Poll` over a shared
+//! `Pool`, so a shutdown request is noticed within `poll_interval` even when
+//! the queue is empty. While the handler runs, a companion thread extends the
+//! lease with `Heartbeat` every `heartbeat_fraction` of `lease_timeout`, as
+//! measured by the `clock::Clock` of the worker (see `Worker::with_clock`).
+
+use std::{
+    fmt,
+    thread,
+    panic::{
+        self,
+        AssertUnwindSafe,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        Condvar,
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+    },
+    time::Duration,
+};
+
+use super::{
+    Value,
+    LendMode,
+    RepayStatus,
+    GlobalReq,
+    GlobalRep,
+};
+use super::client::{
+    LentTask,
+    Transport,
+};
+use super::clock::{
+    Clock,
+    SystemClock,
+};
+use super::pool::Pool;
+use super::retry::{
+    self,
+    RetryPolicy,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorkerConfig {
+    /// Number of tasks processed at the same time, one thread each.
+    pub concurrency: usize,
+    /// Lease requested with `Lend` and renewed with every `Heartbeat`.
+    pub lease_timeout: Duration,
+    /// Part of `lease_timeout` between heartbeats.
+    pub heartbeat_fraction: f64,
+    /// Pause before the next `Lend` when the queue is empty or the server is unavailable.
+    pub poll_interval: Duration,
+    /// Used for `Repay`, which is safe to retry.
+    pub retry: RetryPolicy,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> WorkerConfig {
+        WorkerConfig {
+            concurrency: 1,
+            lease_timeout: Duration::from_secs(30),
+            heartbeat_fraction: 1.0 / 3.0,
+            poll_interval: Duration::from_millis(200),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl WorkerConfig {
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.lease_timeout.mul_f64(self.heartbeat_fraction)
+    }
+}
+
+/// What to do with a processed task, together with the value to store for it.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The task is finished: it leaves the queue (repaid with `Drop`) while
+    /// its value stays in the database.
+    Success(Value),
+    Penalty(Value),
+    Reward(Value),
+    Front(Value),
+    /// The task is given up (repaid with `Drop` as well), counted in
+    /// `WorkerStats::dropped`.
+    Drop(Value),
+}
+
+impl Outcome {
+    fn into_repay(self) -> (RepayStatus, Value) {
+        match self {
+            Outcome::Success(value) => (RepayStatus::Drop, value),
+            Outcome::Penalty(value) => (RepayStatus::Penalty, value),
+            Outcome::Reward(value) => (RepayStatus::Reward, value),
+            Outcome::Front(value) => (RepayStatus::Front, value),
+            Outcome::Drop(value) => (RepayStatus::Drop, value),
+        }
+    }
+}
+
+/// A lent task handed to the handler.
+pub struct Job {
+    pub task: LentTask,
+    lease_lost: AtomicBool,
+}
+
+impl Job {
+    /// Whether a heartbeat was answered with `Skipped`: the task went back
+    /// to the queue and the result of the handler will be thrown away, so a
+    /// long running handler may give up early.
+    pub fn is_lease_lost(&self) -> bool {
+        self.lease_lost.load(Ordering::SeqCst)
+    }
+}
+
+/// Requests a graceful shutdown: workers stop lending new tasks, finish and
+/// repay the tasks in flight and then `Worker::run` returns.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn shutdown(&self) {
+        *self.inner.0.lock().unwrap() = true;
+        self.inner.1.notify_all();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+
+    /// Sleeps for `timeout` or until shutdown.
+    fn wait(&self, timeout: Duration) {
+        let requested = self.inner.0.lock().unwrap();
+        let _ = self.inner.1.wait_timeout_while(requested, timeout, |requested| !*requested).unwrap();
+    }
+}
+
+impl fmt::Debug for Shutdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Shutdown").field("requested", &self.is_shutdown()).finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorkerStats {
+    /// Tasks processed and repaid.
+    pub repaid: u64,
+    /// Tasks of `repaid` given up with `Outcome::Drop`.
+    pub dropped: u64,
+    /// Tasks whose lease was lost before they were repaid.
+    pub lost: u64,
+    /// Tasks whose handler panicked, they are left to expire.
+    pub panicked: u64,
+    /// Failed requests.
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    repaid: AtomicU64,
+    dropped: AtomicU64,
+    lost: AtomicU64,
+    panicked: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    fn stats(&self) -> WorkerStats {
+        WorkerStats {
+            repaid: self.repaid.load(Ordering::SeqCst),
+            dropped: self.dropped.load(Ordering::SeqCst),
+            lost: self.lost.load(Ordering::SeqCst),
+            panicked: self.panicked.load(Ordering::SeqCst),
+            errors: self.errors.load(Ordering::SeqCst),
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(&Job) -> Outcome + Send + Sync>;
+
+pub struct Worker<S> {
+    config: WorkerConfig,
+    pool: Pool<S>,
+    clock: Arc<dyn Clock>,
+    handler: Handler,
+    shutdown: Shutdown,
+}
+
+impl<S> Worker<S> where S: Transport + Send {
+    pub fn new<F>(config: WorkerConfig, pool: Pool<S>, handler: F) -> Worker<S> where F: Fn(&Job) -> Outcome + Send + Sync + 'static {
+        Worker::with_clock(config, pool, Arc::new(SystemClock), handler)
+    }
+
+    pub fn with_clock<F>(config: WorkerConfig, pool: Pool<S>, clock: Arc<dyn Clock>, handler: F) -> Worker<S>
+        where F: Fn(&Job) -> Outcome + Send + Sync + 'static
+    {
+        Worker {
+            config: config,
+            pool: pool,
+            clock: clock,
+            handler: Box::new(handler),
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Processes tasks with `concurrency` threads until shutdown is requested.
+    pub fn run(&self) -> WorkerStats {
+        let counters = Counters::default();
+        thread::scope(|scope| {
+            for _ in 0 .. self.config.concurrency {
+                scope.spawn(|| self.work(&counters));
+            }
+        });
+        counters.stats()
+    }
+
+    fn work(&self, counters: &Counters) {
+        let lease_ms = self.config.lease_timeout.as_millis() as u64;
+        while !self.shutdown.is_shutdown() {
+            let lent = self.pool.get()
+                .map_err(|_| ())
+                .and_then(|mut client| client.lend(lease_ms, LendMode::Poll).map_err(|_| ()));
+            let task = match lent {
+                Ok(Some(task)) => task,
+                Ok(None) => {
+                    self.shutdown.wait(self.config.poll_interval);
+                    continue;
+                },
+                Err(()) => {
+                    counters.errors.fetch_add(1, Ordering::SeqCst);
+                    self.shutdown.wait(self.config.poll_interval);
+                    continue;
+                },
+            };
+            self.process(Job { task: task, lease_lost: AtomicBool::new(false), }, counters);
+        }
+    }
+
+    fn process(&self, job: Job, counters: &Counters) {
+        let done = Arc::new((Mutex::new(false), Condvar::new()));
+        let weak_done = Arc::downgrade(&done);
+        self.clock.on_advance(Box::new(move || match weak_done.upgrade() {
+            Some(done) => {
+                let _finished = done.0.lock().unwrap();
+                done.1.notify_one();
+                true
+            },
+            None =>
+                false,
+        }));
+        let outcome = thread::scope(|scope| {
+            scope.spawn(|| self.heartbeat(&job, &done, counters));
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| (self.handler)(&job)));
+            *done.0.lock().unwrap() = true;
+            done.1.notify_one();
+            outcome
+        });
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(..) => {
+                counters.panicked.fetch_add(1, Ordering::SeqCst);
+                return;
+            },
+        };
+        if job.is_lease_lost() {
+            counters.lost.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+        let dropped = matches!(outcome, Outcome::Drop(..));
+        let (status, value) = outcome.into_repay();
+        let repay = GlobalReq::Repay { lend_key: job.task.lend_key, key: job.task.key, value: value, status: status, };
+        match self.config.retry.run_pooled(&self.pool, &repay) {
+            Ok(retry::Outcome::Reply(GlobalRep::Repaid)) | Ok(retry::Outcome::ProbablyRepaid) => {
+                if dropped {
+                    counters.dropped.fetch_add(1, Ordering::SeqCst);
+                }
+                counters.repaid.fetch_add(1, Ordering::SeqCst)
+            },
+            Ok(retry::Outcome::Reply(GlobalRep::NotFound)) =>
+                counters.lost.fetch_add(1, Ordering::SeqCst),
+            Ok(..) | Err(..) =>
+                counters.errors.fetch_add(1, Ordering::SeqCst),
+        };
+    }
+
+    fn heartbeat(&self, job: &Job, done: &(Mutex<bool>, Condvar), counters: &Counters) {
+        let interval = self.config.heartbeat_interval();
+        let lease_ms = self.config.lease_timeout.as_millis() as u64;
+        loop {
+            let next = self.clock.now() + interval;
+            let mut finished = done.0.lock().unwrap();
+            loop {
+                let now = self.clock.now();
+                if *finished || now >= next {
+                    break;
+                }
+                finished = done.1.wait_timeout(finished, next - now).unwrap().0;
+            }
+            if *finished {
+                return;
+            }
+            // the handler must not wait for the network to report it is done
+            drop(finished);
+            let heartbeaten = self.pool.get()
+                .map_err(|_| ())
+                .and_then(|mut client| client.heartbeat(job.task.lend_key, job.task.key.clone(), lease_ms).map_err(|_| ()));
+            match heartbeaten {
+                Ok(true) => (),
+                Ok(false) => {
+                    job.lease_lost.store(true, Ordering::SeqCst);
+                    return;
+                },
+                // the next heartbeat may still get through before the lease expires
+                Err(()) => {
+                    counters.errors.fetch_add(1, Ordering::SeqCst);
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        thread,
+        collections::{HashMap, VecDeque},
+        net::{TcpListener, TcpStream, SocketAddr},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+    use super::super::{Key, RepayStatus, GlobalReq, GlobalRep};
+    use super::super::clock::ManualClock;
+    use super::super::client::{read_frame, write_frame, DEFAULT_MAX_FRAME_LEN};
+    use super::super::pool::{Pool, PoolConfig};
+    use super::{Worker, WorkerConfig, WorkerStats, Outcome};
+
+    #[derive(Default)]
+    struct Queue {
+        tasks: VecDeque<Key>,
+        lent: HashMap<u64, Key>,
+        next_lend_key: u64,
+        heartbeats: usize,
+        repaid: Vec<(Key, Vec<u8>, RepayStatus)>,
+        /// Heartbeats are answered with `Skipped`.
+        expire: bool,
+    }
+
+    fn server(keys: &[&str], expire: bool) -> (SocketAddr, Arc<Mutex<Queue>>) {
+        let queue = Arc::new(Mutex::new(Queue { expire: expire, ..Queue::default() }));
+        queue.lock().unwrap().tasks.extend(keys.iter().map(|key| Arc::from(key.as_bytes())));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = queue.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let queue = shared.clone();
+                thread::spawn(move || {
+                    while let Ok(frame) = read_frame(&mut stream, DEFAULT_MAX_FRAME_LEN) {
+                        let mut queue = queue.lock().unwrap();
+                        let reply = match GlobalReq::decode(&frame).unwrap().0 {
+                            GlobalReq::Ping => GlobalRep::Pong,
+                            GlobalReq::Lend { .. } => match queue.tasks.pop_front() {
+                                Some(key) => {
+                                    queue.next_lend_key += 1;
+                                    let lend_key = queue.next_lend_key;
+                                    queue.lent.insert(lend_key, key.clone());
+                                    GlobalRep::Lent { lend_key: lend_key, value: key.clone(), key: key, }
+                                },
+                                None => GlobalRep::QueueEmpty,
+                            },
+                            GlobalReq::Heartbeat { lend_key, .. } => {
+                                queue.heartbeats += 1;
+                                if queue.expire {
+                                    queue.lent.remove(&lend_key);
+                                    GlobalRep::Skipped
+                                } else {
+                                    GlobalRep::Heartbeaten
+                                }
+                            },
+                            GlobalReq::Repay { lend_key, key, value, status, } => match queue.lent.remove(&lend_key) {
+                                Some(..) => {
+                                    queue.repaid.push((key, value.to_vec(), status));
+                                    GlobalRep::Repaid
+                                },
+                                None => GlobalRep::NotFound,
+                            },
+                            req => panic!("unexpected request {:?}", req),
+                        };
+                        let mut frame = vec![0; reply.encode_len()];
+                        reply.encode(&mut frame);
+                        write_frame(&mut stream, &frame).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, queue)
+    }
+
+    fn config() -> WorkerConfig {
+        WorkerConfig {
+            concurrency: 2,
+            lease_timeout: Duration::from_millis(30),
+            poll_interval: Duration::from_millis(5),
+            ..WorkerConfig::default()
+        }
+    }
+
+    fn pool(addr: SocketAddr) -> Pool<TcpStream> {
+        Pool::tcp(addr, Some(Duration::from_secs(5)), PoolConfig::default())
+    }
+
+    #[test]
+    fn process_and_shutdown() {
+        let (addr, queue) = server(&["a", "b", "c", "d"], false);
+        let worker = Worker::new(config(), pool(addr), |job| {
+            thread::sleep(Duration::from_millis(40));
+            let mut value = job.task.value.to_vec();
+            value.extend_from_slice(b"!");
+            match &job.task.key[..] {
+                b"a" => Outcome::Success(Arc::from(value)),
+                b"b" => Outcome::Drop(Arc::from(value)),
+                _ => Outcome::Penalty(Arc::from(value)),
+            }
+        });
+        let shutdown = worker.shutdown_handle();
+        let waiter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(60));
+            shutdown.shutdown();
+        });
+        let stats = worker.run();
+        waiter.join().unwrap();
+        // two tasks were in flight when shutdown was requested, they were finished
+        assert_eq!(stats, WorkerStats { repaid: 4, dropped: 1, ..WorkerStats::default() });
+        let queue = queue.lock().unwrap();
+        assert!(queue.heartbeats >= 4);
+        let mut repaid: Vec<_> = queue.repaid.iter().map(|&(ref key, ref value, ref status)| (key.to_vec(), value.clone(), *status == RepayStatus::Drop)).collect();
+        repaid.sort();
+        assert_eq!(repaid, vec![
+            (b"a".to_vec(), b"a!".to_vec(), true),
+            (b"b".to_vec(), b"b!".to_vec(), true),
+            (b"c".to_vec(), b"c!".to_vec(), false),
+            (b"d".to_vec(), b"d!".to_vec(), false),
+        ]);
+    }
+
+    #[test]
+    fn lease_lost() {
+        let (addr, queue) = server(&["a"], true);
+        let worker = Worker::new(WorkerConfig { concurrency: 1, ..config() }, pool(addr), |job| {
+            while !job.is_lease_lost() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Outcome::Drop(job.task.value.clone())
+        });
+        let shutdown = worker.shutdown_handle();
+        let waiter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            shutdown.shutdown();
+        });
+        let stats = worker.run();
+        waiter.join().unwrap();
+        assert_eq!(stats, WorkerStats { lost: 1, ..WorkerStats::default() });
+        assert!(queue.lock().unwrap().repaid.is_empty());
+    }
+
+    #[test]
+    fn manual_clock_heartbeats() {
+        let (addr, queue) = server(&["a"], false);
+        let clock = ManualClock::new();
+        let config = WorkerConfig { concurrency: 1, lease_timeout: Duration::from_secs(30), ..config() };
+        let heartbeats = queue.clone();
+        let worker = Worker::with_clock(config, pool(addr), Arc::new(clock.clone()), move |job| {
+            while heartbeats.lock().unwrap().heartbeats == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Outcome::Success(job.task.value.clone())
+        });
+        let shutdown = worker.shutdown_handle();
+        let started = Instant::now();
+        let waiter = thread::spawn(move || {
+            while queue.lock().unwrap().heartbeats == 0 {
+                thread::sleep(Duration::from_millis(5));
+                clock.advance(Duration::from_secs(10));
+            }
+            shutdown.shutdown();
+            queue
+        });
+        assert_eq!(worker.run(), WorkerStats { repaid: 1, ..WorkerStats::default() });
+        let queue = waiter.join().unwrap();
+        // a heartbeat every 10 seconds of the clock rather than of real time
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(queue.lock().unwrap().repaid.len(), 1);
+    }
+
+    #[test]
+    fn handler_panic() {
+        let (addr, queue) = server(&["a"], false);
+        let worker = Worker::new(WorkerConfig { concurrency: 1, ..config() }, pool(addr), |_| panic!("handler failed"));
+        let shutdown = worker.shutdown_handle();
+        let waiter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(30));
+            shutdown.shutdown();
+        });
+        assert_eq!(worker.run(), WorkerStats { panicked: 1, ..WorkerStats::default() });
+        waiter.join().unwrap();
+        // the task is left to expire
+        assert_eq!(queue.lock().unwrap().lent.len(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+//! ZeroMQ REQ/REP transport used by spiderq server: every message is sent as
+//! a single frame, so no framing is needed.
+
+use std::fmt;
+
+use zeromq::{
+    Socket,
+    SocketRecv,
+    SocketSend,
+    ReqSocket,
+    RepSocket,
+    Endpoint,
+    ZmqMessage,
+    ZmqError,
+};
+
+use super::{
+    GlobalReq,
+    GlobalRep,
+    codec::{
+        self,
+        DecodeError,
+    },
+};
+
+#[derive(Debug)]
+pub enum TransportError {
+    Zmq(ZmqError),
+    EmptyMessage,
+    Decode(DecodeError),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &TransportError::Zmq(ref err) => write!(f, "zmq error: {}", err),
+            &TransportError::EmptyMessage => write!(f, "empty zmq message"),
+            &TransportError::Decode(ref err) => write!(f, "invalid reply: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+impl From<ZmqError> for TransportError {
+    fn from(err: ZmqError) -> TransportError {
+        TransportError::Zmq(err)
+    }
+}
+
+fn first_frame(message: &ZmqMessage) -> Result<&[u8], TransportError> {
+    message.get(0).map(|frame| &frame[..]).ok_or(TransportError::EmptyMessage)
+}
+
+/// REQ socket: requests and replies strictly alternate.
+pub struct ZmqClient {
+    socket: ReqSocket,
+}
+
+impl ZmqClient {
+    pub async fn connect(endpoint: &str) -> Result<ZmqClient, TransportError> {
+        let mut socket = ReqSocket::new();
+        socket.connect(endpoint).await?;
+        Ok(ZmqClient { socket: socket, })
+    }
+
+    pub async fn request(&mut self, req: &GlobalReq) -> Result<GlobalRep, TransportError> {
+        let mut frame = vec![0; req.encode_len()];
+        req.encode(&mut frame);
+        self.socket.send(ZmqMessage::from(frame)).await?;
+        let message = self.socket.recv().await?;
+        codec::decode_rep(first_frame(&message)?).map_err(TransportError::Decode)
+    }
+
+    pub async fn close(self) {
+        self.socket.close().await;
+    }
+}
+
+/// REP socket: every received request must be answered with `send` before
+/// the next one is received.
+pub struct ZmqServer {
+    socket: RepSocket,
+    endpoint: Endpoint,
+}
+
+impl ZmqServer {
+    /// Binds to `endpoint`, e.g. `tcp://127.0.0.1:0` picks a free port; see `endpoint()`.
+    pub async fn bind(endpoint: &str) -> Result<ZmqServer, TransportError> {
+        let mut socket = RepSocket::new();
+        let endpoint = socket.bind(endpoint).await?;
+        Ok(ZmqServer { socket: socket, endpoint: endpoint, })
+    }
+
+    /// The endpoint actually bound.
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    /// Receives the next request. Undecodable requests (including ones with
+    /// trailing bytes) are answered with `DecodeError::into_rep` right away
+    /// and skipped.
+    pub async fn recv(&mut self) -> Result<GlobalReq, TransportError> {
+        loop {
+            let message = self.socket.recv().await?;
+            let frame = message.get(0).map(|frame| &frame[..]).unwrap_or(&[]);
+            match codec::decode_req(frame) {
+                Ok(req) => return Ok(req),
+                Err(err) => self.send(&err.into_rep()).await?,
+            }
+        }
+    }
+
+    pub async fn send(&mut self, rep: &GlobalRep) -> Result<(), TransportError> {
+        let mut frame = vec![0; rep.encode_len()];
+        rep.encode(&mut frame);
+        self.socket.send(ZmqMessage::from(frame)).await?;
+        Ok(())
+    }
+
+    /// Answers requests with `handler` until a transport error occurs.
+    pub async fn serve<F>(&mut self, mut handler: F) -> TransportError where F: FnMut(GlobalReq) -> GlobalRep {
+        loop {
+            let req = match self.recv().await {
+                Ok(req) => req,
+                Err(err) => return err,
+            };
+            if let Err(err) = self.send(&handler(req)).await {
+                return err;
+            }
+        }
+    }
+
+    pub async fn close(self) {
+        self.socket.close().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use zeromq::{Socket, SocketRecv, SocketSend, ReqSocket, ZmqMessage};
+    use super::super::{AddMode, ServerErrorCode, GlobalReq, GlobalRep, ProtoError, MessageKind, FieldKind};
+    use super::{ZmqClient, ZmqServer};
+
+    async fn server() -> String {
+        let mut server = ZmqServer::bind("tcp://127.0.0.1:0").await.unwrap();
+        let endpoint = server.endpoint().to_string();
+        tokio::spawn(async move {
+            server.serve(|req| match req {
+                GlobalReq::Ping => GlobalRep::Pong,
+                GlobalReq::Add { .. } => GlobalRep::Added,
+                _ => GlobalRep::Forbidden,
+            }).await;
+        });
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn request_reply() {
+        let mut client = ZmqClient::connect(&server().await).await.unwrap();
+        assert_eq!(client.request(&GlobalReq::Ping).await.unwrap(), GlobalRep::Pong);
+        let add = GlobalReq::Add { key: Arc::from(&b"cat"[..]), value: Arc::from(&b"small"[..]), mode: AddMode::Tail, };
+        assert_eq!(client.request(&add).await.unwrap(), GlobalRep::Added);
+        assert_eq!(client.request(&GlobalReq::Flush).await.unwrap(), GlobalRep::Forbidden);
+        client.close().await;
+    }
+
+    #[tokio::test]
+    async fn invalid_request() {
+        let mut socket = ReqSocket::new();
+        socket.connect(&server().await).await.unwrap();
+        socket.send(ZmqMessage::from(vec![2, 0, 0])).await.unwrap();
+        let reply = socket.recv().await.unwrap();
+        let (rep, _) = GlobalRep::decode(reply.get(0).unwrap()).unwrap();
+        assert_eq!(rep, GlobalRep::Error(ProtoError::NotEnoughData {
+            message: MessageKind::GlobalReqAdd,
+            field: FieldKind::KeyLen,
+            required: 4,
+            given: 2,
+        }));
+        socket.send(ZmqMessage::from(vec![11])).await.unwrap();
+        let reply = socket.recv().await.unwrap();
+        assert_eq!(GlobalRep::decode(reply.get(0).unwrap()).unwrap().0, GlobalRep::Pong);
+    }
+
+    #[tokio::test]
+    async fn trailing_bytes() {
+        let mut socket = ReqSocket::new();
+        socket.connect(&server().await).await.unwrap();
+        socket.send(ZmqMessage::from(vec![11, 0, 0])).await.unwrap();
+        let reply = socket.recv().await.unwrap();
+        match GlobalRep::decode(reply.get(0).unwrap()).unwrap().0 {
+            GlobalRep::ServerError { code: ServerErrorCode::Internal, message } =>
+                assert_eq!(message, "invalid request: 2 trailing bytes after the message"),
+            rep => panic!("unexpected reply {:?}", rep),
+        }
+    }
+}